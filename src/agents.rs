@@ -0,0 +1,85 @@
+use std::{
+    env,
+    ffi::{CStr, OsStr},
+    fs,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// The environment is only touched here, at startup, before any thread exists.
+fn export(name: &str, value: impl AsRef<OsStr>) {
+    unsafe { env::set_var(name, value) };
+}
+
+fn agent_alive(socket: &Path) -> bool {
+    UnixStream::connect(socket).is_ok()
+}
+
+// A fixed per-user path, so every login shell finds the agent the first one
+// started instead of spawning its own.
+fn agent_socket() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("ssh-agent.socket"),
+        None => PathBuf::from(format!("/tmp/ssh-agent-{}.socket", unsafe {
+            libc::getuid()
+        })),
+    }
+}
+
+fn export_gpg_tty() {
+    let name = unsafe { libc::ttyname(0) };
+    if name.is_null() {
+        return;
+    }
+    let tty = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .to_string();
+    export("GPG_TTY", tty);
+}
+
+fn attach_ssh_agent() -> Result<(), String> {
+    if env::var_os("SSH_AUTH_SOCK").is_some_and(|socket| agent_alive(Path::new(&socket))) {
+        return Ok(());
+    }
+
+    let socket = agent_socket();
+    if !agent_alive(&socket) {
+        // A socket left behind by a dead agent would make ssh-agent -a fail.
+        _ = fs::remove_file(&socket);
+
+        let output = Command::new("ssh-agent")
+            .arg("-s")
+            .arg("-a")
+            .arg(&socket)
+            .output()
+            .map_err(|e| format!("ssh-agent: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "ssh-agent: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        // It prints `SSH_AGENT_PID=1234; export SSH_AGENT_PID;` for sh to eval.
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if let Some(pid) = stdout
+            .split(';')
+            .find_map(|field| field.trim().strip_prefix("SSH_AGENT_PID="))
+        {
+            export("SSH_AGENT_PID", pid);
+        }
+    }
+
+    export("SSH_AUTH_SOCK", &socket);
+    Ok(())
+}
+
+/// What a login shell's rc file would otherwise do: point gpg at our terminal
+/// and make sure ssh finds an agent.
+pub fn setup() {
+    export_gpg_tty();
+    if let Err(err) = attach_ssh_agent() {
+        eprintln!("codecrafters-shell: {}", err);
+    }
+}
@@ -0,0 +1,52 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+static ALIASES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Whether `name` can be an alias: it has to read as one word, so no blanks,
+/// quotes, `=` or characters the lexer treats specially.
+pub fn is_valid(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || "=/$`'\"\\|&;()<>".contains(c))
+}
+
+/// What an alias stands for.
+pub fn get(name: &str) -> Option<String> {
+    ALIASES
+        .lock()
+        .expect("Failed to lock aliases!")
+        .get(name)
+        .cloned()
+}
+
+pub fn set(name: &str, value: String) {
+    ALIASES
+        .lock()
+        .expect("Failed to lock aliases!")
+        .insert(name.to_string(), value);
+}
+
+/// Removes an alias, saying whether there was one.
+pub fn remove(name: &str) -> bool {
+    ALIASES
+        .lock()
+        .expect("Failed to lock aliases!")
+        .remove(name)
+        .is_some()
+}
+
+/// Removes every alias, for `unalias -a`.
+pub fn clear() {
+    ALIASES.lock().expect("Failed to lock aliases!").clear();
+}
+
+/// Every alias, by name, for saving and listing.
+pub fn snapshot() -> BTreeMap<String, String> {
+    ALIASES.lock().expect("Failed to lock aliases!").clone()
+}
+
+/// Locks the aliases for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    ALIASES.lock().expect("Failed to lock aliases!")
+}
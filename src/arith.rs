@@ -0,0 +1,587 @@
+use std::{cmp::Ordering, fmt, iter::Peekable, str::Chars};
+
+use thiserror::Error;
+
+/// Integers stay exact until something forces a float, like a literal with a
+/// decimal point or `sqrt`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+}
+
+impl Value {
+    fn as_float(self) -> f64 {
+        match self {
+            Value::Int(n) => n as f64,
+            Value::Float(x) => x,
+        }
+    }
+
+    /// Whether the value counts as true, like in C: anything but zero.
+    pub fn is_true(self) -> bool {
+        match self {
+            Value::Int(n) => n != 0,
+            Value::Float(x) => x != 0.0,
+        }
+    }
+
+    fn compare(self, other: Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(&b)),
+            (a, b) => a.as_float().partial_cmp(&b.as_float()),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Int(b as i64)
+    }
+}
+
+/// Where the names in an expression get their values and keep new ones, as
+/// in `(( i += 2 ))`. Unset or empty names count as 0.
+pub trait Scope {
+    fn get(&self, name: &str) -> Option<String>;
+    fn set(&mut self, name: &str, value: Value);
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ArithError {
+    #[error("syntax error: unexpected `{0}'")]
+    UnexpectedChar(char),
+    #[error("syntax error: unexpected end of expression")]
+    UnexpectedEnd,
+    #[error("{0}: invalid number")]
+    InvalidNumber(String),
+    #[error("{0}: unknown function")]
+    UnknownFunction(String),
+    #[error("{0}: expected {1} argument(s)")]
+    WrongArity(String, usize),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("integer overflow")]
+    Overflow,
+}
+
+/// Functions callable as `name(args)`, with how many arguments they take.
+const FUNCTIONS: [(&str, usize); 6] = [
+    ("sqrt", 1),
+    ("pow", 2),
+    ("abs", 1),
+    ("floor", 1),
+    ("ceil", 1),
+    ("round", 1),
+];
+
+fn call(name: &str, args: &[Value]) -> Result<Value, ArithError> {
+    let Some((_, arity)) = FUNCTIONS.iter().find(|(n, _)| *n == name) else {
+        return Err(ArithError::UnknownFunction(name.to_string()));
+    };
+    if args.len() != *arity {
+        return Err(ArithError::WrongArity(name.to_string(), *arity));
+    }
+
+    let x = args[0];
+    Ok(match name {
+        "sqrt" => Value::Float(x.as_float().sqrt()),
+        "pow" => power(x, args[1])?,
+        "abs" => match x {
+            Value::Int(n) => Value::Int(n.checked_abs().ok_or(ArithError::Overflow)?),
+            Value::Float(x) => Value::Float(x.abs()),
+        },
+        "floor" => Value::Float(x.as_float().floor()),
+        "ceil" => Value::Float(x.as_float().ceil()),
+        _ => Value::Float(x.as_float().round()),
+    })
+}
+
+fn power(base: Value, exp: Value) -> Result<Value, ArithError> {
+    match (base, exp) {
+        (Value::Int(b), Value::Int(e)) if e >= 0 => {
+            let e = u32::try_from(e).map_err(|_| ArithError::Overflow)?;
+            b.checked_pow(e).map(Value::Int).ok_or(ArithError::Overflow)
+        }
+        _ => Ok(Value::Float(base.as_float().powf(exp.as_float()))),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+fn binary(op: Op, lhs: Value, rhs: Value) -> Result<Value, ArithError> {
+    let (Value::Int(a), Value::Int(b)) = (lhs, rhs) else {
+        let (a, b) = (lhs.as_float(), rhs.as_float());
+        return Ok(Value::Float(match op {
+            Op::Add => a + b,
+            Op::Sub => a - b,
+            Op::Mul => a * b,
+            Op::Div => a / b,
+            Op::Rem => a % b,
+        }));
+    };
+
+    if matches!(op, Op::Div | Op::Rem) && b == 0 {
+        return Err(ArithError::DivisionByZero);
+    }
+    let result = match op {
+        Op::Add => a.checked_add(b),
+        Op::Sub => a.checked_sub(b),
+        Op::Mul => a.checked_mul(b),
+        Op::Div => a.checked_div(b),
+        Op::Rem => a.checked_rem(b),
+    };
+    result.map(Value::Int).ok_or(ArithError::Overflow)
+}
+
+struct Parser<'a, 's> {
+    chars: Peekable<Chars<'a>>,
+    // Without one, names can only call functions, as in `calc`.
+    scope: Option<&'s mut dyn Scope>,
+    // Set for the side of `&&`, `||` or `?:` that doesn't count, which is
+    // still parsed but can't fail or change anything.
+    skipping: bool,
+}
+
+impl Parser<'_, '_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    // Whether the operator comes next.
+    fn at(&mut self, op: &str) -> bool {
+        self.skip_whitespace();
+        let mut ahead = self.chars.clone();
+        op.chars().all(|c| ahead.next() == Some(c))
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        let found = self.at(op);
+        if found {
+            self.chars.nth(op.chars().count() - 1);
+        }
+        found
+    }
+
+    // Errors from the side that doesn't count don't count either.
+    fn check(&self, result: Result<Value, ArithError>) -> Result<Value, ArithError> {
+        match result {
+            Err(ArithError::DivisionByZero | ArithError::Overflow) if self.skipping => {
+                Ok(Value::Int(0))
+            }
+            result => result,
+        }
+    }
+
+    fn skipped<T>(&mut self, parse: impl FnOnce(&mut Self) -> T) -> T {
+        let skipping = self.skipping;
+        self.skipping = true;
+        let parsed = parse(self);
+        self.skipping = skipping;
+        parsed
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_alphanumeric() || *c == '_')
+        {
+            name.push(c);
+        }
+        name
+    }
+
+    fn variable(&self, name: &str) -> Result<Value, ArithError> {
+        let scope = self.scope.as_ref().expect("Only read with a scope!");
+        match scope.get(name) {
+            Some(value) if !value.trim().is_empty() => {
+                eval(&value).map_err(|_| ArithError::InvalidNumber(value))
+            }
+            _ => Ok(Value::Int(0)),
+        }
+    }
+
+    fn store(&mut self, name: &str, value: Value) {
+        if let Some(scope) = self.scope.as_mut()
+            && !self.skipping
+        {
+            scope.set(name, value);
+        }
+    }
+
+    // comma := assign (',' assign)*
+    fn comma(&mut self) -> Result<Value, ArithError> {
+        let mut value = self.assign()?;
+        while self.eat(",") {
+            value = self.assign()?;
+        }
+        Ok(value)
+    }
+
+    // assign := name ('=' | '+=' | '-=' | '*=' | '/=' | '%=') assign | ternary
+    fn assign(&mut self) -> Result<Value, ArithError> {
+        self.skip_whitespace();
+        let starts_name = self
+            .chars
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_');
+        if self.scope.is_none() || !starts_name {
+            return self.ternary();
+        }
+
+        let before = self.chars.clone();
+        let name = self.name();
+        let op = match () {
+            _ if self.eat("+=") => Some(Op::Add),
+            _ if self.eat("-=") => Some(Op::Sub),
+            _ if self.eat("*=") => Some(Op::Mul),
+            _ if self.eat("/=") => Some(Op::Div),
+            _ if self.eat("%=") => Some(Op::Rem),
+            _ if self.at("=") && !self.at("==") => {
+                self.eat("=");
+                None
+            }
+            _ => {
+                self.chars = before;
+                return self.ternary();
+            }
+        };
+        let rhs = self.assign()?;
+        let value = match op {
+            Some(op) => {
+                let lhs = self.variable(&name)?;
+                self.check(binary(op, lhs, rhs))?
+            }
+            None => rhs,
+        };
+        self.store(&name, value);
+        Ok(value)
+    }
+
+    // ternary := or ('?' assign ':' ternary)?
+    fn ternary(&mut self) -> Result<Value, ArithError> {
+        let condition = self.or()?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        match condition.is_true() {
+            true => {
+                let then = self.assign()?;
+                self.expect(':')?;
+                self.skipped(Self::ternary)?;
+                Ok(then)
+            }
+            false => {
+                self.skipped(Self::assign)?;
+                self.expect(':')?;
+                self.ternary()
+            }
+        }
+    }
+
+    // or := and ('||' and)*
+    fn or(&mut self) -> Result<Value, ArithError> {
+        let mut value = self.and()?;
+        while self.eat("||") {
+            let rhs = match value.is_true() {
+                true => self.skipped(Self::and)?,
+                false => self.and()?,
+            };
+            value = (value.is_true() || rhs.is_true()).into();
+        }
+        Ok(value)
+    }
+
+    // and := equality ('&&' equality)*
+    fn and(&mut self) -> Result<Value, ArithError> {
+        let mut value = self.equality()?;
+        while self.eat("&&") {
+            let rhs = match value.is_true() {
+                true => self.equality()?,
+                false => self.skipped(Self::equality)?,
+            };
+            value = (value.is_true() && rhs.is_true()).into();
+        }
+        Ok(value)
+    }
+
+    // equality := comparison (('==' | '!=') comparison)*
+    fn equality(&mut self) -> Result<Value, ArithError> {
+        let mut value = self.comparison()?;
+        loop {
+            let equal = match () {
+                _ if self.eat("==") => true,
+                _ if self.eat("!=") => false,
+                _ => return Ok(value),
+            };
+            let rhs = self.comparison()?;
+            value = ((value.compare(rhs) == Some(Ordering::Equal)) == equal).into();
+        }
+    }
+
+    // comparison := sum (('<' | '>' | '<=' | '>=') sum)*
+    fn comparison(&mut self) -> Result<Value, ArithError> {
+        let mut value = self.sum()?;
+        loop {
+            let accepts: &[Ordering] = match () {
+                _ if self.eat("<=") => &[Ordering::Less, Ordering::Equal],
+                _ if self.eat(">=") => &[Ordering::Greater, Ordering::Equal],
+                _ if self.eat("<") => &[Ordering::Less],
+                _ if self.eat(">") => &[Ordering::Greater],
+                _ => return Ok(value),
+            };
+            let rhs = self.sum()?;
+            value = value
+                .compare(rhs)
+                .is_some_and(|ordering| accepts.contains(&ordering))
+                .into();
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ArithError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            Some(c) => Err(ArithError::UnexpectedChar(c)),
+            None => Err(ArithError::UnexpectedEnd),
+        }
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<Value, ArithError> {
+        let mut value = self.product()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => Op::Add,
+                Some('-') => Op::Sub,
+                _ => return Ok(value),
+            };
+            self.chars.next();
+            let rhs = self.product()?;
+            value = self.check(binary(op, value, rhs))?;
+        }
+    }
+
+    // product := power (('*' | '/' | '%') power)*
+    fn product(&mut self) -> Result<Value, ArithError> {
+        let mut value = self.power()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') if !self.at_double_star() => Op::Mul,
+                Some('/') => Op::Div,
+                Some('%') => Op::Rem,
+                _ => return Ok(value),
+            };
+            self.chars.next();
+            let rhs = self.power()?;
+            value = self.check(binary(op, value, rhs))?;
+        }
+    }
+
+    fn at_double_star(&self) -> bool {
+        let mut ahead = self.chars.clone();
+        ahead.next() == Some('*') && ahead.next() == Some('*')
+    }
+
+    // power := unary (('**' | '^') power)?, so it groups to the right.
+    fn power(&mut self) -> Result<Value, ArithError> {
+        let base = self.unary()?;
+        match self.peek() {
+            Some('^') => {
+                self.chars.next();
+            }
+            Some('*') if self.at_double_star() => {
+                self.chars.next();
+                self.chars.next();
+            }
+            _ => return Ok(base),
+        }
+        let exp = self.power()?;
+        self.check(power(base, exp))
+    }
+
+    // unary := ('-' | '+' | '!') unary | ('++' | '--') name | primary
+    fn unary(&mut self) -> Result<Value, ArithError> {
+        if self.scope.is_some() {
+            for (op, step) in [("++", Op::Add), ("--", Op::Sub)] {
+                let before = self.chars.clone();
+                if !self.eat(op) {
+                    continue;
+                }
+                self.skip_whitespace();
+                let name = self.name();
+                if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+                    self.chars = before;
+                    break;
+                }
+                let value = self.variable(&name)?;
+                let value = self.check(binary(step, value, Value::Int(1)))?;
+                self.store(&name, value);
+                return Ok(value);
+            }
+        }
+        match self.peek() {
+            Some('!') => {
+                self.chars.next();
+                Ok((!self.unary()?.is_true()).into())
+            }
+            Some('-') => {
+                self.chars.next();
+                let value = self.unary()?;
+                self.check(binary(Op::Sub, Value::Int(0), value))
+            }
+            Some('+') => {
+                self.chars.next();
+                self.unary()
+            }
+            _ => self.primary(),
+        }
+    }
+
+    // primary := number | name '(' args ')' | name ('++' | '--')? | '(' comma ')'
+    fn primary(&mut self) -> Result<Value, ArithError> {
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let value = self.comma()?;
+                self.expect(')')?;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let before = self.chars.clone();
+                let name = self.name();
+                if self.scope.is_none() || self.at("(") {
+                    self.chars = before;
+                    return self.function();
+                }
+                let value = self.variable(&name)?;
+                let step = match () {
+                    _ if self.eat("++") => Op::Add,
+                    _ if self.eat("--") => Op::Sub,
+                    _ => return Ok(value),
+                };
+                let stepped = self.check(binary(step, value, Value::Int(1)))?;
+                self.store(&name, stepped);
+                Ok(value)
+            }
+            Some(c) => Err(ArithError::UnexpectedChar(c)),
+            None => Err(ArithError::UnexpectedEnd),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ArithError> {
+        let mut literal = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_alphanumeric() || *c == '.' || *c == '_')
+        {
+            literal.push(c);
+        }
+
+        parse_number(&literal)
+    }
+
+    fn function(&mut self) -> Result<Value, ArithError> {
+        let name = self.name();
+
+        self.expect('(')?;
+        let mut args = Vec::new();
+        if self.peek() != Some(')') {
+            args.push(self.assign()?);
+            while self.peek() == Some(',') {
+                self.chars.next();
+                args.push(self.assign()?);
+            }
+        }
+        self.expect(')')?;
+
+        call(&name, &args)
+    }
+}
+
+/// Parses an unsigned literal the way Rust writes them: `_` can group digits
+/// and `0x`, `0o` and `0b` pick the base.
+pub fn parse_number(literal: &str) -> Result<Value, ArithError> {
+    let invalid = || ArithError::InvalidNumber(literal.to_string());
+    if literal.starts_with('_') || literal.ends_with('_') {
+        return Err(invalid());
+    }
+    let digits = literal.replace('_', "");
+
+    let radix = match digits.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0o" | "0O") => 8,
+        Some("0b" | "0B") => 2,
+        _ => 10,
+    };
+    if radix != 10 {
+        let body = &digits[2..];
+        if !body.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+            return Err(invalid());
+        }
+        return i64::from_str_radix(body, radix)
+            .map(Value::Int)
+            .map_err(|_| invalid());
+    }
+
+    if let Ok(n) = digits.parse() {
+        return Ok(Value::Int(n));
+    }
+    match digits.parse() {
+        Ok(x) if digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') => {
+            Ok(Value::Float(x))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn evaluate(expr: &str, scope: Option<&mut dyn Scope>) -> Result<Value, ArithError> {
+    let mut parser = Parser {
+        chars: expr.chars().peekable(),
+        scope,
+        skipping: false,
+    };
+
+    let value = parser.comma()?;
+    match parser.peek() {
+        Some(c) => Err(ArithError::UnexpectedChar(c)),
+        None => Ok(value),
+    }
+}
+
+/// Evaluates an arithmetic expression. Integer division truncates like it does
+/// in `$((...))`; write `7/2.0` to get a fraction.
+pub fn eval(expr: &str) -> Result<Value, ArithError> {
+    evaluate(expr, None)
+}
+
+/// Evaluates an expression whose names are variables in the scope, which
+/// `=`, `+=` and the like, `++` and `--` change.
+pub fn eval_in(expr: &str, scope: &mut dyn Scope) -> Result<Value, ArithError> {
+    evaluate(expr, Some(scope))
+}
@@ -0,0 +1,274 @@
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+use rustyline::{
+    At, Cmd, ConditionalEventHandler, Event, EventContext, EventHandler, KeyCode, KeyEvent,
+    Modifiers, Movement, RepeatCount, Word,
+};
+
+use crate::{Shell, clipboard, fzf, jobs, lexer, options, palette, terminal};
+
+// Alt-w, same as emacs' copy-region-as-kill, but the region is the whole line.
+struct CopyLine;
+
+impl ConditionalEventHandler for CopyLine {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        _ = clipboard::copy_osc52(ctx.line().as_bytes());
+        Some(Cmd::Noop)
+    }
+}
+
+fn pager() -> Command {
+    let pager = env::var("PAGER").unwrap_or_default();
+    let mut words = pager.split_whitespace();
+    let mut command = Command::new(words.next().unwrap_or("less"));
+    command.args(words);
+    command
+}
+
+// The man page if there is one, otherwise whatever `--help` prints, which
+// for a builtin means asking a copy of this shell.
+fn show_help(name: &str) -> io::Result<()> {
+    let has_page = Command::new("man")
+        .args(["-w", name])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if has_page {
+        Command::new("man").arg(name).status()?;
+        return Ok(());
+    }
+
+    let output = Command::new(env::current_exe()?)
+        .arg("-c")
+        .arg(format!("'{}' --help 2>&1", name.replace('\'', "'\\''")))
+        .stdin(Stdio::null())
+        .output()?;
+    let mut pager = pager().stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = pager.stdin.take() {
+        _ = stdin.write_all(&output.stdout);
+    }
+    pager.wait()?;
+    Ok(())
+}
+
+// Alt-h, help for the command the line starts with. The line being edited
+// stays as it was and gets drawn again below.
+struct ShowHelp;
+
+impl ConditionalEventHandler for ShowHelp {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let name = ctx.line().split_whitespace().next()?;
+        _ = terminal::emit("\r\n");
+        if let Err(err) = show_help(name) {
+            _ = terminal::emit(&format!("{}: {}\r\n", name, err));
+        }
+        Some(Cmd::Repaint)
+    }
+}
+
+// Ctrl-Space, the command palette. What gets picked goes in at the cursor.
+struct Palette;
+
+impl ConditionalEventHandler for Palette {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        match palette::pick() {
+            Ok(Some(text)) => Some(Cmd::Insert(1, text)),
+            Ok(None) | Err(_) => Some(Cmd::Repaint),
+        }
+    }
+}
+
+// Ctrl-Z again right after it stopped the pipeline at the prompt carries the
+// pipeline on in the background, as `bg` would. Otherwise it does nothing,
+// since the shell itself doesn't stop for it.
+struct Background;
+
+impl ConditionalEventHandler for Background {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        let Some(id) = jobs::just_stopped(jobs::DOUBLE_STOP) else {
+            return Some(Cmd::Noop);
+        };
+        if jobs::resume(id).is_err() {
+            return Some(Cmd::Noop);
+        }
+        let number = jobs::number(id).unwrap_or_default();
+        let command = jobs::command(id).unwrap_or_default();
+        _ = terminal::emit(&format!("\r\n[{}]+ {} &\r\n", number, command));
+        Some(Cmd::Repaint)
+    }
+}
+
+// Enter on an empty line, with `set -o enterfg` and just the one job stopped,
+// puts `fg %N` on the line for a second Enter to bring it back. Otherwise
+// Enter runs the line as usual.
+struct OfferFg;
+
+impl ConditionalEventHandler for OfferFg {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if !options::is_set("enterfg") || !ctx.line().trim().is_empty() {
+            return None;
+        }
+        let number = jobs::only_stopped()?;
+        Some(Cmd::Insert(1, format!("fg %{}", number)))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Quotes {
+    Single,
+    Double,
+}
+
+// Alt-' and Alt-", which turn the whole line into one quoted word that reads
+// back as what was typed. The editor has no selection to go by. The cursor is
+// left in front, for the command the word is going to be an argument of.
+struct QuoteLine(Quotes);
+
+impl ConditionalEventHandler for QuoteLine {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let quoted = match self.0 {
+            Quotes::Single => lexer::single_quoted(ctx.line()),
+            Quotes::Double => lexer::double_quoted(ctx.line()),
+        };
+        Some(Cmd::Replace(Movement::WholeBuffer, Some(quoted)))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FzfWidget {
+    History,
+    Files,
+    Directory,
+}
+
+// Ctrl-R, Ctrl-T and Alt-C through fzf, with `set -o fzf`. Otherwise the keys
+// do what they always do.
+struct Fzf(FzfWidget);
+
+impl ConditionalEventHandler for Fzf {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if !fzf::enabled() {
+            return None;
+        }
+        let picked = match self.0 {
+            // Replacing the line would leave the cursor at its start, so an
+            // empty line gets the pick inserted instead.
+            FzfWidget::History => fzf::history(ctx.line()).map(|line| {
+                line.map(|line| match ctx.line().is_empty() {
+                    true => Cmd::Insert(1, line),
+                    false => Cmd::Replace(Movement::WholeLine, Some(line)),
+                })
+            }),
+            FzfWidget::Files => fzf::files().map(|files| files.map(|files| Cmd::Insert(1, files))),
+            FzfWidget::Directory => fzf::directory().map(|dir| {
+                dir.map(|dir| match fzf::change_directory(&dir) {
+                    Ok(()) => Cmd::Repaint,
+                    Err(err) => {
+                        _ = terminal::emit(&format!("\r\ncd: {}\r\n", err));
+                        Cmd::Repaint
+                    }
+                })
+            }),
+        };
+        match picked {
+            Ok(Some(cmd)) => Some(cmd),
+            Ok(None) | Err(_) => Some(Cmd::Repaint),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum WordMotion {
+    Backward,
+    Forward,
+    KillBackward,
+    KillForward,
+}
+
+/// What the word motions treat as a word, from $WORDSTYLE:
+/// - `whitespace`: anything between blanks, so Ctrl-w takes a whole path.
+/// - `punctuation`: letters, digits and `_`, or a run of other symbols, so
+///   `/` and `-` end a word.
+/// - `alnum`: only letters and digits.
+///
+/// Left unset, rustyline's defaults stay: Alt-b/Alt-f stop at anything that
+/// isn't a letter or digit, and Ctrl-w at blanks.
+fn word_style() -> Option<Word> {
+    match env::var("WORDSTYLE").ok()?.as_str() {
+        "whitespace" => Some(Word::Big),
+        "punctuation" => Some(Word::Vi),
+        "alnum" => Some(Word::Emacs),
+        _ => None,
+    }
+}
+
+// Alt-b, Alt-f, Ctrl-w and Alt-d, all with the same idea of a word.
+struct WordMotions(WordMotion);
+
+impl ConditionalEventHandler for WordMotions {
+    fn handle(&self, _: &Event, n: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        let word = word_style()?;
+        Some(match self.0 {
+            WordMotion::Backward => Cmd::Move(Movement::BackwardWord(n, word)),
+            WordMotion::Forward => Cmd::Move(Movement::ForwardWord(n, At::AfterEnd, word)),
+            WordMotion::KillBackward => Cmd::Kill(Movement::BackwardWord(n, word)),
+            WordMotion::KillForward => Cmd::Kill(Movement::ForwardWord(n, At::AfterEnd, word)),
+        })
+    }
+}
+
+pub fn install(editor: &mut Shell) {
+    editor.bind_sequence(
+        KeyEvent::alt('w'),
+        EventHandler::Conditional(Box::new(CopyLine)),
+    );
+    editor.bind_sequence(
+        KeyEvent::alt('h'),
+        EventHandler::Conditional(Box::new(ShowHelp)),
+    );
+    // Terminals send Ctrl-Space as NUL, which reads as Ctrl-@.
+    editor.bind_sequence(
+        KeyEvent::ctrl('@'),
+        EventHandler::Conditional(Box::new(Palette)),
+    );
+    editor.bind_sequence(
+        KeyEvent::ctrl('z'),
+        EventHandler::Conditional(Box::new(Background)),
+    );
+    editor.bind_sequence(
+        KeyEvent(KeyCode::Enter, Modifiers::NONE),
+        EventHandler::Conditional(Box::new(OfferFg)),
+    );
+
+    for (key, quotes) in [
+        (KeyEvent::alt('\''), Quotes::Single),
+        (KeyEvent::alt('"'), Quotes::Double),
+    ] {
+        editor.bind_sequence(key, EventHandler::Conditional(Box::new(QuoteLine(quotes))));
+    }
+
+    for (key, widget) in [
+        (KeyEvent::ctrl('r'), FzfWidget::History),
+        (KeyEvent::ctrl('t'), FzfWidget::Files),
+        (KeyEvent::alt('c'), FzfWidget::Directory),
+    ] {
+        editor.bind_sequence(key, EventHandler::Conditional(Box::new(Fzf(widget))));
+    }
+
+    for (key, motion) in [
+        (KeyEvent::alt('b'), WordMotion::Backward),
+        (KeyEvent::alt('f'), WordMotion::Forward),
+        (KeyEvent::ctrl('w'), WordMotion::KillBackward),
+        (KeyEvent::alt('d'), WordMotion::KillForward),
+    ] {
+        editor.bind_sequence(
+            key,
+            EventHandler::Conditional(Box::new(WordMotions(motion))),
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    io::{self, PipeWriter, Read, Write, pipe},
+    sync::Mutex,
+    thread::{self, JoinHandle},
+};
+
+use crate::vars;
+
+/// Only this much of a command's output is kept, the rest still reaches the
+/// terminal but is dropped from $LAST_OUTPUT.
+const CAPTURE_LIMIT: usize = 64 * 1024;
+
+static LAST_OUTPUT: Mutex<Option<String>> = Mutex::new(None);
+
+pub struct Capture {
+    handle: JoinHandle<Vec<u8>>,
+}
+
+// Tees whatever is written to the returned pipe onto our stdout.
+pub fn start() -> io::Result<(PipeWriter, Capture)> {
+    let (mut reader, writer) = pipe()?;
+
+    let handle = thread::spawn(move || {
+        let mut captured = Vec::new();
+        let mut buf = [0; 8192];
+        let mut stdout = io::stdout();
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            _ = stdout.write_all(&buf[..n]);
+            _ = stdout.flush();
+
+            let room = CAPTURE_LIMIT.saturating_sub(captured.len());
+            captured.extend_from_slice(&buf[..n.min(room)]);
+        }
+        captured
+    });
+
+    Ok((writer, Capture { handle }))
+}
+
+impl Capture {
+    pub fn finish(self) {
+        let captured = self.handle.join().unwrap_or_default();
+        let output = String::from_utf8_lossy(&captured)
+            .trim_end_matches('\n')
+            .to_string();
+
+        vars::set("LAST_OUTPUT", output.clone());
+        *LAST_OUTPUT.lock().expect("Failed to lock last output!") = Some(output);
+    }
+}
+
+/// The last captured output, which `!!:out` expands to.
+pub fn last_output() -> Option<String> {
+    LAST_OUTPUT
+        .lock()
+        .expect("Failed to lock last output!")
+        .clone()
+}
+
+/// Locks the last output for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    LAST_OUTPUT.lock().expect("Failed to lock last output!")
+}
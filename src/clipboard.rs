@@ -0,0 +1,116 @@
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+use crate::terminal;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub fn osc52(data: &[u8]) -> String {
+    format!("\x1b]52;c;{}\x07", base64(data))
+}
+
+fn copy_commands() -> Vec<Vec<&'static str>> {
+    let mut commands = vec![vec!["pbcopy"]];
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(vec!["wl-copy"]);
+    }
+    if env::var_os("DISPLAY").is_some() {
+        commands.push(vec!["xclip", "-selection", "clipboard"]);
+        commands.push(vec!["xsel", "--clipboard", "--input"]);
+    }
+    commands.push(vec!["clip.exe"]);
+    commands
+}
+
+fn paste_commands() -> Vec<Vec<&'static str>> {
+    let mut commands = vec![vec!["pbpaste"]];
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(vec!["wl-paste", "--no-newline"]);
+    }
+    if env::var_os("DISPLAY").is_some() {
+        commands.push(vec!["xclip", "-selection", "clipboard", "-o"]);
+        commands.push(vec!["xsel", "--clipboard", "--output"]);
+    }
+    commands.push(vec![
+        "powershell.exe",
+        "-NoProfile",
+        "-Command",
+        "Get-Clipboard",
+    ]);
+    commands
+}
+
+fn copy_with(command: &[&str], data: &[u8]) -> io::Result<bool> {
+    let mut child = match Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(data)?;
+    }
+    Ok(child.wait()?.success())
+}
+
+pub fn copy_osc52(data: &[u8]) -> io::Result<()> {
+    terminal::emit(&osc52(data))
+}
+
+pub fn copy(data: &[u8]) -> io::Result<()> {
+    for command in copy_commands() {
+        if copy_with(&command, data)? {
+            return Ok(());
+        }
+    }
+    copy_osc52(data)
+}
+
+pub fn paste() -> io::Result<Vec<u8>> {
+    for command in paste_commands() {
+        let output = match Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        if output.status.success() {
+            return Ok(output.stdout);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no clipboard tool found (pbpaste, wl-paste, xclip, xsel)",
+    ))
+}
@@ -0,0 +1,119 @@
+use std::{collections::BTreeMap, fs, os::unix::fs::PermissionsExt, path::Path, sync::Mutex};
+
+use rustyline::completion::Pair;
+
+use crate::{lscolors, names, options, paths, snippets};
+
+// The LS_COLORS style of each entry in the last path listing, by what the
+// listing shows, since that is all the highlighter gets to see.
+static STYLES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// How the listing should color a path candidate, if at all.
+pub fn style(display: &str) -> Option<String> {
+    STYLES
+        .lock()
+        .expect("Failed to lock completion styles!")
+        .get(display)
+        .cloned()
+}
+
+/// Keeps the last path listing's colors off candidates that aren't paths.
+pub fn clear_styles() {
+    STYLES
+        .lock()
+        .expect("Failed to lock completion styles!")
+        .clear();
+}
+
+/// Completes a path argument. Directories get a trailing `/` so completion
+/// can carry on into them, and symlinks are marked with `@` in the listing.
+pub fn paths(word: &str) -> Vec<Pair> {
+    candidates(word, |_| true)
+}
+
+/// Completes a path typed as the command, like `./scr`. Only directories and
+/// files someone may execute are worth offering there.
+pub fn commands(word: &str) -> Vec<Pair> {
+    candidates(word, |path| {
+        path.metadata()
+            .is_ok_and(|m| m.is_dir() || m.permissions().mode() & 0o111 != 0)
+    })
+}
+
+pub fn snippets(word: &str) -> Vec<Pair> {
+    snippets::names()
+        .into_iter()
+        .filter(|name| name.starts_with(word))
+        .map(|name| Pair {
+            display: name.clone(),
+            replacement: name + " ",
+        })
+        .collect()
+}
+
+fn candidates(word: &str, keep: fn(&Path) -> bool) -> Vec<Pair> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+
+    let lookup = if dir.is_empty() { "." } else { dir };
+    let dir_path = paths::resolve(Path::new(lookup), paths::physical());
+    let Ok(entries) = fs::read_dir(dir_path) else {
+        return Vec::new();
+    };
+
+    let colors = lscolors::LsColors::from_env();
+    let icons = options::is_set("icons");
+    let mut styles = BTreeMap::new();
+
+    let prefix = names::fold(prefix).into_owned();
+    let mut matches: Vec<(String, Pair)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !names::fold(&name).starts_with(&prefix)
+                || name.starts_with('.') && !prefix.starts_with('.')
+            {
+                return None;
+            }
+
+            if !keep(&entry.path()) {
+                return None;
+            }
+
+            let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+            let is_dir = entry.path().is_dir();
+            let marker = match (is_symlink, is_dir) {
+                (true, _) => "@",
+                (false, true) => "/",
+                (false, false) => "",
+            };
+            let path = entry.path();
+            let display = match icons {
+                true => format!("{} {}{}", lscolors::icon(&path), name, marker),
+                false => format!("{}{}", name, marker),
+            };
+            if let Some(style) = colors.as_ref().and_then(|colors| colors.style(&path)) {
+                styles.insert(display.clone(), style.to_string());
+            }
+            let replacement = format!("{}{}{}", dir, name, if is_dir { "/" } else { " " });
+            Some((
+                name,
+                Pair {
+                    display,
+                    replacement,
+                },
+            ))
+        })
+        .collect();
+
+    *STYLES.lock().expect("Failed to lock completion styles!") = styles;
+    matches.sort_by(|(a, _), (b, _)| a.cmp(b));
+    matches.into_iter().map(|(_, pair)| pair).collect()
+}
+
+/// Locks the completion styles for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    STYLES.lock().expect("Failed to lock completion styles!")
+}
@@ -0,0 +1,153 @@
+use std::{
+    env,
+    fs::{self, File, Permissions},
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::PathBuf,
+    process,
+    sync::OnceLock,
+    thread,
+};
+
+use crate::{jobs, options, params, vars};
+
+/// Runs the command in an `eval` request, writing what it prints to the file
+/// given, and gives its status.
+pub type Eval = Box<dyn Fn(&str, File) -> io::Result<i32> + Send>;
+
+// Set once the socket is listening, so it is only ever set up once.
+static LISTENING: OnceLock<PathBuf> = OnceLock::new();
+
+/// Where the shell with this pid listens: in $XDG_RUNTIME_DIR, which only its
+/// owner can get into, or the temporary directory without one.
+fn socket_path(pid: u32) -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join(format!("codecrafters-shell-{}.sock", pid))
+}
+
+/// Starts answering requests on the shell's socket, unless it already is.
+/// Each connection sends one request and reads the reply until the socket
+/// closes:
+///
+/// - `eval COMMAND` runs the command like it was typed,
+/// - `get-var NAME` gives a variable's value,
+/// - `list-jobs` lists the background jobs.
+///
+/// The reply is whatever the request printed, then a NUL and its status.
+pub fn listen(eval: Eval) {
+    if LISTENING.get().is_some() {
+        return;
+    }
+    let path = socket_path(process::id());
+    // Left behind by an earlier shell that had the same pid.
+    _ = fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("codecrafters-shell: {}: {}", path.to_string_lossy(), err);
+            _ = options::set("control", false);
+            return;
+        }
+    };
+    _ = fs::set_permissions(&path, Permissions::from_mode(0o600));
+    _ = LISTENING.set(path);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            _ = answer(stream, &eval);
+        }
+    });
+}
+
+/// Removes the socket as the shell exits.
+pub fn close() {
+    if let Some(path) = LISTENING.get() {
+        _ = fs::remove_file(path);
+    }
+}
+
+// Only the user the shell runs as may talk to it, wherever the socket is.
+fn same_user(stream: &UnixStream) -> bool {
+    let mut credentials: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+    let found = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&raw mut credentials).cast(),
+            &mut len,
+        )
+    } == 0;
+    found && credentials.uid == unsafe { libc::getuid() }
+}
+
+fn answer(mut stream: UnixStream, eval: &Eval) -> io::Result<()> {
+    if !same_user(&stream) {
+        return Ok(());
+    }
+    let mut request = String::new();
+    stream.read_to_string(&mut request)?;
+    let request = request.trim_end_matches('\n');
+    let (kind, rest) = request.split_once(' ').unwrap_or((request, ""));
+
+    let status = match kind {
+        // The set -o can be taken back after the socket is up.
+        _ if !options::is_set("control") => {
+            stream.write_all(b"control: set -o control is off\n")?;
+            1
+        }
+        "eval" => eval(rest, File::from(OwnedFd::from(stream.try_clone()?)))?,
+        "get-var" => {
+            let value = match params::is_special(rest) {
+                true => params::is_set(rest).then(|| params::get(rest, false)),
+                false => vars::get(rest),
+            };
+            match value {
+                Some(value) => {
+                    stream.write_all(format!("{}\n", value).as_bytes())?;
+                    0
+                }
+                None => 1,
+            }
+        }
+        "list-jobs" => {
+            for line in jobs::list(false, false) {
+                stream.write_all(format!("{}\n", line).as_bytes())?;
+            }
+            0
+        }
+        kind => {
+            stream.write_all(format!("control: {}: unknown request\n", kind).as_bytes())?;
+            2
+        }
+    };
+    stream.write_all(format!("\0{}", status).as_bytes())
+}
+
+/// `--send PID REQUEST...`: sends a request to the shell with that pid and
+/// prints the reply, giving the request's status.
+pub fn send(pid: u32, request: &[String]) -> io::Result<i32> {
+    let mut stream = UnixStream::connect(socket_path(pid))?;
+    stream.write_all(request.join(" ").as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply)?;
+    let Some(end) = reply.iter().rposition(|&b| b == 0) else {
+        return Err(io::Error::other("the shell closed the connection"));
+    };
+    io::stdout().write_all(&reply[..end])?;
+    Ok(String::from_utf8_lossy(&reply[end + 1..])
+        .parse()
+        .unwrap_or(1))
+}
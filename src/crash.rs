@@ -0,0 +1,132 @@
+use std::{
+    env,
+    ffi::CStr,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The core file size limit children start with, in bytes, or None if it is
+/// unlimited.
+pub fn core_limit() -> io::Result<Option<u64>> {
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur))
+}
+
+/// Sets the core file size limit for the shell and so for every child it
+/// starts after. It can't go past the hard limit.
+pub fn set_core_limit(bytes: Option<u64>) -> io::Result<()> {
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    limit.rlim_cur = bytes.unwrap_or(libc::RLIM_INFINITY);
+    match unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn describe(signal: i32) -> String {
+    let description = unsafe { libc::strsignal(signal) };
+    match description.is_null() {
+        true => format!("Killed by signal {}", signal),
+        false => unsafe { CStr::from_ptr(description) }
+            .to_string_lossy()
+            .to_string(),
+    }
+}
+
+// Where the kernel wrote the core, going by /proc/sys/kernel/core_pattern.
+// Patterns piped to a program, like systemd-coredump, name the program.
+fn core_location(name: &str, pid: u32, signal: i32) -> Option<String> {
+    let pattern = fs::read_to_string("/proc/sys/kernel/core_pattern").ok()?;
+    let pattern = pattern.trim_end();
+    if let Some(program) = pattern.strip_prefix('|') {
+        let program = program.split_whitespace().next()?;
+        let program = Path::new(program).file_name()?.to_string_lossy();
+        return Some(match program.as_ref() {
+            "systemd-coredump" => format!("see coredumpctl info {}", pid),
+            program => format!("handed to {}", program),
+        });
+    }
+
+    // The kernel cuts the command name short, like in /proc/PID/comm.
+    let comm: String = Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().chars().take(15).collect())
+        .unwrap_or_default();
+    let mut path = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            path.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '%' => path.push('%'),
+            'p' | 'P' | 'i' | 'I' => path.push_str(&pid.to_string()),
+            'e' => path.push_str(&comm),
+            's' => path.push_str(&signal.to_string()),
+            'u' => path.push_str(&unsafe { libc::getuid() }.to_string()),
+            'g' => path.push_str(&unsafe { libc::getgid() }.to_string()),
+            't' => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+                path.push_str(&now.as_secs().to_string());
+            }
+            'h' => {
+                let host = fs::read_to_string("/proc/sys/kernel/hostname").ok()?;
+                path.push_str(host.trim_end());
+            }
+            // Anything else can't be worked out from here.
+            _ => return None,
+        }
+    }
+    let uses_pid = fs::read_to_string("/proc/sys/kernel/core_uses_pid")
+        .is_ok_and(|uses_pid| uses_pid.trim() == "1");
+    if uses_pid && !pattern.contains("%p") {
+        path.push_str(&format!(".{}", pid));
+    }
+
+    // Relative to the directory the child was in, which is still the shell's.
+    let path = env::current_dir().ok()?.join(PathBuf::from(path));
+    path.exists().then(|| path.to_string_lossy().to_string())
+}
+
+/// Says on stderr how a child was killed, the way sh does, since its status
+/// alone is easy to miss: "Segmentation fault (core dumped)". Ctrl-C and a
+/// reader going away down a pipe are how commands usually end, so SIGINT and
+/// SIGPIPE say nothing.
+pub fn report(name: &str, pid: u32, signal: i32, core_dumped: bool) {
+    if signal == libc::SIGINT || signal == libc::SIGPIPE {
+        return;
+    }
+
+    let crashed = [
+        libc::SIGSEGV,
+        libc::SIGBUS,
+        libc::SIGABRT,
+        libc::SIGFPE,
+        libc::SIGILL,
+    ]
+    .contains(&signal);
+    let detail = match core_dumped {
+        true => match core_location(name, pid, signal) {
+            Some(location) => format!(" (core dumped: {})", location),
+            None => " (core dumped)".to_string(),
+        },
+        false if crashed && core_limit().is_ok_and(|limit| limit == Some(0)) => {
+            " (no core dumped; `ulimit -c unlimited` allows one)".to_string()
+        }
+        false => String::new(),
+    };
+    eprintln!(
+        "codecrafters-shell: {}: {}{}",
+        name,
+        describe(signal),
+        detail
+    );
+}
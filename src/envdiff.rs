@@ -0,0 +1,59 @@
+use std::{collections::BTreeMap, env, sync::OnceLock};
+
+use crate::vars;
+
+static STARTUP: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+
+fn current() -> BTreeMap<String, String> {
+    env::vars_os()
+        .map(|(name, value)| {
+            (
+                name.to_string_lossy().to_string(),
+                value.to_string_lossy().to_string(),
+            )
+        })
+        .chain(vars::exported())
+        .collect()
+}
+
+/// Remembers the environment the shell started with, before anything it runs
+/// gets a chance to change it.
+pub fn snapshot() {
+    _ = STARTUP.set(current());
+}
+
+pub enum Change {
+    Added(String, String),
+    Changed(String, String, String),
+    Removed(String, String),
+}
+
+/// Every variable that differs from the environment at startup, by name.
+pub fn changes() -> Vec<Change> {
+    let before = STARTUP.get_or_init(BTreeMap::new);
+    let mut after = current();
+
+    let mut changes = Vec::new();
+    for (name, old) in before {
+        match after.remove(name) {
+            Some(new) if new == *old => {}
+            Some(new) => changes.push(Change::Changed(name.clone(), old.clone(), new)),
+            None => changes.push(Change::Removed(name.clone(), old.clone())),
+        }
+    }
+    changes.extend(
+        after
+            .into_iter()
+            .map(|(name, value)| Change::Added(name, value)),
+    );
+    changes.sort_by(|a, b| a.name().cmp(b.name()));
+    changes
+}
+
+impl Change {
+    pub fn name(&self) -> &str {
+        match self {
+            Change::Added(name, _) | Change::Changed(name, _, _) | Change::Removed(name, _) => name,
+        }
+    }
+}
@@ -0,0 +1,495 @@
+use std::{iter::Peekable, mem, str::Chars};
+
+use thiserror::Error;
+
+use crate::{
+    capture, glob,
+    lexer::{self, Word, WordPart},
+    params::{self, FIELD_BREAK},
+    vars,
+};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ExpandError {
+    #[error("{0}: bad substitution")]
+    BadSubstitution(String),
+    /// `${NAME:?message}` with NAME unset or empty.
+    #[error("{0}: {1}")]
+    Required(String, String),
+}
+
+fn lookup(name: &str, quoted: bool) -> String {
+    match params::is_special(name) {
+        true => params::get(name, quoted),
+        false => vars::get(name).unwrap_or_default(),
+    }
+}
+
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// The word after an operator like `:-`, with its quotes removed. Also gives
+// it as a glob pattern, in which quoted characters only match themselves.
+fn operand(text: &str) -> Result<(String, String), ExpandError> {
+    let mut expanded = String::new();
+    let mut pattern = String::new();
+    let mut in_double = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let mut quoted = String::new();
+        match c {
+            '"' => {
+                in_double = !in_double;
+                continue;
+            }
+            '\\' => quoted.extend(chars.next()),
+            '\'' if !in_double => quoted.extend(chars.by_ref().take_while(|c| *c != '\'')),
+            '$' => {
+                let mut value = String::new();
+                parameter(&mut chars, &mut value, in_double)?;
+                match in_double {
+                    true => quoted = value,
+                    false => {
+                        expanded.push_str(&value);
+                        pattern.push_str(&value);
+                        continue;
+                    }
+                }
+            }
+            c if in_double => quoted.push(c),
+            c => {
+                expanded.push(c);
+                pattern.push(c);
+                continue;
+            }
+        }
+        expanded.push_str(&quoted);
+        pattern.push_str(&glob::escape(&quoted));
+    }
+    Ok((expanded, pattern))
+}
+
+// `${NAME#pattern}` and friends: cuts the shortest (or, doubled, the longest)
+// match of the pattern off the front or the back of the value.
+fn strip(value: &str, pattern: &str, suffix: bool, longest: bool) -> String {
+    let mut cuts: Vec<usize> = value
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([value.len()])
+        .collect();
+    // Shortest first: from the front for a prefix, from the back for a suffix.
+    if suffix {
+        cuts.reverse();
+    }
+    if longest {
+        cuts.reverse();
+    }
+
+    for cut in cuts {
+        let (cut_off, kept) = match suffix {
+            true => (&value[cut..], &value[..cut]),
+            false => (&value[..cut], &value[cut..]),
+        };
+        if glob::matches(pattern, cut_off) {
+            return kept.to_string();
+        }
+    }
+    value.to_string()
+}
+
+// Everything between `${` and `}`: a name, maybe preceded by `#` for its
+// length or followed by an operator and its word.
+fn braced(body: &str, quoted: bool) -> Result<String, ExpandError> {
+    let bad = || ExpandError::BadSubstitution(format!("${{{}}}", body));
+
+    if let Some(name) = body.strip_prefix('#')
+        && !name.is_empty()
+    {
+        return match name {
+            "@" | "*" => Ok(params::get("#", quoted)),
+            name if is_name(name) || params::is_special(name) => {
+                Ok(lookup(name, quoted).chars().count().to_string())
+            }
+            _ => Err(bad()),
+        };
+    }
+
+    let end = match body.starts_with(|c| "?$!#@*".contains(c)) {
+        true => 1,
+        false => body
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(body.len()),
+    };
+    let (name, rest) = body.split_at(end);
+    let special = params::is_special(name);
+    if !is_name(name) && !special {
+        return Err(bad());
+    }
+    if rest.is_empty() {
+        return Ok(lookup(name, quoted));
+    }
+
+    let value = match special {
+        true => params::is_set(name).then(|| params::get(name, quoted)),
+        false => vars::get(name),
+    };
+    // With a `:`, an empty value counts as unset too.
+    let (colon, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let unset = match colon {
+        true => value.as_deref().is_none_or(str::is_empty),
+        false => value.is_none(),
+    };
+    let value = value.unwrap_or_default();
+
+    let mut ops = rest.chars();
+    let op = ops.next().ok_or_else(bad)?;
+    let doubled = !colon && matches!(op, '#' | '%') && ops.next() == Some(op);
+    let word = &rest[op.len_utf8() * if doubled { 2 } else { 1 }..];
+
+    match op {
+        '-' if unset => Ok(operand(word)?.0),
+        // Only variables can be assigned to.
+        '=' if unset && special => Err(bad()),
+        '=' if unset => {
+            let word = operand(word)?.0;
+            vars::set(name, word.clone());
+            Ok(word)
+        }
+        '?' if unset => {
+            let message = match operand(word)?.0 {
+                message if message.is_empty() => "parameter null or not set".to_string(),
+                message => message,
+            };
+            Err(ExpandError::Required(name.to_string(), message))
+        }
+        '+' if unset => Ok(String::new()),
+        '+' => Ok(operand(word)?.0),
+        '-' | '=' | '?' => Ok(value),
+        '#' | '%' if !colon => Ok(strip(&value, &operand(word)?.1, op == '%', doubled)),
+        _ => Err(bad()),
+    }
+}
+
+// Called with the `$` already consumed. `quoted` is whether it is inside
+// double quotes.
+fn parameter(
+    chars: &mut Peekable<Chars>,
+    out: &mut String,
+    quoted: bool,
+) -> Result<(), ExpandError> {
+    if chars.next_if_eq(&'{').is_some() {
+        let rest: String = chars.clone().collect();
+        let body = lexer::enclosed(chars, '{', '}')
+            .ok_or_else(|| ExpandError::BadSubstitution(format!("${{{}", rest)))?;
+        out.push_str(&braced(&body, quoted)?);
+        return Ok(());
+    }
+    let mut name = String::new();
+    match chars.next_if(|c| c.is_ascii_alphanumeric() || "_?$!#@*".contains(*c)) {
+        Some(c) if !c.is_ascii_alphabetic() && c != '_' => name.push(c),
+        Some(c) => {
+            name.push(c);
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                name.push(c);
+            }
+        }
+        // A `$` that doesn't start a name is just a dollar sign.
+        None => {
+            out.push('$');
+            return Ok(());
+        }
+    }
+    out.push_str(&lookup(&name, quoted));
+    Ok(())
+}
+
+// A stretch of an expanded word. Only what unquoted expansions produced gets
+// split on $IFS; everything else stays in whichever field it lands in.
+struct Piece {
+    text: String,
+    pattern: String,
+    split: bool,
+}
+
+impl Piece {
+    fn new(text: String, quoted: bool, split: bool) -> Self {
+        let pattern = match quoted {
+            true => glob::escape(&text),
+            false => text.clone(),
+        };
+        Piece {
+            text,
+            pattern,
+            split,
+        }
+    }
+}
+
+fn expand_text(text: &str, quoted: bool, pieces: &mut Vec<Piece>) -> Result<(), ExpandError> {
+    let mut chars = text.chars().peekable();
+    let mut literal = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => {
+                let mut value = String::new();
+                parameter(&mut chars, &mut value, quoted)?;
+                if !literal.is_empty() {
+                    pieces.push(Piece::new(mem::take(&mut literal), quoted, false));
+                }
+                pieces.push(Piece::new(value, quoted, !quoted));
+            }
+            // `!!:out` is the last captured output as one word, quoted or not.
+            '!' if chars.clone().take(5).eq("!:out".chars())
+                && let Some(output) = capture::last_output() =>
+            {
+                chars.nth(4);
+                if !literal.is_empty() {
+                    pieces.push(Piece::new(mem::take(&mut literal), quoted, false));
+                }
+                pieces.push(Piece::new(output, true, false));
+            }
+            c => literal.push(c),
+        }
+    }
+    // An empty pair of quotes still makes an argument; empty bare text doesn't.
+    if quoted || !literal.is_empty() {
+        pieces.push(Piece::new(literal, quoted, false));
+    }
+    Ok(())
+}
+
+/// Expands `$NAME`, `${NAME}` and `!!:out` in the unquoted and double-quoted
+/// parts of a word. Single-quoted and escaped text is left alone.
+pub fn word(word: &Word) -> Result<String, ExpandError> {
+    let text: String = expand_parts(word)?
+        .iter()
+        .map(|piece| piece.text.as_str())
+        .collect();
+    Ok(text.replace(FIELD_BREAK, " "))
+}
+
+/// Expands a word into a glob pattern, without splitting it, in which the
+/// quoted parts only match themselves, as for the arms of a `case`.
+pub fn pattern(word: &Word) -> Result<String, ExpandError> {
+    let pattern: String = expand_parts(word)?
+        .iter()
+        .map(|piece| piece.pattern.as_str())
+        .collect();
+    Ok(pattern.replace(FIELD_BREAK, " "))
+}
+
+// Expands each part of a word. The pieces carry a glob pattern too, in which
+// anything that was quoted is escaped so it only matches itself.
+fn expand_parts(word: &Word) -> Result<Vec<Piece>, ExpandError> {
+    let mut pieces = Vec::new();
+    for part in &word.0 {
+        match part {
+            WordPart::Bare(text) => expand_text(text, false, &mut pieces)?,
+            WordPart::Double(text) => expand_text(text, true, &mut pieces)?,
+            WordPart::Literal(text) => pieces.push(Piece::new(text.clone(), true, false)),
+            WordPart::ProcessIn(_) | WordPart::ProcessOut(_) => {
+                unreachable!("process substitutions are started before expansion")
+            }
+        }
+    }
+    Ok(pieces)
+}
+
+// What ended the last field, which decides whether a non-whitespace IFS
+// character right after it makes an empty field (`a::b`) or not (`a : b`).
+#[derive(Clone, Copy, PartialEq)]
+enum Delimiter {
+    Start,
+    Space,
+    Other,
+}
+
+// Word splitting: breaks what unquoted expansions produced at $IFS
+// characters. Runs of IFS whitespace count once and vanish at the edges,
+// while every other IFS character ends a field, empty or not. Unset, IFS is
+// space, tab and newline; set but empty, nothing is split.
+fn split_fields(pieces: Vec<Piece>, ifs: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    let mut current: Option<(String, String)> = None;
+    let mut delimiter = Delimiter::Start;
+
+    for piece in pieces {
+        if !piece.split {
+            // Only `"$@"` leaves breaks in quoted text, one per argument.
+            let mut texts = piece.text.split(FIELD_BREAK);
+            let mut patterns = piece.pattern.split(FIELD_BREAK);
+            let field = current.get_or_insert_default();
+            field.0.push_str(texts.next().unwrap_or_default());
+            field.1.push_str(patterns.next().unwrap_or_default());
+            for (text, pattern) in texts.zip(patterns) {
+                fields.extend(current.take());
+                current = Some((text.to_string(), pattern.to_string()));
+            }
+            continue;
+        }
+
+        for c in piece.text.chars() {
+            let space = c == FIELD_BREAK || ifs.contains(c) && c.is_whitespace();
+            match current.take() {
+                Some(field) if space || ifs.contains(c) => {
+                    fields.push(field);
+                    delimiter = match space {
+                        true => Delimiter::Space,
+                        false => Delimiter::Other,
+                    };
+                }
+                None if space => {}
+                None if ifs.contains(c) => match delimiter {
+                    Delimiter::Space => delimiter = Delimiter::Other,
+                    Delimiter::Start | Delimiter::Other => fields.push(Default::default()),
+                },
+                field => {
+                    let (text, pattern) = current.insert(field.unwrap_or_default());
+                    text.push(c);
+                    pattern.push(c);
+                }
+            }
+        }
+    }
+    fields.extend(current);
+    fields
+}
+
+/// Where a word's arguments came from, as `explain` reports it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origin {
+    Literal,
+    Variable,
+    Glob,
+}
+
+fn has_parameter(text: &str) -> bool {
+    text.chars()
+        .zip(text.chars().skip(1))
+        .any(|(c, next)| c == '$' && (next.is_ascii_alphanumeric() || "_{?$!#@*".contains(next)))
+}
+
+/// Expands one word into the arguments it becomes: unquoted expansions are
+/// split on $IFS, leaving none if they came out empty, then each field that
+/// is a pattern becomes every path it matches.
+pub fn fields(word: &Word) -> Result<(Vec<String>, Origin), ExpandError> {
+    let pieces = expand_parts(word)?;
+
+    let origin = match word.0.iter().any(|part| match part {
+        WordPart::Bare(text) | WordPart::Double(text) => has_parameter(text),
+        _ => false,
+    }) {
+        true => Origin::Variable,
+        false => Origin::Literal,
+    };
+    // `"$@"` with no arguments leaves nothing behind, not even an empty one.
+    let all_arguments = word.0.iter().any(|part| {
+        matches!(part, WordPart::Double(text) if text.contains("$@") || text.contains("${@}"))
+    });
+    if all_arguments && params::no_positional() && pieces.iter().all(|p| p.text.is_empty()) {
+        return Ok((Vec::new(), origin));
+    }
+
+    let mut fields = Vec::new();
+    let mut globbed = false;
+    let ifs = vars::get("IFS").unwrap_or_else(|| " \t\n".to_string());
+    for (text, pattern) in split_fields(pieces, &ifs) {
+        if glob::has_magic(&pattern) {
+            let paths = glob::expand(&pattern);
+            if !paths.is_empty() {
+                fields.extend(paths);
+                globbed = true;
+                continue;
+            }
+        }
+        fields.push(text);
+    }
+    match globbed {
+        true => Ok((fields, Origin::Glob)),
+        false => Ok((fields, origin)),
+    }
+}
+
+/// Expands a command's words, globs included. An unquoted word that expands to
+/// nothing is dropped, so `$UNSET cmd` runs `cmd`, and a pattern that matches
+/// nothing is kept as it is.
+pub fn words(words: &[Word]) -> Result<Vec<String>, ExpandError> {
+    let mut expanded = Vec::new();
+    for w in words {
+        expanded.extend(fields(w)?.0);
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_IFS: &str = " \t\n";
+
+    fn split(pieces: Vec<Piece>, ifs: &str) -> Vec<String> {
+        split_fields(pieces, ifs)
+            .into_iter()
+            .map(|(text, _)| text)
+            .collect()
+    }
+
+    fn unquoted(text: &str) -> Piece {
+        Piece::new(text.to_string(), false, true)
+    }
+
+    #[test]
+    fn splits_on_default_ifs() {
+        assert_eq!(
+            split(vec![unquoted("  x \t y\nz  ")], DEFAULT_IFS),
+            ["x", "y", "z"]
+        );
+    }
+
+    #[test]
+    fn empty_ifs_splits_nothing() {
+        assert_eq!(split(vec![unquoted(" x y ")], ""), [" x y "]);
+    }
+
+    #[test]
+    fn non_whitespace_ifs_keeps_empty_fields() {
+        assert_eq!(split(vec![unquoted("a::b:")], ":"), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn whitespace_around_other_ifs_counts_once() {
+        assert_eq!(split(vec![unquoted("a : b")], " :"), ["a", "b"]);
+    }
+
+    #[test]
+    fn quoted_pieces_are_not_split() {
+        let pieces = vec![Piece::new("x y".to_string(), true, false), unquoted(" z")];
+        assert_eq!(split(pieces, DEFAULT_IFS), ["x y", "z"]);
+    }
+
+    #[test]
+    fn unquoted_variable_splits_but_quoted_does_not() {
+        vars::set("EXPAND_TEST_A", "x y".to_string());
+        let unquoted = Word(vec![WordPart::Bare("$EXPAND_TEST_A".to_string())]);
+        let quoted = Word(vec![WordPart::Double("$EXPAND_TEST_A".to_string())]);
+        assert_eq!(fields(&unquoted).unwrap().0, ["x", "y"]);
+        assert_eq!(fields(&quoted).unwrap().0, ["x y"]);
+    }
+
+    #[test]
+    fn empty_unquoted_variable_leaves_no_field() {
+        let unset = Word(vec![WordPart::Bare("$EXPAND_TEST_UNSET".to_string())]);
+        let quoted = Word(vec![WordPart::Double("$EXPAND_TEST_UNSET".to_string())]);
+        assert!(fields(&unset).unwrap().0.is_empty());
+        assert_eq!(fields(&quoted).unwrap().0, [""]);
+    }
+}
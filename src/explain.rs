@@ -0,0 +1,229 @@
+use std::fmt::Write;
+
+use crate::{
+    expand::{self, Origin},
+    lexer::{self, Word, WordPart},
+    parser::{self, Command, Connector, Group, List, Pipeline, Redirect, RedirectMode},
+};
+
+fn fields(fields: &[String]) -> String {
+    match fields {
+        [] => "(nothing)".to_string(),
+        fields => fields
+            .iter()
+            .map(|field| format!("[{}]", field))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+fn word(word: &Word) -> String {
+    if word
+        .0
+        .iter()
+        .any(|part| matches!(part, WordPart::ProcessIn(_) | WordPart::ProcessOut(_)))
+    {
+        return format!("{} -> a /dev/fd path (process substitution)", word.source());
+    }
+
+    let (expanded, origin) = match expand::fields(word) {
+        Ok(expanded) => expanded,
+        Err(err) => return format!("{} -> error: {}", word.source(), err),
+    };
+    let origin = match origin {
+        Origin::Literal => "",
+        Origin::Variable => " (variable)",
+        Origin::Glob => " (glob)",
+    };
+    format!("{} -> {}{}", word.source(), fields(&expanded), origin)
+}
+
+fn assignment(name: &str, value: &Word, scope: &str) -> String {
+    let assigned = match expand::word(value) {
+        Ok(value) => format!("sets {} to [{}] {}", name, value, scope),
+        Err(err) => format!("error: {}", err),
+    };
+    format!("{}={} -> {}", name, value.source(), assigned)
+}
+
+fn redirect(redirect: &Redirect) -> String {
+    let stream = match redirect.fd {
+        0 => "stdin".to_string(),
+        1 => "stdout".to_string(),
+        2 => "stderr".to_string(),
+        parser::BOTH => "stdout and stderr".to_string(),
+        fd => format!("fd {}", fd),
+    };
+    let target = || match expand::word(&redirect.target) {
+        Ok(target) => format!("[{}]", target),
+        Err(err) => format!("error: {}", err),
+    };
+
+    match redirect.mode {
+        RedirectMode::Read => format!("{} reads from {}", stream, target()),
+        RedirectMode::Truncate => format!("{} overwrites {}", stream, target()),
+        RedirectMode::Clobber => format!("{} overwrites {}, even with noclobber", stream, target()),
+        RedirectMode::Append => format!("{} appends to {}", stream, target()),
+        RedirectMode::HereDoc => format!(
+            "{} reads a here-document of {} line(s)",
+            stream,
+            redirect.target.text().lines().count()
+        ),
+        RedirectMode::HereString => format!("{} reads the here-string {}", stream, target()),
+        RedirectMode::Duplicate => format!(
+            "{} goes wherever fd {} goes",
+            stream,
+            redirect.target.text()
+        ),
+    }
+}
+
+fn command(out: &mut String, depth: usize, index: usize, command: &Command) {
+    let indent = "  ".repeat(depth);
+    _ = writeln!(out, "{}    stage {}:", indent, index + 1);
+    let group = match &command.group {
+        Some(Group::Subshell(body)) => Some(("a subshell, whose changes are undone after", body)),
+        Some(Group::Braces(body)) => Some(("a group, run in this shell", body)),
+        Some(Group::If {
+            branches,
+            otherwise,
+        }) => {
+            for (index, (condition, body)) in branches.iter().enumerate() {
+                let keyword = if index == 0 { "if" } else { "elif" };
+                _ = writeln!(out, "{}      {} this succeeds:", indent, keyword);
+                list(out, depth + 3, condition);
+                _ = writeln!(out, "{}      then:", indent);
+                list(out, depth + 3, body);
+            }
+            if let Some(body) = otherwise {
+                _ = writeln!(out, "{}      else:", indent);
+                list(out, depth + 3, body);
+            }
+            None
+        }
+        Some(Group::Loop {
+            until,
+            condition,
+            body,
+        }) => {
+            let keyword = if *until { "until" } else { "while" };
+            _ = writeln!(out, "{}      {} this succeeds:", indent, keyword);
+            list(out, depth + 3, condition);
+            _ = writeln!(out, "{}      do:", indent);
+            list(out, depth + 3, body);
+            None
+        }
+        Some(Group::Arith(expr)) => {
+            _ = writeln!(
+                out,
+                "{}      arithmetic (({})), which succeeds unless it comes out 0",
+                indent, expr
+            );
+            None
+        }
+        Some(Group::For {
+            init,
+            condition,
+            step,
+            body,
+        }) => {
+            _ = writeln!(
+                out,
+                "{}      (({})) once, then while (({})) is not 0, after each run (({})):",
+                indent, init, condition, step
+            );
+            list(out, depth + 3, body);
+            None
+        }
+        Some(Group::Select { name, words, body }) => {
+            let words = match words {
+                Some(words) => words.iter().map(Word::source).collect::<Vec<_>>().join(" "),
+                None => "\"$@\"".to_string(),
+            };
+            _ = writeln!(
+                out,
+                "{}      a menu of {} on stderr, with the pick in ${} and the reply in $REPLY:",
+                indent, words, name
+            );
+            list(out, depth + 3, body);
+            None
+        }
+        Some(Group::Case { subject, arms }) => {
+            _ = writeln!(
+                out,
+                "{}      the first arm matching {}:",
+                indent,
+                subject.source()
+            );
+            for (patterns, body) in arms {
+                let patterns = patterns.iter().map(Word::source).collect::<Vec<_>>();
+                _ = writeln!(out, "{}      {}):", indent, patterns.join(" | "));
+                list(out, depth + 3, body);
+            }
+            None
+        }
+        None => None,
+    };
+    if let Some((heading, body)) = group {
+        _ = writeln!(out, "{}      {}:", indent, heading);
+        list(out, depth + 3, body);
+    }
+    let scope = match command.words.is_empty() {
+        true => "in the shell",
+        false => "for this command only",
+    };
+    for (name, value) in &command.assignments {
+        _ = writeln!(out, "{}      {}", indent, assignment(name, value, scope));
+    }
+    for w in &command.words {
+        _ = writeln!(out, "{}      {}", indent, word(w));
+    }
+    for r in &command.redirects {
+        _ = writeln!(out, "{}      {}", indent, redirect(r));
+    }
+}
+
+fn pipeline(out: &mut String, depth: usize, heading: &str, pipeline: &Pipeline) {
+    _ = writeln!(out, "{}  {}:", "  ".repeat(depth), heading);
+    for (index, c) in pipeline.commands.iter().enumerate() {
+        command(out, depth, index, c);
+    }
+}
+
+fn list(out: &mut String, depth: usize, list: &List) {
+    for (index, and_or) in list.items.iter().enumerate() {
+        _ = writeln!(out, "{}command {}:", "  ".repeat(depth), index + 1);
+        pipeline(out, depth, "pipeline", &and_or.first);
+        for (connector, next) in &and_or.rest {
+            let heading = match connector {
+                Connector::And => "&& pipeline, if the last one succeeded",
+                Connector::Or => "|| pipeline, if the last one failed",
+            };
+            pipeline(out, depth, heading, next);
+        }
+        if and_or.background.is_some() {
+            _ = writeln!(out, "{}  run in the background", "  ".repeat(depth));
+        }
+    }
+}
+
+/// Describes how a line is tokenized, split into pipelines and stages, and
+/// what each word and redirection target expands to right now.
+pub fn explain(line: &str) -> Result<String, String> {
+    let tokens = lexer::tokenize(line).map_err(|err| err.to_string())?;
+    let parsed = parser::parse(line).map_err(|err| err.to_string())?;
+
+    let mut out = String::new();
+    _ = writeln!(
+        out,
+        "tokens: {}",
+        tokens
+            .iter()
+            .map(|t| format!("[{}]", t.source()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    list(&mut out, 0, &parsed);
+    Ok(out)
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    io::{self, Write},
+    os::fd::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    sync::Mutex,
+};
+
+const AUDIT_VAR: &str = "SHELL_FD_AUDIT";
+
+/// Fds above 2 that `exec 3> file` opened in the shell itself. Children are
+/// meant to inherit these, and `>&3` may refer to them.
+static OPENED: Mutex<BTreeSet<RawFd>> = Mutex::new(BTreeSet::new());
+
+// Everything the shell opens goes through std, which already asks for O_CLOEXEC
+// (files, pipes, read_dir handles), so children should only ever see 0, 1 and 2.
+fn is_cloexec(fd: RawFd) -> bool {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    flags < 0 || flags & libc::FD_CLOEXEC != 0
+}
+
+fn inheritable_fds() -> Vec<RawFd> {
+    let Ok(entries) = fs::read_dir("/proc/self/fd") else {
+        return Vec::new();
+    };
+
+    let mut fds = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter(|fd| *fd > 2 && !is_cloexec(*fd) && !is_opened(*fd))
+        .collect::<Vec<RawFd>>();
+    fds.sort();
+    fds
+}
+
+// Debug builds only: with SHELL_FD_AUDIT set, scan /proc/self/fd before every
+// spawn and abort if a descriptor would leak into the child.
+pub fn audit_before_spawn(program: &str) {
+    if !cfg!(debug_assertions) || env::var_os(AUDIT_VAR).is_none() {
+        return;
+    }
+
+    let leaked = inheritable_fds();
+    if leaked.is_empty() {
+        return;
+    }
+
+    let targets = leaked
+        .iter()
+        .map(|fd| {
+            let target = fs::read_link(format!("/proc/self/fd/{}", fd))
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|_| "?".to_string());
+            format!("{} -> {}", fd, target)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    _ = io::stderr().write_all(
+        format!("{}: fds without CLOEXEC would leak: {}\n", program, targets).as_bytes(),
+    );
+    panic!("fd audit failed before spawning '{}'", program);
+}
+
+fn is_opened(fd: RawFd) -> bool {
+    OPENED.lock().expect("Failed to lock fds!").contains(&fd)
+}
+
+/// In a forked copy of the shell, closes every fd but 0, 1, 2, those `exec`
+/// opened and `kept`. The rest were the other threads', and a pipe's write
+/// end left open here would keep whatever reads it from ever seeing the end.
+pub fn close_all_but(kept: &[RawFd]) {
+    let Ok(entries) = fs::read_dir("/proc/self/fd") else {
+        return;
+    };
+    let open = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect::<Vec<RawFd>>();
+    for fd in open {
+        if fd > 2 && !kept.contains(&fd) && !is_opened(fd) {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+/// Makes `fd` refer to `source` in the shell itself, for `exec` with only
+/// redirections.
+pub fn install(fd: RawFd, source: OwnedFd) -> io::Result<()> {
+    let raw = source.into_raw_fd();
+    if raw == fd {
+        // Already in place; it only needs to survive exec.
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    } else {
+        let result = unsafe { libc::dup2(raw, fd) };
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(raw) };
+        if result < 0 {
+            return Err(err);
+        }
+    }
+
+    if fd > 2 {
+        OPENED.lock().expect("Failed to lock fds!").insert(fd);
+    }
+    Ok(())
+}
+
+/// A new handle on an fd that `exec` opened, for `>&3`.
+pub fn duplicate(fd: RawFd) -> io::Result<OwnedFd> {
+    if !is_opened(fd) {
+        return Err(io::Error::from_raw_os_error(libc::EBADF));
+    }
+    unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()
+}
+
+/// Moves every source above all the targets first, so putting one source on
+/// its target can never overwrite another source that is still to be placed.
+pub fn lift(moves: Vec<(RawFd, OwnedFd)>) -> io::Result<Vec<(RawFd, OwnedFd)>> {
+    let above = moves.iter().map(|(target, _)| *target).max().unwrap_or(2) + 1;
+    moves
+        .into_iter()
+        .map(|(target, source)| {
+            let lifted = unsafe { libc::fcntl(source.as_raw_fd(), libc::F_DUPFD_CLOEXEC, above) };
+            match lifted {
+                -1 => Err(io::Error::last_os_error()),
+                lifted => Ok((target, unsafe { OwnedFd::from_raw_fd(lifted) })),
+            }
+        })
+        .collect()
+}
+
+/// Runs in the child between fork and exec, so it only makes system calls:
+/// puts each lifted source on its target fd.
+pub fn place(moves: &[(RawFd, RawFd)]) -> io::Result<()> {
+    for &(target, source) in moves {
+        if unsafe { libc::dup2(source, target) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Locks the fds `exec` opened for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    OPENED.lock().expect("Failed to lock fds!")
+}
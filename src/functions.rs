@@ -0,0 +1,57 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crate::vars;
+
+/// A function read from the file named after it in $FPATH.
+pub struct Function {
+    pub path: PathBuf,
+    pub body: Arc<str>,
+}
+
+// Functions already read, so each file is only read the first time its
+// function is called, not at startup and not every call after.
+static LOADED: Mutex<Option<HashMap<String, Arc<Function>>>> = Mutex::new(None);
+
+/// The function `name`, reading it from the first directory in $FPATH (split
+/// like $PATH) that has a file by that name, if it hasn't been already. The
+/// file holds the body, run like a script with the call's arguments as `$1`
+/// onwards.
+pub fn load(name: &str) -> Option<Arc<Function>> {
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    let mut loaded = LOADED.lock().expect("Failed to lock functions!");
+    let loaded = loaded.get_or_insert_with(HashMap::new);
+    if let Some(function) = loaded.get(name) {
+        return Some(function.clone());
+    }
+
+    let fpath = vars::get("FPATH")?;
+    let path = env::split_paths(&fpath)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())?;
+    let body = fs::read_to_string(&path).ok()?;
+    let function = Arc::new(Function {
+        path,
+        body: body.into(),
+    });
+    loaded.insert(name.to_string(), function.clone());
+    Some(function)
+}
+
+/// The function `name` if it has been read already, without looking in
+/// $FPATH for it.
+pub fn loaded(name: &str) -> Option<Arc<Function>> {
+    let loaded = LOADED.lock().expect("Failed to lock functions!");
+    loaded.as_ref()?.get(name).cloned()
+}
+
+/// Locks the functions for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    LOADED.lock().expect("Failed to lock functions!")
+}
@@ -0,0 +1,99 @@
+use std::{
+    env,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+use crate::{find_executable, options, palette, paths, quote_for_display, vars};
+
+/// Whether the fzf widgets should take over their keys. Without fzf on $PATH
+/// the keys keep their usual meaning.
+pub fn enabled() -> bool {
+    options::is_set("fzf") && find_executable("fzf").is_some()
+}
+
+// Runs fzf inline under the prompt and gives back the lines picked, or None if
+// the user backed out. With no input, fzf lists the files below the current
+// directory itself, honoring $FZF_DEFAULT_COMMAND.
+fn run(args: &[&str], input: Option<String>) -> io::Result<Option<Vec<String>>> {
+    let mut command = Command::new("fzf");
+    command
+        .args(["--height=40%", "--reverse"])
+        .args(args)
+        .stdout(Stdio::piped());
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let mut child = command.spawn()?;
+
+    // Written from a thread, so a long history can't fill the pipe while fzf
+    // is still waiting to start reading.
+    let writer = input
+        .zip(child.stdin.take())
+        .map(|(input, mut stdin)| thread::spawn(move || _ = stdin.write_all(input.as_bytes())));
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        _ = writer.join();
+    }
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let picked: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect();
+    Ok((!picked.is_empty()).then_some(picked))
+}
+
+/// Ctrl-R: a history line, searched for starting from what is typed.
+pub fn history(query: &str) -> io::Result<Option<String>> {
+    let input = palette::recent().join("\n");
+    let query = format!("--query={}", query);
+    let picked = run(&["--no-sort", "--no-multi", &query], Some(input))?;
+    Ok(picked.and_then(|lines| lines.into_iter().next()))
+}
+
+/// Ctrl-T: any number of files, quoted and ready to insert.
+pub fn files() -> io::Result<Option<String>> {
+    let picked = run(&["--multi"], None)?;
+    Ok(picked.map(|lines| {
+        lines
+            .iter()
+            .map(|line| quote_for_display(line))
+            .collect::<Vec<_>>()
+            .join(" ")
+            + " "
+    }))
+}
+
+/// Alt-C: a directory below this one to change into.
+pub fn directory() -> io::Result<Option<PathBuf>> {
+    let listing = Command::new("find")
+        .args(". -mindepth 1 -path */.* -prune -o -type d -print".split(' '))
+        .stderr(Stdio::null())
+        .output()?;
+    let input = String::from_utf8_lossy(&listing.stdout).to_string();
+    let picked = run(&["--no-multi"], Some(input))?;
+    Ok(picked
+        .and_then(|lines| lines.into_iter().next())
+        .map(PathBuf::from))
+}
+
+/// Changes into the directory like `cd` would, right from the prompt.
+pub fn change_directory(dir: &Path) -> io::Result<()> {
+    let old_pwd = paths::logical_cwd();
+    let target = paths::resolve(dir, paths::physical());
+    env::set_current_dir(&target)?;
+    let pwd = match paths::physical() || target.is_relative() {
+        true => env::current_dir()?,
+        false => target,
+    };
+    if let Ok(old_pwd) = old_pwd {
+        vars::export("OLDPWD", old_pwd.to_string_lossy().to_string());
+    }
+    vars::export("PWD", pwd.to_string_lossy().to_string());
+    Ok(())
+}
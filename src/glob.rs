@@ -0,0 +1,201 @@
+use std::{fs, path::Path};
+
+use crate::names;
+
+/// Characters with a meaning in patterns. Quoted ones reach this module
+/// backslash-escaped.
+const SPECIAL: [char; 4] = ['*', '?', '[', '\\'];
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if SPECIAL.contains(&c) || c == ']' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+// Tries to read a `[...]` class, with the `[` already consumed. Gives None if
+// it never closes, in which case the `[` is an ordinary character.
+fn class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let mut c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        if c == '\\' {
+            i += 1;
+            c = *chars.get(i)?;
+        }
+        first = false;
+        i += 1;
+
+        match (chars.get(i), chars.get(i + 1)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                ranges.push((c, end));
+                i += 2;
+            }
+            _ => ranges.push((c, c)),
+        }
+    }
+}
+
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        let token = match c {
+            '*' => Token::Star,
+            '?' => Token::Any,
+            '\\' if i < chars.len() => {
+                i += 1;
+                Token::Char(chars[i - 1])
+            }
+            '[' => match class(&chars[i..]) {
+                Some((class, len)) => {
+                    i += len;
+                    class
+                }
+                None => Token::Char('['),
+            },
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn matches_one(token: &Token, c: char) -> bool {
+    match token {
+        Token::Char(expected) => *expected == c,
+        Token::Any => true,
+        Token::Star => false,
+        Token::Class { negated, ranges } => {
+            ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+        }
+    }
+}
+
+/// Whether the pattern has anything unescaped that could match more than itself.
+pub fn has_magic(pattern: &str) -> bool {
+    tokenize(pattern)
+        .iter()
+        .any(|token| !matches!(token, Token::Char(_)))
+}
+
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let tokens = tokenize(&names::fold(pattern));
+    let name: Vec<char> = names::fold(name).chars().collect();
+
+    // The classic single-backtrack wildcard match: on a mismatch, let the most
+    // recent `*` swallow one more character and try again from there.
+    let (mut t, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        match tokens.get(t) {
+            Some(Token::Star) => {
+                star = Some((t, n));
+                t += 1;
+            }
+            Some(token) if matches_one(token, name[n]) => {
+                t += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_t, star_n)) => {
+                    t = star_t + 1;
+                    n = star_n + 1;
+                    star = Some((star_t, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[t..].iter().all(|token| *token == Token::Star)
+}
+
+fn join(base: &str, name: &str) -> String {
+    match base {
+        "" => name.to_string(),
+        base if base.ends_with('/') => format!("{}{}", base, name),
+        base => format!("{}/{}", base, name),
+    }
+}
+
+/// Expands a pattern into the paths it matches, sorted. Dotfiles only match
+/// when the pattern spells out the leading dot. Gives nothing if no path
+/// matches, and the caller decides what to do with the pattern then.
+pub fn expand(pattern: &str) -> Vec<String> {
+    let (mut found, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    for component in rest.split('/') {
+        if !has_magic(component) {
+            let literal = unescape(component);
+            found = found
+                .iter()
+                .map(|base| join(base, &literal))
+                .filter(|path| Path::new(path).symlink_metadata().is_ok())
+                .collect();
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for base in &found {
+            let Ok(entries) = fs::read_dir(if base.is_empty() { "." } else { base }) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                if matches(component, &name) {
+                    next.push(join(base, &name));
+                }
+            }
+        }
+        found = next;
+    }
+
+    found.sort();
+    found
+}
@@ -0,0 +1,104 @@
+use std::borrow::Cow;
+
+fn closing(open: char) -> char {
+    match open {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+/// Byte offsets of the quotes and brackets that pair up in a line, read the
+/// way the lexer reads it: nothing escaped, single-quoted or in a comment
+/// counts, and inside double quotes only the closing quote does.
+fn pairs(line: &str) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut brackets: Vec<(char, usize)> = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    let mut word_start = true;
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => _ = chars.next(),
+            '\'' => {
+                if let Some((j, _)) = chars.find(|(_, c)| *c == '\'') {
+                    pairs.push((i, j));
+                }
+            }
+            '"' => loop {
+                match chars.next() {
+                    Some((_, '\\')) => _ = chars.next(),
+                    Some((j, '"')) => {
+                        pairs.push((i, j));
+                        break;
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            },
+            '#' if word_start => break,
+            '(' | '[' | '{' => brackets.push((c, i)),
+            ')' | ']' | '}' => {
+                if let Some(&(open, j)) = brackets.last()
+                    && closing(open) == c
+                {
+                    brackets.pop();
+                    pairs.push((j, i));
+                }
+            }
+            _ => {}
+        }
+        word_start = c.is_whitespace() || "|;&<>".contains(c);
+    }
+    pairs
+}
+
+/// The partner of the quote or bracket under the cursor, or of the one just
+/// before it, so a closing bracket lights up its opener as it is typed.
+pub fn matching(line: &str, pos: usize) -> Option<usize> {
+    let pairs = pairs(line);
+    let partner = |at: usize| {
+        pairs.iter().find_map(|&(open, close)| match at {
+            at if at == open => Some(close),
+            at if at == close => Some(open),
+            _ => None,
+        })
+    };
+    partner(pos).or_else(|| partner(pos.checked_sub(1)?))
+}
+
+pub fn highlight(line: &str, pos: usize) -> Cow<'_, str> {
+    match matching(line, pos) {
+        Some(at) => {
+            let mut highlighted = line.to_string();
+            highlighted.replace_range(at..=at, &format!("\x1b[1;34m{}\x1b[0m", &line[at..=at]));
+            Cow::Owned(highlighted)
+        }
+        None => Cow::Borrowed(line),
+    }
+}
+
+/// What Ctrl-R is looking for, if `prompt` is the one it shows while it
+/// searches: "(reverse-i-search)`query': ", with "failed " in front when
+/// nothing matches.
+pub fn search_query(prompt: &str) -> Option<&str> {
+    prompt
+        .strip_prefix("(reverse-i-search)`")
+        .or_else(|| prompt.strip_prefix("(failed reverse-i-search)`"))?
+        .strip_suffix("': ")
+}
+
+/// Lights up the match Ctrl-R found in the line, which the cursor sits at
+/// the start of.
+pub fn search_match<'l>(line: &'l str, pos: usize, query: &str) -> Cow<'l, str> {
+    if query.is_empty() || !line.get(pos..).is_some_and(|rest| rest.starts_with(query)) {
+        return Cow::Borrowed(line);
+    }
+    let end = pos + query.len();
+    Cow::Owned(format!(
+        "{}\x1b[1;4;33m{}\x1b[0m{}",
+        &line[..pos],
+        &line[pos..end],
+        &line[end..]
+    ))
+}
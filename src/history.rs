@@ -0,0 +1,333 @@
+use std::{
+    collections::VecDeque,
+    ffi::CString,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum HistoryError {
+    #[error("{0}: event not found")]
+    EventNotFound(String),
+    #[error("{0}: bad word specifier")]
+    BadWordSpecifier(String),
+    #[error("^{0}: substitution failed")]
+    SubstitutionFailed(String),
+}
+
+/// Whether the line might refer to earlier ones, so the history is only
+/// looked at when it has to be.
+pub fn wanted(line: &str) -> bool {
+    line.contains('!') || line.starts_with('^')
+}
+
+// The words of an earlier line, like the shell would see them, but with
+// their quotes kept.
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                continue;
+            }
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None | Some('"'), '\\') => {
+                word.push(c);
+                word.extend(chars.next());
+                continue;
+            }
+            _ => {}
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// `!!`, `!n`, `!-n`, `!?text?` or `!prefix`, which has been taken off the
+// front of `rest`, along with the `!`. `n` counts from 1 like `history` does.
+fn event<'h>(spec: &str, history: &'h [String]) -> Result<&'h String, HistoryError> {
+    let not_found = || HistoryError::EventNotFound(format!("!{}", spec));
+    let found = match spec {
+        "!" => history.last(),
+        spec if let Ok(number) = spec.parse::<isize>() => match number {
+            1.. => history.get(number as usize - 1),
+            ..0 => history
+                .len()
+                .checked_sub(number.unsigned_abs())
+                .and_then(|index| history.get(index)),
+            0 => None,
+        },
+        spec => match spec.strip_prefix('?') {
+            Some(text) => {
+                let text = text.strip_suffix('?').unwrap_or(text);
+                history.iter().rev().find(|line| line.contains(text))
+            }
+            None => history.iter().rev().find(|line| line.starts_with(spec)),
+        },
+    };
+    found.ok_or_else(not_found)
+}
+
+// Reads an event designator off the front of `rest`, the `!` already gone.
+fn take_event(rest: &str) -> (&str, &str) {
+    if rest.starts_with('!') {
+        return rest.split_at(1);
+    }
+    if let Some(text) = rest.strip_prefix('?') {
+        let end = match text.find('?') {
+            Some(at) => at + 2,
+            None => rest.len(),
+        };
+        return rest.split_at(end);
+    }
+    let end = rest
+        .find(|c: char| c.is_whitespace() || matches!(c, ':' | ';' | '|' | '&' | '"' | '\''))
+        .unwrap_or(rest.len());
+    rest.split_at(end)
+}
+
+// Reads a word designator like `2`, `1-$`, `^`, `*` or `2-` off the front of
+// `rest`, the `:` already gone. Gives the range of words it picks, with `None`
+// for the last word.
+fn take_words(rest: &str) -> Option<(usize, Option<usize>, bool, &str)> {
+    fn number(rest: &str) -> Option<(Option<usize>, &str)> {
+        match rest.strip_prefix('$') {
+            Some(rest) => Some((None, rest)),
+            None => {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                let n = rest[..end].parse().ok()?;
+                Some((Some(n), &rest[end..]))
+            }
+        }
+    }
+
+    if let Some(rest) = rest.strip_prefix('*') {
+        return Some((1, None, false, rest));
+    }
+    if let Some(rest) = rest.strip_prefix('^') {
+        return Some((1, Some(1), false, rest));
+    }
+    let (start, rest) = match rest.strip_prefix('-') {
+        Some(_) => (Some(0), rest),
+        None => number(rest)?,
+    };
+    let Some(start) = start else {
+        return Some((usize::MAX, None, false, rest));
+    };
+    if let Some(rest) = rest.strip_prefix('*') {
+        return Some((start, None, false, rest));
+    }
+    let Some(rest) = rest.strip_prefix('-') else {
+        return Some((start, Some(start), false, rest));
+    };
+    match number(rest) {
+        Some((end, rest)) => Some((start, end, false, rest)),
+        // `2-` is `2-$` without the last word.
+        None => Some((start, None, true, rest)),
+    }
+}
+
+// The words an event designator and, if there is one, a word designator pick
+// out of an earlier line. Gives back what is left of `rest`.
+fn reference<'r>(rest: &'r str, history: &[String]) -> Result<(String, &'r str), HistoryError> {
+    // `!$`, `!^` and `!*` are short for `!!:$` and the like.
+    let shorthand = rest.starts_with(['$', '^', '*']);
+    let (spec, rest) = match shorthand {
+        true => ("!", rest),
+        false => take_event(rest),
+    };
+    let line = event(spec, history)?;
+    let designator = match shorthand {
+        true => Some(rest),
+        false => rest.strip_prefix(':'),
+    };
+    let Some(designator) = designator else {
+        return Ok((line.clone(), rest));
+    };
+
+    let bad = || HistoryError::BadWordSpecifier(format!("!{}:{}", spec, designator));
+    let (start, end, drop_last, after) = take_words(designator).ok_or_else(bad)?;
+    let words = words(line);
+    let last = words.len().checked_sub(1).ok_or_else(bad)?;
+    let start = if start == usize::MAX { last } else { start };
+    let end = match end {
+        Some(end) => end,
+        None if drop_last => last.checked_sub(1).ok_or_else(bad)?,
+        None => last,
+    };
+    // `!!:*` on a line with no arguments is just empty.
+    if start > end && start == 1 && end == 0 {
+        return Ok((String::new(), after));
+    }
+    if start > end || end > last {
+        return Err(bad());
+    }
+    Ok((words[start..=end].join(" "), after))
+}
+
+// `^old^new^`, which reruns the last line with the first `old` in it
+// replaced.
+fn quick_substitution(line: &str, history: &[String]) -> Result<String, HistoryError> {
+    let body = &line[1..];
+    let mut parts = body.splitn(3, '^');
+    let old = parts.next().unwrap_or_default();
+    let new = parts.next().unwrap_or_default();
+    let rest = parts.next().unwrap_or_default();
+
+    let failed = || HistoryError::SubstitutionFailed(body.to_string());
+    let last = history.last().ok_or_else(failed)?;
+    if old.is_empty() || !last.contains(old) {
+        return Err(failed());
+    }
+    Ok(last.replacen(old, new, 1) + rest)
+}
+
+/// Expands references to earlier lines: `!!`, `!n`, `!-n`, `!prefix` and
+/// `!?text?` for a whole line, each optionally followed by a word designator
+/// like `:2`, `:1-$` or `:*`, `!$` for the last word of the last line, and
+/// `^old^new^` to rerun the last line with a change.
+/// `history` holds the earlier lines, oldest first. Gives None if the line
+/// refers to none of them.
+///
+/// Like in bash, single quotes and a backslash keep a `!` as it is, as does
+/// whitespace, `=` or `(` after it. `!!:out` is left for the capture option.
+pub fn expand(line: &str, history: &[String]) -> Result<Option<String>, HistoryError> {
+    if line.starts_with('^') {
+        return quick_substitution(line, history).map(Some);
+    }
+
+    let mut expanded = String::new();
+    let mut changed = false;
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let after = &rest[c.len_utf8()..];
+        let literal = match c {
+            '\'' if !in_double => {
+                in_single = !in_single;
+                true
+            }
+            '"' if !in_single => {
+                in_double = !in_double;
+                true
+            }
+            '!' if after.starts_with("!:out") => {
+                expanded.push_str("!!:out");
+                rest = &after["!:out".len()..];
+                continue;
+            }
+            '\\' if !in_single => {
+                expanded.push(c);
+                let escaped = after.chars().next();
+                expanded.extend(escaped);
+                rest = &after[escaped.map_or(0, char::len_utf8)..];
+                continue;
+            }
+            '!' => {
+                in_single
+                    || after.is_empty()
+                    || after.starts_with(|c: char| c.is_whitespace() || matches!(c, '=' | '('))
+                    || expanded.ends_with('$')
+            }
+            _ => true,
+        };
+        if literal {
+            expanded.push(c);
+            rest = after;
+            continue;
+        }
+
+        let (words, after) = reference(after, history)?;
+        expanded.push_str(&words);
+        changed = true;
+        rest = after;
+    }
+    Ok(changed.then_some(expanded))
+}
+
+// When each entry in rustyline's history went in, in the same order, as
+// seconds since the epoch. Entries read from a file without one have None.
+static TIMES: Mutex<VecDeque<Option<i64>>> = Mutex::new(VecDeque::new());
+
+/// The time to stamp an entry going in now with.
+pub fn now() -> Option<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    i64::try_from(now.as_secs()).ok()
+}
+
+/// Notes when the entry just added to the history went in. `len` is how many
+/// entries the history holds now, which also lets go of the times of any it
+/// dropped to make room.
+pub fn stamp(time: Option<i64>, len: usize) {
+    let mut times = TIMES.lock().expect("Failed to lock history times!");
+    times.push_back(time);
+    while times.len() > len {
+        times.pop_front();
+    }
+    while times.len() < len {
+        times.push_front(None);
+    }
+}
+
+/// When the entry at `index`, counting from 0, went in, if that is known.
+pub fn time(index: usize) -> Option<i64> {
+    let times = TIMES.lock().expect("Failed to lock history times!");
+    times.get(index).copied().flatten()
+}
+
+pub fn clear_times() {
+    TIMES.lock().expect("Failed to lock history times!").clear();
+}
+
+/// A history file line like `#1700000000`, which holds when the entry after
+/// it went in, like bash writes them.
+pub fn parse_time(line: &str) -> Option<i64> {
+    let digits = line.strip_prefix('#')?;
+    match !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        true => digits.parse().ok(),
+        false => None,
+    }
+}
+
+/// A time in local time, formatted like strftime(3) does, for
+/// $HISTTIMEFORMAT.
+pub fn format_time(time: i64, format: &str) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let time = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return String::new();
+    }
+    let mut formatted = [0u8; 256];
+    let len = unsafe {
+        libc::strftime(
+            formatted.as_mut_ptr().cast(),
+            formatted.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&formatted[..len]).to_string()
+}
+
+/// Locks the history's times for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    TIMES.lock().expect("Failed to lock history times!")
+}
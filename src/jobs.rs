@@ -0,0 +1,730 @@
+use std::{
+    fs,
+    io::{self, IsTerminal, PipeWriter, Read, Write, pipe},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rustyline::ExternalPrinter;
+use thiserror::Error;
+
+use crate::{preview, signals, summary};
+
+/// Prints above the line being edited, which the editor then redraws.
+pub type Printer = Box<dyn ExternalPrinter + Send>;
+
+/// Tells one job from every other for as long as the shell runs, unlike its
+/// number, which a later job gets once this one is gone.
+pub type JobId = u64;
+
+/// Only this much of what a job prints is held with `bufferjobs`; the rest is
+/// counted and dropped.
+const HELD_LIMIT: usize = 1024 * 1024;
+
+/// How long a finished job's output gets to drain before it is shown. Past
+/// that something it left behind is still writing, and that is its own.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How often a job being waited for is looked at. Only /proc says when it
+/// stops, so there is nothing to be woken by.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+// The job Ctrl-Z last stopped at the prompt, and when.
+static LAST_STOPPED: Mutex<Option<(JobId, Instant)>> = Mutex::new(None);
+
+/// How soon after Ctrl-Z stopped the pipeline at the prompt a second Ctrl-Z
+/// carries it on in the background.
+pub const DOUBLE_STOP: Duration = Duration::from_secs(1);
+
+// Counts starts, stops and resumes, so the jobs most recently through one
+// are `%+` and `%-`.
+static LAST_TOUCHED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Running,
+    Stopped,
+    Done(i32),
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SpecError {
+    #[error("no such job")]
+    NoSuchJob,
+    #[error("ambiguous job spec")]
+    Ambiguous,
+}
+
+/// A command started with `&`, or a pipeline run at the prompt, which is
+/// only shown as a job once Ctrl-Z stops it.
+struct Job {
+    id: JobId,
+    /// What `%N` and `jobs` call it, once it is shown.
+    number: usize,
+    /// Whether it is the pipeline the prompt is waiting for.
+    foreground: bool,
+    /// The command as typed, without the `&`.
+    command: String,
+    /// The processes it started, in order.
+    pids: Vec<u32>,
+    /// The process group its processes go in, apart from the terminal's so
+    /// Ctrl-C at the prompt leaves them be.
+    pgid: Option<i32>,
+    /// The status it finished with, once it has.
+    status: Option<i32>,
+    /// What it printed and nobody has seen yet, with `bufferjobs`.
+    held: Vec<u8>,
+    /// How much more it printed past HELD_LIMIT.
+    dropped: usize,
+    /// Told once everything writing to the held output has closed it.
+    holding: Option<Receiver<()>>,
+    /// When it was last started, stopped or resumed.
+    touched: u64,
+    /// When it started, and when it finished once it has, for `jobs -l`.
+    started: Instant,
+    finished: Option<Instant>,
+    /// Whether it has been said to have stopped, since it was last resumed.
+    told_stopped: bool,
+}
+
+// The fields /proc gives for a process from its state on, which proc(5)
+// numbers from 3, or None once it is gone.
+fn process_stat(pid: u32) -> Option<Vec<String>> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The name before them is in parentheses and may hold anything at all.
+    let fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    Some(fields.map(str::to_string).collect())
+}
+
+// The state letter /proc gives a process, like R, S or T.
+fn process_state(pid: u32) -> Option<char> {
+    process_stat(pid)?.first()?.chars().next()
+}
+
+// The CPU time a process has had and the memory it has resident.
+fn process_usage(pid: u32) -> Option<(Duration, u64)> {
+    let stat = process_stat(pid)?;
+    let field = |number: usize| stat.get(number - 3)?.parse::<u64>().ok();
+    let ticks = field(14)? + field(15)?;
+    let (hz, page) = unsafe {
+        (
+            libc::sysconf(libc::_SC_CLK_TCK) as u64,
+            libc::sysconf(libc::_SC_PAGESIZE) as u64,
+        )
+    };
+    Some((
+        Duration::from_secs_f64(ticks as f64 / hz.max(1) as f64),
+        field(24)? * page,
+    ))
+}
+
+impl Job {
+    fn state(&self) -> State {
+        match self.status {
+            Some(status) => State::Done(status),
+            None if self.pids.iter().any(|&pid| process_state(pid) == Some('T')) => State::Stopped,
+            None => State::Running,
+        }
+    }
+
+    // The state as `jobs` words it, and what goes after the command.
+    fn state_text(&self) -> (String, &'static str) {
+        match self.state() {
+            State::Running => ("Running".to_string(), " &"),
+            State::Stopped => ("Stopped".to_string(), ""),
+            State::Done(0) => ("Done".to_string(), ""),
+            State::Done(status) => (format!("Exit {}", status), ""),
+        }
+    }
+
+    // How `jobs` and the notice at the prompt show it, like bash does.
+    fn describe(&self, marker: char) -> String {
+        let (state, suffix) = self.state_text();
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            self.number, marker, state, self.command, suffix
+        )
+    }
+
+    // How `jobs -l` shows it: with the process group leader's pid where bash
+    // puts it, then how long it has run, and between the processes it has
+    // left, their share of a CPU over that time and their resident memory.
+    fn describe_long(&self, marker: char) -> String {
+        let (state, suffix) = self.state_text();
+        let pid = self
+            .pgid
+            .map(|pgid| pgid as u32)
+            .or(self.pids.first().copied());
+        let elapsed = self.finished.unwrap_or_else(Instant::now) - self.started;
+        let usage = self
+            .pids
+            .iter()
+            .filter_map(|&pid| process_usage(pid))
+            .reduce(|(cpu, rss), (more_cpu, more_rss)| (cpu + more_cpu, rss + more_rss));
+        let cpu = usage.map(|(cpu, _)| {
+            format!(
+                "{:.1}%",
+                100.0 * cpu.as_secs_f64() / elapsed.as_secs_f64().max(0.001)
+            )
+        });
+        let rss = usage.map(|(_, rss)| preview::size(rss));
+        format!(
+            "[{}]{} {:>7} {:<10} {:>7} {:>6} {:>10}  {}{}",
+            self.number,
+            marker,
+            pid.map_or("-".to_string(), |pid| pid.to_string()),
+            state,
+            summary::format_duration(elapsed),
+            cpu.unwrap_or_else(|| "-".to_string()),
+            rss.unwrap_or_else(|| "-".to_string()),
+            self.command,
+            suffix
+        )
+    }
+}
+
+static JOBS: Mutex<Vec<Job>> = Mutex::new(Vec::new());
+
+// Counts the lines read at the prompt, and which one leaving was last held
+// back on (0 for none), since leaving on the very next one goes ahead.
+static LINES: AtomicU64 = AtomicU64::new(0);
+static EXIT_HELD_ON: AtomicU64 = AtomicU64::new(0);
+
+static PRINTER: Mutex<Option<Printer>> = Mutex::new(None);
+
+/// Whether a job's output has been relayed, so the prompt needs a printer.
+static RELAYED: AtomicBool = AtomicBool::new(false);
+
+// The jobs `jobs` and job specs know about: all but the pipeline the prompt
+// is waiting for.
+fn shown(jobs: &[Job]) -> impl Iterator<Item = &Job> {
+    jobs.iter().filter(|job| !job.foreground)
+}
+
+fn with_job<T>(id: JobId, f: impl FnOnce(&mut Job) -> T) -> Option<T> {
+    let mut jobs = JOBS.lock().expect("Failed to lock jobs!");
+    jobs.iter_mut().find(|job| job.id == id).map(f)
+}
+
+// The current job (`%+`) and the previous one (`%-`): the two most recently
+// started, stopped or resumed, stopped ones first as in bash, since those
+// are waiting on the user.
+fn current_and_previous(jobs: &[Job]) -> (Option<JobId>, Option<JobId>) {
+    let mut recent = shown(jobs)
+        .map(|job| (job.state() == State::Stopped, job.touched, job.id))
+        .collect::<Vec<_>>();
+    recent.sort_by_key(|&(stopped, touched, _)| std::cmp::Reverse((stopped, touched)));
+    (
+        recent.first().map(|&(_, _, id)| id),
+        recent.get(1).map(|&(_, _, id)| id),
+    )
+}
+
+// Each job described, with `+` and `-` on the current and previous ones,
+// and with `long` as `jobs -l` has it.
+fn describe_all<'a>(jobs: &[Job], which: impl Iterator<Item = &'a Job>, long: bool) -> Vec<String> {
+    let (current, previous) = current_and_previous(jobs);
+    which
+        .map(|job| {
+            let marker = match Some(job.id) {
+                id if id == current => '+',
+                id if id == previous => '-',
+                _ => ' ',
+            };
+            match long {
+                true => job.describe_long(marker),
+                false => job.describe(marker),
+            }
+        })
+        .collect()
+}
+
+/// Adds a job for `command`, numbered one past the highest still around,
+/// and makes it the current one.
+pub fn start(command: String) -> JobId {
+    add(command, false)
+}
+
+/// Adds a job for the pipeline `command` the prompt is about to wait for.
+/// It is left out of `jobs` until `stopped_in_foreground`, and forgotten
+/// once it finishes.
+pub fn start_foreground(command: String) -> JobId {
+    add(command, true)
+}
+
+fn add(command: String, foreground: bool) -> JobId {
+    let mut jobs = JOBS.lock().expect("Failed to lock jobs!");
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let number = match foreground {
+        true => 0,
+        false => next_number(&jobs),
+    };
+    jobs.push(Job {
+        id,
+        number,
+        foreground,
+        command,
+        pids: Vec::new(),
+        pgid: None,
+        status: None,
+        held: Vec::new(),
+        dropped: 0,
+        holding: None,
+        touched: LAST_TOUCHED.fetch_add(1, Ordering::SeqCst) + 1,
+        started: Instant::now(),
+        finished: None,
+        told_stopped: false,
+    });
+    id
+}
+
+fn next_number(jobs: &[Job]) -> usize {
+    shown(jobs).map(|job| job.number).max().unwrap_or(0) + 1
+}
+
+/// Whether a job is the pipeline the prompt is waiting for, which the
+/// terminal goes to.
+pub fn in_foreground(id: JobId) -> bool {
+    with_job(id, |job| job.foreground).unwrap_or(false)
+}
+
+/// The pipeline the prompt is waiting for, if it started the process `pid`.
+pub fn foreground_with(pid: u32) -> Option<JobId> {
+    let jobs = JOBS.lock().expect("Failed to lock jobs!");
+    jobs.iter()
+        .find(|job| job.foreground && job.pids.contains(&pid))
+        .map(|job| job.id)
+}
+
+/// Shows the pipeline the prompt was waiting for as a job, once Ctrl-Z has
+/// stopped it, and makes it the current one.
+pub fn stopped_in_foreground(id: JobId) {
+    let mut jobs = JOBS.lock().expect("Failed to lock jobs!");
+    let number = next_number(&jobs);
+    if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+        job.foreground = false;
+        job.number = number;
+        job.touched = LAST_TOUCHED.fetch_add(1, Ordering::SeqCst) + 1;
+    }
+    *LAST_STOPPED.lock().expect("Failed to lock the last stop!") = Some((id, Instant::now()));
+}
+
+/// The job Ctrl-Z stopped at the prompt, if that was no longer than
+/// `within` ago and it is still stopped. Only asked once per stop.
+pub fn just_stopped(within: Duration) -> Option<JobId> {
+    let (id, when) = LAST_STOPPED
+        .lock()
+        .expect("Failed to lock the last stop!")
+        .take()?;
+    (when.elapsed() <= within && state(id) == Some(State::Stopped)).then_some(id)
+}
+
+/// Waits for the pipeline the prompt is waiting for to send what it came to
+/// over `done`. Gives None if Ctrl-Z stops it first, leaving it to finish
+/// whenever it is continued.
+pub fn wait_in_foreground<T>(id: JobId, done: &Receiver<T>) -> Option<T> {
+    loop {
+        match done.recv_timeout(POLL_INTERVAL) {
+            Ok(value) => return Some(value),
+            Err(mpsc::RecvTimeoutError::Timeout) if state(id) == Some(State::Stopped) => {
+                return None;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => panic!("Failed waiting for the pipeline!"),
+        }
+    }
+}
+
+/// Makes a job the current one, as it stops or is brought back.
+pub fn touch(id: JobId) {
+    with_job(id, |job| {
+        job.touched = LAST_TOUCHED.fetch_add(1, Ordering::SeqCst) + 1;
+    });
+}
+
+/// What `%N` calls a job.
+pub fn number(id: JobId) -> Option<usize> {
+    with_job(id, |job| job.number)
+}
+
+/// A job as `jobs`, or with `long` `jobs -l`, shows it.
+pub fn describe(id: JobId, long: bool) -> Option<String> {
+    let jobs = JOBS.lock().expect("Failed to lock jobs!");
+    let job = jobs.iter().filter(|job| job.id == id);
+    describe_all(&jobs, job, long).pop()
+}
+
+/// A job's command as typed.
+pub fn command(id: JobId) -> Option<String> {
+    with_job(id, |job| job.command.clone())
+}
+
+pub fn state(id: JobId) -> Option<State> {
+    with_job(id, |job| job.state())
+}
+
+/// The process group a job's processes are in now, for handing it the
+/// terminal or a signal. Jobs that only ran builtins have none.
+pub fn pgid(id: JobId) -> Option<i32> {
+    with_job(id, |job| job.pgid).flatten()
+}
+
+/// The process group a job's next process should go in: the job's own while
+/// any of it is left, or a new one (0) led by that process.
+pub fn group(id: JobId) -> i32 {
+    pgid(id)
+        .filter(|&pgid| unsafe { libc::kill(-pgid, 0) } == 0)
+        .unwrap_or(0)
+}
+
+/// Records a process the job started, in the group `group` gave it.
+pub fn spawned(id: JobId, pid: u32) {
+    with_job(id, |job| {
+        job.pids.push(pid);
+        // A group outlives its leader only as long as something is in it.
+        if job
+            .pgid
+            .is_none_or(|pgid| unsafe { libc::kill(-pgid, 0) } != 0)
+        {
+            job.pgid = Some(pid as i32);
+        }
+    });
+}
+
+/// The job that started the process `pid`, for `wait PID`.
+pub fn with_pid(pid: u32) -> Option<JobId> {
+    let jobs = JOBS.lock().expect("Failed to lock jobs!");
+    shown(&jobs)
+        .find(|job| job.pids.contains(&pid))
+        .map(|job| job.id)
+}
+
+pub fn finish(id: JobId, status: i32) {
+    with_job(id, |job| {
+        job.status = Some(status);
+        job.finished = Some(Instant::now());
+    });
+}
+
+/// Takes a job out of the table, for `disown`, or once `fg` or `wait` has
+/// seen it through and there is nothing left to tell.
+pub fn forget(id: JobId) {
+    JOBS.lock()
+        .expect("Failed to lock jobs!")
+        .retain(|job| job.id != id);
+}
+
+/// Counts a line read at the prompt, or Ctrl-D there.
+pub fn count_line() {
+    LINES.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Whether leaving the shell should wait, for the reminder that there are
+/// stopped jobs. As in bash, that is only the first try: trying again right
+/// after goes ahead.
+pub fn hold_exit() -> bool {
+    let stopped =
+        shown(&JOBS.lock().expect("Failed to lock jobs!")).any(|job| job.state() == State::Stopped);
+    if !stopped {
+        return false;
+    }
+    let line = LINES.load(Ordering::SeqCst);
+    let held_on = EXIT_HELD_ON.swap(line, Ordering::SeqCst);
+    held_on == 0 || held_on + 1 < line
+}
+
+/// Ends the stopped jobs as the shell leaves, since nothing could continue
+/// them after, as bash does.
+pub fn end_stopped() {
+    for id in ids() {
+        if state(id) == Some(State::Stopped) {
+            _ = signal(id, libc::SIGTERM);
+        }
+    }
+}
+
+/// The number of the one stopped job, when there is just the one.
+pub fn only_stopped() -> Option<usize> {
+    let jobs = JOBS.lock().expect("Failed to lock jobs!");
+    match shown(&jobs)
+        .filter(|job| job.state() == State::Stopped)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [job] => Some(job.number),
+        _ => None,
+    }
+}
+
+/// Every job, oldest first.
+pub fn ids() -> Vec<JobId> {
+    let jobs = JOBS.lock().expect("Failed to lock jobs!");
+    shown(&jobs).map(|job| job.id).collect()
+}
+
+/// The job a spec names: `%%`, `%+` or `%` for the current job, `%-` for the
+/// previous one, `%N` by its number, `%?text` for the only one whose command
+/// has `text` in it and `%text` for the only one whose command starts so.
+/// With one job, `%-` is that one too.
+pub fn resolve(spec: &str) -> Result<JobId, SpecError> {
+    let jobs = JOBS.lock().expect("Failed to lock jobs!");
+    let (current, previous) = current_and_previous(&jobs);
+    let Some(rest) = spec.strip_prefix('%') else {
+        return Err(SpecError::NoSuchJob);
+    };
+    let found = match rest {
+        "" | "%" | "+" => return current.ok_or(SpecError::NoSuchJob),
+        "-" => return previous.or(current).ok_or(SpecError::NoSuchJob),
+        rest => match (rest.parse::<usize>(), rest.strip_prefix('?')) {
+            (Ok(number), _) => shown(&jobs)
+                .filter(|job| job.number == number)
+                .collect::<Vec<_>>(),
+            (_, Some(text)) => shown(&jobs)
+                .filter(|job| job.command.contains(text))
+                .collect(),
+            _ => shown(&jobs)
+                .filter(|job| job.command.starts_with(rest))
+                .collect(),
+        },
+    };
+    match found.as_slice() {
+        [] => Err(SpecError::NoSuchJob),
+        [job] => Ok(job.id),
+        _ => Err(SpecError::Ambiguous),
+    }
+}
+
+/// Continues a job's processes, if they were stopped, and makes it the
+/// current job.
+pub fn resume(id: JobId) -> io::Result<()> {
+    touch(id);
+    with_job(id, |job| job.told_stopped = false);
+    if let Some(pgid) = pgid(id)
+        && unsafe { libc::killpg(pgid, libc::SIGCONT) } != 0
+    {
+        let err = io::Error::last_os_error();
+        // Nothing left in the group to continue.
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Sends a signal to a job's process group, where a job started with `&`
+/// has the shell running the rest of it too. A stopped job is continued to
+/// get it, as in bash.
+pub fn signal(id: JobId, signal: i32) -> io::Result<()> {
+    let Some((pgid, stopped)) = with_job(id, |job| (job.pgid, job.state() == State::Stopped))
+    else {
+        return Err(io::Error::from_raw_os_error(libc::ESRCH));
+    };
+    let Some(pgid) = pgid else {
+        return Ok(());
+    };
+    if unsafe { libc::killpg(pgid, signal) } != 0 {
+        let err = io::Error::last_os_error();
+        // Nothing of it left running, but then there is nothing to end.
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            return Err(err);
+        }
+    }
+    if stopped && matches!(signal, libc::SIGTERM | libc::SIGHUP) {
+        unsafe { libc::killpg(pgid, libc::SIGCONT) };
+    }
+    Ok(())
+}
+
+/// Waits until a job is done or, with `stops`, has stopped, and gives the
+/// state it got to. None if it is gone from the table, or Ctrl-C broke off
+/// the wait.
+pub fn wait(id: JobId, stops: bool) -> Option<State> {
+    loop {
+        match state(id)? {
+            State::Running => {}
+            State::Stopped if !stops => {}
+            state => return Some(state),
+        }
+        if signals::interrupted() {
+            return None;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Every job, described for `jobs` or `jobs -l`. `forget` is whether the
+/// user has been told about the finished ones now, so they can go.
+pub fn list(forget: bool, long: bool) -> Vec<String> {
+    let mut jobs = JOBS.lock().expect("Failed to lock jobs!");
+    let lines = describe_all(&jobs, shown(&jobs), long);
+    if forget {
+        jobs.retain(|job| job.status.is_none());
+    }
+    lines
+}
+
+/// The jobs that finished or stopped since the last call, described for the
+/// notice before the prompt. The finished ones are taken out of the table.
+pub fn take_changed() -> Vec<String> {
+    let mut jobs = JOBS.lock().expect("Failed to lock jobs!");
+    let stopped = shown(&jobs)
+        .filter(|job| !job.told_stopped && job.state() == State::Stopped)
+        .map(|job| job.id)
+        .collect::<Vec<_>>();
+    let lines = describe_all(
+        &jobs,
+        shown(&jobs).filter(|job| job.status.is_some() || stopped.contains(&job.id)),
+        false,
+    );
+    for job in jobs.iter_mut().filter(|job| stopped.contains(&job.id)) {
+        job.told_stopped = true;
+    }
+    jobs.retain(|job| job.status.is_none());
+    lines
+}
+
+/// Notes that a job has been said to have stopped, so the notice before the
+/// prompt leaves it out.
+pub fn told_stopped(id: JobId) {
+    with_job(id, |job| job.told_stopped = true);
+}
+
+/// A pipe for a job to write to in place of the terminal, with `bufferjobs`.
+/// What comes out is held for `jobs -o` or until the job finishes.
+pub fn hold(id: JobId) -> io::Result<PipeWriter> {
+    let (mut reader, writer) = pipe()?;
+    let (done, holding) = mpsc::channel();
+    with_job(id, |job| job.holding = Some(holding));
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let kept = with_job(id, |job| {
+                let room = HELD_LIMIT.saturating_sub(job.held.len());
+                job.held.extend_from_slice(&buf[..n.min(room)]);
+                job.dropped += n.saturating_sub(room);
+            });
+            if kept.is_none() {
+                break;
+            }
+        }
+        _ = done.send(());
+    });
+    Ok(writer)
+}
+
+/// Takes what a job has printed and not been shown yet, saying how much of
+/// it did not fit. None if there is no such job.
+pub fn take_held(id: JobId) -> Option<Vec<u8>> {
+    with_job(id, |job| {
+        let mut held = std::mem::take(&mut job.held);
+        if job.dropped > 0 {
+            held.extend_from_slice(
+                format!("[{} more bytes of output dropped]\n", job.dropped).as_bytes(),
+            );
+            job.dropped = 0;
+        }
+        held
+    })
+}
+
+/// Shows what a job that has finished held back, once what it started has
+/// stopped writing.
+pub fn release(id: JobId) {
+    let Some(holding) = with_job(id, |job| job.holding.take()).flatten() else {
+        return;
+    };
+    _ = holding.recv_timeout(DRAIN_TIMEOUT);
+    if let Some(held) = take_held(id) {
+        print(&held);
+    }
+}
+
+/// Whether a job has output to relay and nothing to print it with yet.
+pub fn wants_printer() -> bool {
+    RELAYED.load(Ordering::Relaxed)
+        && PRINTER
+            .lock()
+            .expect("Failed to lock the printer!")
+            .is_none()
+}
+
+/// Sets where background jobs' output goes while a line is being edited.
+pub fn set_printer(printer: Printer) {
+    *PRINTER.lock().expect("Failed to lock the printer!") = Some(printer);
+}
+
+/// Lets go of the printer in a forked copy of the shell, whose prompt is
+/// the parent's. Its fds went with the other threads, so it is leaked rather
+/// than closing them again.
+pub fn drop_printer() {
+    if let Some(printer) = PRINTER.lock().expect("Failed to lock the printer!").take() {
+        std::mem::forget(printer);
+    }
+}
+
+fn print(text: &[u8]) {
+    if text.is_empty() {
+        return;
+    }
+    let text = String::from_utf8_lossy(text).to_string();
+    let mut printer = PRINTER.lock().expect("Failed to lock the printer!");
+    let printed = printer
+        .as_mut()
+        .is_some_and(|printer| printer.print(text.clone()).is_ok());
+    if !printed {
+        _ = io::stdout().write_all(text.as_bytes());
+        _ = io::stdout().flush();
+    }
+}
+
+/// A pipe for a background job to write to in place of the terminal, when
+/// there is a prompt it could land in the middle of. What comes out is
+/// printed a line at a time above the line being edited, which is drawn
+/// again below it, rather than over the top of it.
+/// Until the next prompt makes the printer, it goes straight out.
+pub fn relay() -> io::Result<Option<PipeWriter>> {
+    // Without a terminal on both ends there is no prompt to keep clear.
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Ok(None);
+    }
+    RELAYED.store(true, Ordering::Relaxed);
+    let (mut reader, writer) = pipe()?;
+    thread::spawn(move || {
+        let mut pending = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => pending.extend_from_slice(&buf[..n]),
+            }
+            // Half a line would be finished off with a newline of its own.
+            if let Some(end) = pending.iter().rposition(|&b| b == b'\n') {
+                let rest = pending.split_off(end + 1);
+                print(&pending);
+                pending = rest;
+            }
+        }
+        print(&pending);
+    });
+    Ok(Some(writer))
+}
+
+/// Locks the job table and the printer for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    (
+        JOBS.lock().expect("Failed to lock jobs!"),
+        LAST_STOPPED.lock().expect("Failed to lock the last stop!"),
+        PRINTER.lock().expect("Failed to lock the printer!"),
+    )
+}
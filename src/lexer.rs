@@ -0,0 +1,513 @@
+use std::{fmt, iter::Peekable, mem, str::Chars};
+
+use thiserror::Error;
+
+/// A run of characters that shares the same quoting, which later decides what
+/// expansions are allowed to touch it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    Bare(String),
+    Double(String),
+    /// Single-quoted or backslash-escaped text.
+    Literal(String),
+    /// The command inside `<(...)`, whose output is read through a path.
+    ProcessIn(String),
+    /// The command inside `>(...)`, whose input is written through a path.
+    ProcessOut(String),
+}
+
+impl WordPart {
+    pub fn text(&self) -> &str {
+        match self {
+            WordPart::Bare(s)
+            | WordPart::Double(s)
+            | WordPart::Literal(s)
+            | WordPart::ProcessIn(s)
+            | WordPart::ProcessOut(s) => s,
+        }
+    }
+
+    fn text_mut(&mut self) -> &mut String {
+        match self {
+            WordPart::Bare(s)
+            | WordPart::Double(s)
+            | WordPart::Literal(s)
+            | WordPart::ProcessIn(s)
+            | WordPart::ProcessOut(s) => s,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Word(pub Vec<WordPart>);
+
+impl Word {
+    /// The word after quote removal.
+    pub fn text(&self) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                WordPart::ProcessIn(command) => format!("<({})", command),
+                WordPart::ProcessOut(command) => format!(">({})", command),
+                part => part.text().to_string(),
+            })
+            .collect()
+    }
+
+    /// The word as it was typed, with its quoting put back, so it is clear
+    /// which parts were quoted and how.
+    pub fn source(&self) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                WordPart::Bare(text) => text.clone(),
+                WordPart::Double(text) => format!("\"{}\"", text),
+                WordPart::Literal(text) => single_quoted(text),
+                WordPart::ProcessIn(command) => format!("<({})", command),
+                WordPart::ProcessOut(command) => format!(">({})", command),
+            })
+            .collect()
+    }
+
+    /// The word's text, but only if none of it was quoted or escaped.
+    pub fn bare(&self) -> Option<&str> {
+        match self.0.as_slice() {
+            [WordPart::Bare(s)] => Some(s),
+            _ => None,
+        }
+    }
+
+    fn push(&mut self, c: char, quoting: fn(String) -> WordPart) {
+        let mut part = quoting(String::new());
+        match self.0.last_mut() {
+            Some(last) if mem::discriminant(last) == mem::discriminant(&part) => {
+                last.text_mut().push(c)
+            }
+            _ => {
+                part.text_mut().push(c);
+                self.0.push(part);
+            }
+        }
+    }
+
+    fn open(&mut self, quoting: fn(String) -> WordPart) {
+        self.0.push(quoting(String::new()));
+    }
+}
+
+/// `text` in single quotes, which it reads back as. A quote inside has to
+/// close them, go in escaped and open them again.
+pub fn single_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// `text` in double quotes, which it reads back as, with the characters a
+/// backslash escapes inside them escaped.
+pub fn double_quoted(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    Pipe,
+    Semicolon,
+    /// `;;`, which ends an arm of a `case`.
+    DoubleSemicolon,
+    And,
+    Or,
+    /// A lone `&`, which runs what comes before it in the background.
+    Background,
+    /// `(`, which opens a subshell.
+    LParen,
+    RParen,
+    /// A here-document's body, which stands in for the `<<` operator.
+    HereDoc(Word),
+    /// `((expr))`, with the expression inside.
+    Arith(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word.text()),
+            Token::Pipe => write!(f, "|"),
+            Token::Semicolon => write!(f, ";"),
+            Token::DoubleSemicolon => write!(f, ";;"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Background => write!(f, "&"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::HereDoc(_) => write!(f, "<<"),
+            Token::Arith(expr) => write!(f, "(({}))", expr),
+        }
+    }
+}
+
+impl Token {
+    /// The token as it was typed, quotes and all.
+    pub fn source(&self) -> String {
+        match self {
+            Token::Word(word) => word.source(),
+            token => token.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum LexError {
+    #[error("unexpected EOF while looking for matching `{0}'")]
+    UnterminatedQuote(char),
+    #[error("unexpected EOF while looking for matching `)'")]
+    UnterminatedSubstitution,
+    #[error("unexpected EOF while looking for matching `}}'")]
+    UnterminatedParameter,
+    #[error("here-document delimited by end-of-file (wanted `{0}')")]
+    UnterminatedHereDoc(String),
+    #[error("syntax error near unexpected token `newline'")]
+    MissingDelimiter,
+    #[error("unexpected EOF after `\\'")]
+    TrailingBackslash,
+}
+
+/// Reads up to the `close` that matches an `open` which has been consumed, as
+/// in `<(...)` or `${...}`. Quoted brackets don't count.
+pub fn enclosed(chars: &mut Peekable<Chars>, open: char, close: char) -> Option<String> {
+    let mut body = String::new();
+    let mut depth = 1;
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\\') | (Some('"'), '\\') => {
+                body.push(c);
+                body.extend(chars.next());
+                continue;
+            }
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, c) if c == open => depth += 1,
+            (None, c) if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(body);
+                }
+            }
+            _ => {}
+        }
+        body.push(c);
+    }
+
+    None
+}
+
+// Reads up to the `))` ending a `((` that has been consumed. Gives None if the
+// parentheses close some other way, as in `((cd a); ls)`, which is two
+// subshells instead.
+fn arithmetic(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut body = String::new();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' => return chars.next_if_eq(&')').map(|_| body),
+            _ => {}
+        }
+        body.push(c);
+    }
+    None
+}
+
+// `${...}` stays in one piece, spaces and quotes in it included, for the
+// expansion to take apart.
+fn parameter(
+    chars: &mut Peekable<Chars>,
+    word: &mut Word,
+    quoting: fn(String) -> WordPart,
+) -> Result<(), LexError> {
+    let body = enclosed(chars, '{', '}').ok_or(LexError::UnterminatedParameter)?;
+    for c in format!("${{{}}}", body).chars() {
+        word.push(c, quoting);
+    }
+    Ok(())
+}
+
+// Reads one here-document body, up to a line holding just the delimiter. A
+// quoted delimiter keeps the body literal, otherwise it expands like a
+// double-quoted string.
+fn here_document(
+    chars: &mut Peekable<Chars>,
+    delimiter: &Word,
+    strip_tabs: bool,
+) -> Result<Word, LexError> {
+    let quoted = delimiter
+        .0
+        .iter()
+        .any(|part| !matches!(part, WordPart::Bare(_)));
+    let delimiter = delimiter.text();
+    let mut body = Word::default();
+
+    loop {
+        let mut line = String::new();
+        let mut ended = false;
+        for c in chars.by_ref() {
+            if c == '\n' {
+                ended = true;
+                break;
+            }
+            line.push(c);
+        }
+
+        let line = match strip_tabs {
+            true => line.trim_start_matches('\t'),
+            false => &line,
+        };
+        if line == delimiter {
+            return Ok(body);
+        }
+        if !ended {
+            return Err(LexError::UnterminatedHereDoc(delimiter));
+        }
+
+        let mut line = line.chars().chain(['\n']).peekable();
+        while let Some(c) = line.next() {
+            if quoted {
+                body.push(c, WordPart::Literal);
+            } else if c == '\\' && matches!(line.peek(), Some('$' | '`' | '\\')) {
+                let nc = line.next().expect("peeked char should be there");
+                body.push(nc, WordPart::Literal);
+            } else {
+                body.push(c, WordPart::Double);
+            }
+        }
+    }
+}
+
+// Fills in the here-documents started on the line that just ended, in order.
+// Each one's delimiter is the word right after its `<<`.
+fn here_documents(
+    tokens: &mut Vec<Token>,
+    pending: &mut Vec<(usize, bool)>,
+    chars: &mut Peekable<Chars>,
+) -> Result<(), LexError> {
+    for (removed, (index, strip_tabs)) in pending.drain(..).enumerate() {
+        let index = index - removed;
+        let Some(Token::Word(delimiter)) = tokens.get(index + 1).cloned() else {
+            return Err(LexError::MissingDelimiter);
+        };
+        tokens.remove(index + 1);
+        tokens[index] = Token::HereDoc(here_document(chars, &delimiter, strip_tabs)?);
+    }
+    Ok(())
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let mut tokens = Vec::new();
+    let mut current: Option<Word> = None;
+
+    let mut chars = input.chars().peekable();
+
+    let mut in_single = false;
+    let mut in_double = false;
+
+    // `<<` tokens still waiting for the end of the line to read their bodies.
+    let mut heredocs = Vec::new();
+
+    while let Some(c) = chars.next() {
+        if in_single {
+            match c {
+                '\'' => in_single = false,
+                c => current.get_or_insert_default().push(c, WordPart::Literal),
+            }
+            continue;
+        }
+
+        if in_double {
+            match c {
+                '"' => in_double = false,
+                '$' if chars.next_if_eq(&'{').is_some() => parameter(
+                    &mut chars,
+                    current.get_or_insert_default(),
+                    WordPart::Double,
+                )?,
+                '\\' if chars.next_if_eq(&'\n').is_some() => {}
+                '\\' if matches!(chars.peek(), Some('"' | '\\' | '$' | '`')) => {
+                    let nc = chars.next().expect("peeked char should be there");
+                    current.get_or_insert_default().push(nc, WordPart::Literal);
+                }
+                c => current.get_or_insert_default().push(c, WordPart::Double),
+            }
+            continue;
+        }
+
+        match c {
+            '\\' => match chars.next() {
+                // An escaped newline joins the two lines.
+                Some('\n') => {}
+                Some(nc) => current.get_or_insert_default().push(nc, WordPart::Literal),
+                None => return Err(LexError::TrailingBackslash),
+            },
+            '$' if chars.next_if_eq(&'{').is_some() => {
+                parameter(&mut chars, current.get_or_insert_default(), WordPart::Bare)?
+            }
+            '\'' => {
+                in_single = true;
+                current.get_or_insert_default().open(WordPart::Literal);
+            }
+            '"' => {
+                in_double = true;
+                current.get_or_insert_default().open(WordPart::Double);
+            }
+            '<' | '>' if current.is_none() && chars.next_if_eq(&'(').is_some() => {
+                let body =
+                    enclosed(&mut chars, '(', ')').ok_or(LexError::UnterminatedSubstitution)?;
+                let part = match c {
+                    '<' => WordPart::ProcessIn(body),
+                    _ => WordPart::ProcessOut(body),
+                };
+                current = Some(Word(vec![part]));
+            }
+            '<' if chars.next_if_eq(&'<').is_some() => {
+                if let Some(word) = current.take() {
+                    tokens.push(Token::Word(word));
+                }
+                if chars.next_if_eq(&'<').is_some() {
+                    tokens.push(Token::Word(Word(vec![WordPart::Bare("<<<".to_string())])));
+                    continue;
+                }
+                let strip_tabs = chars.next_if_eq(&'-').is_some();
+                heredocs.push((tokens.len(), strip_tabs));
+                tokens.push(Token::HereDoc(Word::default()));
+            }
+            '<' | '>' => {
+                // Digits right before the operator name the fd it redirects.
+                let mut op = match current.take() {
+                    Some(word)
+                        if word
+                            .bare()
+                            .is_some_and(|s| s.chars().all(|c| c.is_ascii_digit())) =>
+                    {
+                        word.text()
+                    }
+                    Some(word) => {
+                        tokens.push(Token::Word(word));
+                        String::new()
+                    }
+                    None => String::new(),
+                };
+                op.push(c);
+                if c == '>'
+                    && let Some(next) = chars.next_if(|next| matches!(next, '>' | '|'))
+                {
+                    op.push(next);
+                } else if chars.next_if_eq(&'&').is_some() {
+                    op.push('&');
+                }
+                let duplicate = op.ends_with('&');
+                tokens.push(Token::Word(Word(vec![WordPart::Bare(op)])));
+
+                // The fd after `>&` or `<&` ends there, so `2>&1>file` is two
+                // redirects.
+                let mut fd = String::new();
+                while duplicate && let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    fd.push(digit);
+                }
+                if !fd.is_empty() {
+                    tokens.push(Token::Word(Word(vec![WordPart::Bare(fd)])));
+                }
+            }
+            '&' if chars.next_if_eq(&'>').is_some() => {
+                if let Some(word) = current.take() {
+                    tokens.push(Token::Word(word));
+                }
+                let op = match chars.next_if_eq(&'>') {
+                    Some(_) => "&>>",
+                    None => "&>",
+                };
+                tokens.push(Token::Word(Word(vec![WordPart::Bare(op.to_string())])));
+            }
+            '#' if current.is_none() => while chars.next_if(|c| *c != '\n').is_some() {},
+            '\n' => {
+                if let Some(word) = current.take() {
+                    tokens.push(Token::Word(word));
+                }
+                here_documents(&mut tokens, &mut heredocs, &mut chars)?;
+                // A newline ends a command like `;` does, unless the line stops
+                // where more has to follow, as in `a |` or `(`.
+                if matches!(
+                    tokens.last(),
+                    Some(Token::Word(_) | Token::HereDoc(_) | Token::RParen | Token::Arith(_))
+                ) {
+                    tokens.push(Token::Semicolon);
+                }
+            }
+            '(' if current.is_none() && chars.peek() == Some(&'(') => {
+                let mut ahead = chars.clone();
+                ahead.next();
+                match arithmetic(&mut ahead) {
+                    Some(expr) => {
+                        chars = ahead;
+                        tokens.push(Token::Arith(expr));
+                    }
+                    None => tokens.push(Token::LParen),
+                }
+            }
+            '|' | ';' | '(' | ')' => {
+                if let Some(word) = current.take() {
+                    tokens.push(Token::Word(word));
+                }
+                tokens.push(match c {
+                    '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+                    '|' => Token::Pipe,
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    ';' if chars.next_if_eq(&';').is_some() => Token::DoubleSemicolon,
+                    _ => Token::Semicolon,
+                });
+            }
+            '&' if chars.next_if_eq(&'&').is_some() => {
+                if let Some(word) = current.take() {
+                    tokens.push(Token::Word(word));
+                }
+                tokens.push(Token::And);
+            }
+            '&' => {
+                if let Some(word) = current.take() {
+                    tokens.push(Token::Word(word));
+                }
+                tokens.push(Token::Background);
+            }
+            c if c.is_whitespace() => {
+                if let Some(word) = current.take() {
+                    tokens.push(Token::Word(word));
+                }
+            }
+            c => current.get_or_insert_default().push(c, WordPart::Bare),
+        }
+    }
+
+    if in_single {
+        return Err(LexError::UnterminatedQuote('\''));
+    }
+    if in_double {
+        return Err(LexError::UnterminatedQuote('"'));
+    }
+
+    if let Some(word) = current.take() {
+        tokens.push(Token::Word(word));
+    }
+    here_documents(&mut tokens, &mut heredocs, &mut chars)?;
+
+    Ok(tokens)
+}
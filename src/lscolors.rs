@@ -0,0 +1,154 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::Path,
+};
+
+/// The styles `ls` would give files, as configured in $LS_COLORS: two-letter
+/// keys for file types (`di=01;34`) and `*suffix` patterns for names.
+#[derive(Debug, Default)]
+pub struct LsColors {
+    types: HashMap<String, String>,
+    suffixes: Vec<(String, String)>,
+}
+
+impl LsColors {
+    pub fn parse(spec: &str) -> Self {
+        let mut colors = LsColors::default();
+        for (key, style) in spec.split(':').filter_map(|entry| entry.split_once('=')) {
+            match key.strip_prefix('*') {
+                Some(suffix) => colors
+                    .suffixes
+                    .push((suffix.to_lowercase(), style.to_string())),
+                None => _ = colors.types.insert(key.to_string(), style.to_string()),
+            }
+        }
+        colors
+    }
+
+    /// None when $LS_COLORS is unset, so nothing gets colored.
+    pub fn from_env() -> Option<Self> {
+        env::var("LS_COLORS")
+            .ok()
+            .map(|spec| LsColors::parse(&spec))
+    }
+
+    fn of_type(&self, key: &str) -> Option<&str> {
+        self.types
+            .get(key)
+            .map(String::as_str)
+            .filter(|style| !style.is_empty() && *style != "0" && *style != "00")
+    }
+
+    // Later patterns win, like in dircolors.
+    fn of_name(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.suffixes
+            .iter()
+            .rev()
+            .find(|(suffix, _)| name.ends_with(suffix.as_str()))
+            .map(|(_, style)| style.as_str())
+    }
+
+    /// The SGR parameters for the file at `path`, if any apply.
+    pub fn style(&self, path: &Path) -> Option<&str> {
+        let metadata = fs::symlink_metadata(path).ok()?;
+        let file_type = metadata.file_type();
+        let mode = metadata.permissions().mode();
+
+        if file_type.is_symlink() {
+            return match fs::metadata(path) {
+                Err(_) => self.of_type("or").or_else(|| self.of_type("ln")),
+                Ok(_) if self.types.get("ln").is_some_and(|ln| ln == "target") => {
+                    self.style(&fs::canonicalize(path).ok()?)
+                }
+                Ok(_) => self.of_type("ln"),
+            };
+        }
+        if file_type.is_dir() {
+            let sticky = mode & 0o1000 != 0;
+            let writable = mode & 0o002 != 0;
+            let key = match (sticky, writable) {
+                (true, true) => "tw",
+                (false, true) => "ow",
+                (true, false) => "st",
+                (false, false) => "di",
+            };
+            return self.of_type(key).or_else(|| self.of_type("di"));
+        }
+        if file_type.is_fifo() {
+            return self.of_type("pi");
+        }
+        if file_type.is_socket() {
+            return self.of_type("so");
+        }
+        if file_type.is_block_device() {
+            return self.of_type("bd");
+        }
+        if file_type.is_char_device() {
+            return self.of_type("cd");
+        }
+
+        let special = if mode & 0o4000 != 0 {
+            self.of_type("su")
+        } else if mode & 0o2000 != 0 {
+            self.of_type("sg")
+        } else if mode & 0o111 != 0 {
+            self.of_type("ex")
+        } else {
+            None
+        };
+        let name = path.file_name()?.to_string_lossy();
+        special
+            .or_else(|| self.of_name(&name))
+            .or_else(|| self.of_type("fi"))
+    }
+}
+
+pub fn paint(text: &str, style: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", style, text)
+}
+
+/// Nerd Font glyphs for a few common kinds of file.
+const ICONS: [(&str, &str); 14] = [
+    ("rs", "\u{e7a8}"),
+    ("py", "\u{e606}"),
+    ("js", "\u{e74e}"),
+    ("ts", "\u{e628}"),
+    ("go", "\u{e627}"),
+    ("c", "\u{e61e}"),
+    ("h", "\u{e61e}"),
+    ("md", "\u{e609}"),
+    ("json", "\u{e60b}"),
+    ("toml", "\u{e615}"),
+    ("sh", "\u{f489}"),
+    ("lock", "\u{f023}"),
+    ("png", "\u{f1c5}"),
+    ("zip", "\u{f410}"),
+];
+
+/// The icon `set -o icons` puts before a file's name.
+pub fn icon(path: &Path) -> &'static str {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return "\u{f15b}";
+    };
+    if metadata.file_type().is_symlink() {
+        return "\u{f481}";
+    }
+    if metadata.is_dir() {
+        return "\u{f115}";
+    }
+
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match ICONS
+        .iter()
+        .find(|(ext, _)| Some(*ext) == extension.as_deref())
+    {
+        Some((_, icon)) => icon,
+        None if metadata.permissions().mode() & 0o111 != 0 => "\u{f489}",
+        None => "\u{f15b}",
+    }
+}
@@ -1,28 +1,139 @@
+mod agents;
+mod aliases;
+mod arith;
+mod bindings;
+mod capture;
+mod clipboard;
+mod complete;
+mod control;
+mod crash;
+mod envdiff;
+mod expand;
+mod explain;
+mod fd;
+mod functions;
+mod fzf;
+mod glob;
+mod highlight;
+mod history;
+mod jobs;
+mod lexer;
+mod lscolors;
+mod names;
+mod options;
+mod palette;
+mod params;
+mod parser;
+mod paths;
+mod policy;
+mod preview;
+mod printf;
+mod profile;
+mod progress;
+mod replay;
+mod sandbox;
+mod signals;
+mod snippets;
+mod subshell;
+mod summary;
+mod terminal;
+mod traps;
+mod trash;
+mod vars;
+mod winpath;
+
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
     env::{self, split_paths},
+    ffi::{CString, OsStr},
     fmt::Debug,
     fs::{self, File, OpenOptions},
-    io::{self, BufRead, BufReader, PipeReader, PipeWriter, Read, Write, pipe},
-    os::unix::{fs::PermissionsExt, process::CommandExt},
-    path::PathBuf,
-    process::{self, Child, Command, Stdio},
-    sync::{Arc, Mutex},
+    io::{self, BufRead, BufReader, IsTerminal, PipeReader, PipeWriter, Read, Write, pipe},
+    iter, mem,
+    ops::ControlFlow,
+    os::unix::{
+        fs::PermissionsExt,
+        io::{AsFd, AsRawFd, OwnedFd, RawFd},
+        process::{CommandExt, ExitStatusExt},
+    },
+    path::{Path, PathBuf},
+    process::{self, Child, Command, ExitStatus, Stdio},
+    slice,
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, mpsc},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use itertools::Itertools;
+use jobs::{JobId, State};
+use lexer::{Word, WordPart};
+use parser::{AndOr, Connector, Group, List, Pipeline, Redirect, RedirectMode};
+use policy::Verdict;
 use rustyline::{
-    CompletionType, Config, Context, Editor, Helper, Highlighter, Hinter, Validator,
+    CompletionType, Config, Context, Editor, Helper, Validator,
     completion::{Completer, Pair},
     config::{BellStyle, Configurer},
     error::ReadlineError,
-    history::FileHistory,
+    highlight::{CmdKind, Highlighter},
+    hint::Hinter,
+    history::{FileHistory, History},
 };
 
-const BUILTINS: [&str; 6] = ["echo", "type", "exit", "pwd", "cd", "history"];
+const BUILTINS: [&str; 42] = [
+    "echo",
+    "type",
+    "exit",
+    "pwd",
+    "cd",
+    "history",
+    "replay",
+    "clip",
+    "set",
+    "watch",
+    "repeat",
+    "retry",
+    "calc",
+    "printf",
+    "wslpath",
+    "snippet",
+    "undo-clobber",
+    "explain",
+    "exec",
+    "whence",
+    "envdiff",
+    "chunked",
+    "isatty",
+    "break",
+    "continue",
+    "read",
+    "trap",
+    "ulimit",
+    "let",
+    "fc",
+    "alias",
+    "unalias",
+    "tt",
+    "source",
+    ".",
+    "return",
+    "jobs",
+    "fg",
+    "bg",
+    "wait",
+    "disown",
+    "kill",
+];
+
+/// Builtins that read flags of their own, and so find the `--` ending them
+/// themselves. `set --` replaces the positional parameters.
+const READS_FLAGS: [&str; 8] = [
+    "cd", "pwd", "set", "whence", "wslpath", "watch", "repeat", "retry",
+];
 
-type IOJoinHandle = JoinHandle<io::Result<()>>;
+type IOJoinHandle = JoinHandle<io::Result<i32>>;
 type Shell = Editor<ShellHelper, FileHistory>;
 
 #[derive(Debug)]
@@ -81,6 +192,61 @@ impl Write for IOSource {
     }
 }
 
+impl IOSource {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            IOSource::PipeReader(reader) => IOSource::PipeReader(reader.try_clone()?),
+            IOSource::PipeWriter(writer) => IOSource::PipeWriter(writer.try_clone()?),
+            IOSource::File(file) => IOSource::File(file.try_clone()?),
+            IOSource::Stdout => IOSource::Stdout,
+            IOSource::Stdin => IOSource::Stdin,
+            IOSource::Stderr => IOSource::Stderr,
+        })
+    }
+
+    /// The number a standard stream stands for. Those only work in their own
+    /// place, since they write through std's handle for it.
+    fn standard_fd(&self) -> Option<RawFd> {
+        match self {
+            IOSource::Stdin => Some(0),
+            IOSource::Stdout => Some(1),
+            IOSource::Stderr => Some(2),
+            _ => None,
+        }
+    }
+
+    // The fd it holds of its own, if it isn't a standard stream.
+    fn raw_fd(&self) -> Option<RawFd> {
+        match self {
+            IOSource::PipeReader(reader) => Some(reader.as_raw_fd()),
+            IOSource::PipeWriter(writer) => Some(writer.as_raw_fd()),
+            IOSource::File(file) => Some(file.as_raw_fd()),
+            IOSource::Stdin | IOSource::Stdout | IOSource::Stderr => None,
+        }
+    }
+
+    fn is_terminal(&self) -> bool {
+        match self {
+            IOSource::PipeReader(_) | IOSource::PipeWriter(_) => false,
+            IOSource::File(file) => file.is_terminal(),
+            IOSource::Stdin => io::stdin().is_terminal(),
+            IOSource::Stdout => io::stdout().is_terminal(),
+            IOSource::Stderr => io::stderr().is_terminal(),
+        }
+    }
+
+    fn into_fd(self) -> io::Result<OwnedFd> {
+        Ok(match self {
+            IOSource::PipeReader(reader) => OwnedFd::from(reader),
+            IOSource::PipeWriter(writer) => OwnedFd::from(writer),
+            IOSource::File(file) => OwnedFd::from(file),
+            IOSource::Stdin => io::stdin().as_fd().try_clone_to_owned()?,
+            IOSource::Stdout => io::stdout().as_fd().try_clone_to_owned()?,
+            IOSource::Stderr => io::stderr().as_fd().try_clone_to_owned()?,
+        })
+    }
+}
+
 impl Read for IOSource {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
@@ -94,15 +260,233 @@ impl Read for IOSource {
     }
 }
 
+// Whatever a single command turned into: a running process, a builtin running on
+// its own thread, or a status that is already known (e.g. command not found).
+enum Spawned {
+    /// An external command, with the name it was run by.
+    Child(Child, String),
+    /// A forked copy of the shell running a subshell.
+    Forked(libc::pid_t),
+    Builtin(IOJoinHandle),
+    Finished(i32),
+}
+
+impl Spawned {
+    fn wait(self) -> io::Result<i32> {
+        match self {
+            Spawned::Child(mut child, name) => {
+                let status = child.wait()?;
+                if let Some(signal) = status.signal() {
+                    crash::report(&name, child.id(), signal, status.core_dumped());
+                }
+                if let Some(job) = jobs::foreground_with(child.id()) {
+                    left_foreground(job, status.signal());
+                }
+                Ok(status
+                    .code()
+                    .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)))
+            }
+            Spawned::Forked(pid) => {
+                let mut raw = 0;
+                while unsafe { libc::waitpid(pid, &mut raw, 0) } == -1 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                let status = ExitStatus::from_raw(raw);
+                if let Some(job) = jobs::foreground_with(pid as u32) {
+                    left_foreground(job, status.signal());
+                }
+                Ok(status
+                    .code()
+                    .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)))
+            }
+            Spawned::Builtin(handle) => match handle.join().expect("Failed joining handle") {
+                Ok(status) => Ok(status),
+                Err(err) => {
+                    eprintln!("codecrafters-shell: write error: {}", error_message(&err));
+                    Ok(1)
+                }
+            },
+            Spawned::Finished(status) => Ok(status),
+        }
+    }
+}
+
+// A process the prompt was waiting for is done. Ctrl-C went to it rather than
+// the shell, which acts on it all the same. Once nothing in its group is left
+// the terminal comes back, for builtins like `read` between its commands.
+fn left_foreground(job: JobId, signal: Option<i32>) {
+    if signal == Some(libc::SIGINT) {
+        signals::interrupt();
+    }
+    if jobs::group(job) == 0 {
+        _ = terminal::take_back();
+    }
+}
+
 struct IOPipes {
-    #[allow(dead_code)]
     input: IOSource,
     output: IOSource,
     error: IOSource,
+    /// Pipe ends from process substitution, passed on under their own numbers.
+    fds: Vec<OwnedFd>,
+    /// What redirections like `3> file` put on fds above 2, which a child
+    /// gets under those numbers.
+    numbered: Vec<(RawFd, IOSource)>,
+}
+
+impl IOPipes {
+    fn standard() -> Self {
+        Self {
+            input: IOSource::Stdin,
+            output: IOSource::Stdout,
+            error: IOSource::Stderr,
+            fds: Vec::new(),
+            numbered: Vec::new(),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            input: self.input.try_clone()?,
+            output: self.output.try_clone()?,
+            error: self.error.try_clone()?,
+            fds: self
+                .fds
+                .iter()
+                .map(OwnedFd::try_clone)
+                .collect::<io::Result<_>>()?,
+            numbered: self
+                .numbered
+                .iter()
+                .map(|(fd, source)| Ok((*fd, source.try_clone()?)))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+
+    // Every fd they hold, which a forked copy of the shell has to keep.
+    fn raw_fds(&self) -> Vec<RawFd> {
+        [&self.input, &self.output, &self.error]
+            .into_iter()
+            .chain(self.numbered.iter().map(|(_, source)| source))
+            .filter_map(IOSource::raw_fd)
+            .chain(self.fds.iter().map(AsRawFd::as_raw_fd))
+            .collect()
+    }
+}
+
+/// Everything a command may need from the shell that is running it.
+#[derive(Clone)]
+struct Session {
+    editor: Arc<Mutex<Shell>>,
+    append_history: Arc<Mutex<Vec<String>>>,
+    history_path: Option<String>,
+    interactive: bool,
+    /// Set inside a subshell, which is all `exit` leaves there.
+    exit: Option<subshell::Exit>,
+    /// Where `break` and `continue` leave word for the loops around them.
+    jump: Arc<Mutex<Option<Jump>>>,
+    /// How many loops the commands run in, which `break 5` can't go past.
+    loops: u32,
+    /// Set for an `if` or loop condition, where failing is no error for the
+    /// ERR trap.
+    condition: bool,
+    /// Set while a trap runs, so its own commands don't set off traps.
+    trapped: bool,
+    /// The files being sourced, outermost first, so `source` can refuse to
+    /// go round in a cycle or too deep.
+    sourcing: Vec<PathBuf>,
+    /// Set in a sourced file or a function, which `return` can leave.
+    returnable: bool,
+    /// The job the commands belong to: one started with `&`, or the hidden
+    /// one for a pipeline run at the prompt.
+    job: Option<JobId>,
+}
+
+impl Session {
+    // The status `exit` left, once a subshell has to stop.
+    fn exited(&self) -> Option<i32> {
+        self.exit.as_ref().and_then(|exit| exit.get().copied())
+    }
+
+    // Whether the rest of a list gets skipped, for `exit` or a loop's
+    // `break` or `continue`.
+    fn stopping(&self) -> bool {
+        self.exited().is_some() || self.jump.lock().expect("Failed to lock jump!").is_some()
+    }
+}
+
+/// What `break n` or `continue n` asked of the loops around it, with how many
+/// of them are left to get out of, or the status `return` left for the
+/// sourced file or function it is in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Jump {
+    Break(u32),
+    Continue(u32),
+    Return(i32),
+}
+
+#[derive(Debug, Default, Helper, Validator)]
+struct ShellHelper {
+    // Whether the line on screen has a matching quote or bracket lit up, so
+    // the final redraw of an accepted line can leave it plain.
+    matching: Cell<bool>,
+    // What Ctrl-R is searching for, read off its prompt, which is drawn just
+    // before the line.
+    search: RefCell<Option<String>>,
+}
+
+impl Highlighter for ShellHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        if let Some(query) = self.search.borrow().as_deref() {
+            return highlight::search_match(line, pos, query);
+        }
+        match self.matching.get() {
+            true => highlight::highlight(line, pos),
+            false => Cow::Borrowed(line),
+        }
+    }
+
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        default: bool,
+    ) -> Cow<'b, str> {
+        let query = match default {
+            true => None,
+            false => highlight::search_query(prompt),
+        };
+        *self.search.borrow_mut() = query.map(String::from);
+        Cow::Borrowed(prompt)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+
+    fn highlight_candidate<'c>(&self, candidate: &'c str, _kind: CompletionType) -> Cow<'c, str> {
+        match complete::style(candidate) {
+            Some(style) => Cow::Owned(lscolors::paint(candidate, &style)),
+            None => Cow::Borrowed(candidate),
+        }
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, kind: CmdKind) -> bool {
+        let matching = kind != CmdKind::ForcedRefresh && highlight::matching(line, pos).is_some();
+        self.matching.set(matching);
+        matching
+    }
 }
 
-#[derive(Debug, Helper, Validator, Highlighter, Hinter)]
-struct ShellHelper;
+impl Hinter for ShellHelper {
+    type Hint = preview::Preview;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<Self::Hint> {
+        preview::hint(line, pos)
+    }
+}
 
 impl Completer for ShellHelper {
     type Candidate = Pair;
@@ -112,8 +496,20 @@ impl Completer for ShellHelper {
         pos: usize,
         _ctx: &Context<'_>,
     ) -> Result<(usize, Vec<Self::Candidate>), ReadlineError> {
+        complete::clear_styles();
         let start = line[..pos].rfind(' ').map_or(0, |i| i + 1);
-        let prefix = &line[start..pos].to_lowercase();
+        if let Some(["snippet", "run" | "show" | "rm"]) =
+            Some(line[..start].split_whitespace().collect_vec()).as_deref()
+        {
+            return Ok((start, complete::snippets(&line[start..pos])));
+        }
+        if !line[..start].trim().is_empty() {
+            return Ok((start, complete::paths(&line[start..pos])));
+        }
+        if line[start..pos].contains('/') {
+            return Ok((start, complete::commands(&line[start..pos])));
+        }
+        let prefix = names::fold(&line[start..pos]).to_lowercase();
 
         let builtins = BUILTINS.map(String::from).to_vec();
         let executables = get_external_executables();
@@ -123,7 +519,7 @@ impl Completer for ShellHelper {
 
         let mut matches: Vec<Pair> = commands
             .iter()
-            .filter(|cmd| cmd.to_lowercase().starts_with(prefix))
+            .filter(|cmd| names::fold(cmd).to_lowercase().starts_with(&prefix))
             .map(|cmd| Pair {
                 display: cmd.to_string(),
                 replacement: cmd.to_string() + " ",
@@ -136,6 +532,31 @@ impl Completer for ShellHelper {
     }
 }
 
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+        && (path
+            .metadata()
+            .map(|m| m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false))
+}
+
+/// Finds the program a command name runs. Names with a slash are paths and
+/// skip $PATH; bare names only fall back to `.` in a $TRUSTED_DIRS directory.
+fn find_executable(cmd: &str) -> Option<PathBuf> {
+    if cmd.contains('/') {
+        let path = PathBuf::from(cmd);
+        return is_executable(&path).then_some(path);
+    }
+
+    if let Some(path) = get_external_executables().remove(cmd) {
+        return Some(path);
+    }
+
+    let cwd = env::current_dir().ok()?;
+    let path = cwd.join(cmd);
+    (paths::trusted(&cwd) && is_executable(&path)).then_some(path)
+}
+
 fn get_external_executables() -> HashMap<String, PathBuf> {
     let path = env::var("PATH").expect("Failed to fetch PATH!");
     let mut results = HashMap::new();
@@ -146,13 +567,7 @@ fn get_external_executables() -> HashMap<String, PathBuf> {
 
         for entry in entries.flatten() {
             let path = entry.path();
-            let is_executable = path.is_file()
-                && (path
-                    .metadata()
-                    .map(|m| m.permissions().mode() & 0o111 != 0)
-                    .unwrap_or(false));
-
-            if !is_executable {
+            if !is_executable(&path) {
                 continue;
             }
 
@@ -164,133 +579,364 @@ fn get_external_executables() -> HashMap<String, PathBuf> {
     results
 }
 
-fn parse_args(input: String) -> Vec<String> {
-    let mut args = Vec::new();
-    let mut current = String::new();
-
-    let mut chars = input.trim().chars().peekable();
-
-    let mut in_single = false;
-    let mut in_double = false;
-    let mut escaped = false;
-
-    while let Some(c) = chars.next() {
-        if escaped {
-            current.push(c);
-            escaped = false;
-            continue;
-        }
-        if c == '\\' && (!in_single) {
-            if in_double {
-                if let Some(nc) = chars.peek().copied()
-                    && (nc == '\"' || nc == '\\')
-                {
-                    escaped = true;
-                    continue;
-                }
-            } else {
-                escaped = true;
+fn history_read(editor: Arc<Mutex<Shell>>, read_path: Option<&String>) -> io::Result<bool> {
+    if let Some(file_path) = read_path {
+        let file = File::open(file_path)?;
+        let mut editor = editor.lock().expect("Failed to lock the editor!");
+        let mut time = None;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if let Some(stamp) = history::parse_time(&line) {
+                time = Some(stamp);
                 continue;
             }
+            add_history_entry(&mut editor, &line, time.take());
         }
+        return Ok(true);
+    }
+    Ok(false)
+}
 
-        match c {
-            '\'' if !in_double => {
-                in_single = !in_single;
-            }
-            '"' if !in_single => {
-                in_double = !in_double;
-            }
-            c if c.is_whitespace() && !in_single && !in_double => {
-                if !current.is_empty() {
-                    args.push(current);
-                    current = String::new();
-                }
-            }
-            _ => {
-                current.push(c);
+fn history_write(editor: Arc<Mutex<Shell>>, write_path: Option<&String>) -> io::Result<bool> {
+    if let Some(file_path) = write_path {
+        let mut file = File::create(file_path)?;
+        let editor = editor.lock().expect("Failed to lock the editor!");
+        let history = editor.history();
+        // $HISTFILESIZE keeps only the newest entries, and without it all go.
+        let kept = vars::get("HISTFILESIZE")
+            .and_then(|size| size.parse::<usize>().ok())
+            .unwrap_or(usize::MAX);
+        let skipped = history.len().saturating_sub(kept);
+        for (index, entry) in history.iter().enumerate().skip(skipped) {
+            if let Some(time) = history::time(index) {
+                file.write_all(format!("#{}\n", time).as_bytes())?;
             }
+            file.write_all(format!("{}\n", entry).as_bytes())?;
         }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+fn history_append(
+    append_history: Arc<Mutex<Vec<String>>>,
+    append_path: Option<&String>,
+) -> io::Result<bool> {
+    if let Some(file_path) = append_path {
+        let mut file = OpenOptions::new().append(true).open(file_path)?;
+        let mut append_history = append_history
+            .lock()
+            .expect("Failed to lock append history!");
+        for line in append_history.iter() {
+            file.write_all(format!("{}\n", line).as_bytes())?;
+        }
+        append_history.clear();
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+// Drops the entry numbered like in `history`'s listing, which rustyline can't
+// do in place, so the rest are added back.
+fn history_delete(editor: &Mutex<Shell>, number: usize) -> io::Result<bool> {
+    let mut editor = editor.lock().expect("Failed to lock the editor!");
+    if number == 0 || number > editor.history().len() {
+        return Ok(false);
+    }
+    let kept = editor
+        .history()
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| index + 1 != number)
+        .map(|(index, entry)| (entry.clone(), history::time(index)))
+        .collect_vec();
+    editor.clear_history().map_err(io::Error::other)?;
+    history::clear_times();
+    for (entry, time) in kept {
+        add_history_entry(&mut editor, &entry, time);
     }
+    Ok(true)
+}
 
-    if !current.is_empty() {
-        args.push(current);
+// Adds an entry to the history along with when it went in, keeping the times
+// in step with whatever rustyline keeps and lets go of.
+fn add_history_entry(editor: &mut Shell, line: &str, time: Option<i64>) {
+    if let Ok(true) = editor.add_history_entry(line) {
+        history::stamp(time, editor.history().len());
     }
+}
 
-    args
+fn handle_echo(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    pipes
+        .output
+        .write_all(format!("{}\n", args.join(" ")).as_bytes())?;
+    Ok(0)
 }
 
-fn get_redirect(args: &mut Vec<String>, redirect_pipes: Vec<String>) -> Option<String> {
-    let pos = args.clone().iter().position(|x| redirect_pipes.contains(x));
+fn handle_printf(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let Some((format, args)) = args.split_first() else {
+        pipes
+            .error
+            .write_all(b"Usage: printf format [arguments]\n")?;
+        return Ok(2);
+    };
 
-    if let Some(pos) = pos {
-        let path = Some(args[pos + 1].clone());
-        args.remove(pos + 1);
-        args.remove(pos);
-        path
-    } else {
-        None
+    match printf::format(format, args) {
+        Ok(output) => {
+            pipes.output.write_all(output.as_bytes())?;
+            Ok(0)
+        }
+        Err(err) => {
+            pipes
+                .error
+                .write_all(format!("printf: {}\n", err).as_bytes())?;
+            Ok(1)
+        }
     }
 }
 
-fn history_read(editor: Arc<Mutex<Shell>>, read_path: Option<&String>) -> bool {
-    if let Some(file_path) = read_path {
-        let file = File::open(file_path)
-            .unwrap_or_else(|e| panic!("Failed to open '{}': {}", file_path, e));
-        for line in BufReader::new(file).lines() {
-            let line = line.unwrap();
-            editor
-                .lock()
-                .expect("Failed to lock the editor!")
-                .add_history_entry(line)
-                .expect("Failed to add history entry!");
+// With only redirections, `exec` makes them stick for the rest of the session.
+fn keep_redirections(pipes: &mut IOPipes) -> io::Result<()> {
+    let standard = [
+        (0, mem::replace(&mut pipes.input, IOSource::Stdin)),
+        (1, mem::replace(&mut pipes.output, IOSource::Stdout)),
+        (2, mem::replace(&mut pipes.error, IOSource::Stderr)),
+    ];
+    _ = io::stdout().flush();
+    _ = io::stderr().flush();
+
+    for (fd, source) in standard.into_iter().chain(mem::take(&mut pipes.numbered)) {
+        if source.standard_fd() != Some(fd) {
+            fd::install(fd, source.into_fd()?)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", fd, error_message(&e))))?;
         }
-        return true;
     }
-    false
+    Ok(())
 }
 
-fn history_write(editor: Arc<Mutex<Shell>>, write_path: Option<&String>) -> bool {
-    if let Some(file_path) = write_path {
-        let mut file = File::create(file_path)
-            .unwrap_or_else(|e| panic!("Failed to create '{}': {}", file_path, e));
-        for entry in editor
-            .lock()
-            .expect("Failed to lock the editor!")
-            .history()
-            .iter()
-        {
-            file.write_all(format!("{}\n", entry).as_bytes())
-                .unwrap_or_else(|e| panic!("Failed to write to '{}': {}", file_path, e));
+fn handle_exec(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let Some((cmd, args)) = args.split_first() else {
+        return match keep_redirections(pipes) {
+            Ok(()) => Ok(0),
+            Err(err) => {
+                eprintln!("exec: {}", err);
+                Ok(1)
+            }
+        };
+    };
+
+    let Some(executable) = find_executable(cmd) else {
+        pipes
+            .error
+            .write_all(format!("exec: {}: not found\n", cmd).as_bytes())?;
+        return Ok(127);
+    };
+    if options::is_set("dryrun") {
+        return handle_external(
+            cmd,
+            args.to_vec(),
+            Vec::new(),
+            None,
+            mem::replace(pipes, IOPipes::standard()),
+        )?
+        .wait();
+    }
+
+    let mut command = Command::new(&executable);
+    command
+        .arg0(cmd)
+        .args(winpath::translate_args(&executable, args.to_vec()))
+        .envs(vars::exported())
+        .envs(vars::prefixed());
+    let _fds = connect(&mut command, mem::replace(pipes, IOPipes::standard()))?;
+    let _ruleset = sandbox::apply(&mut command)?;
+
+    // Only comes back if the program couldn't be started.
+    let running = unless_timed_out();
+    let err = command.exec();
+    drop(running);
+    if err.raw_os_error() == Some(libc::E2BIG) {
+        let argv = iter::once(cmd.clone()).chain(args.to_vec()).collect_vec();
+        eprint!("exec: {}", argument_list_too_long(cmd, &argv));
+        return Ok(126);
+    }
+    eprintln!("exec: {}: {}", cmd, error_message(&err));
+    Ok(126)
+}
+
+fn handle_undo_clobber(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    if !args.is_empty() {
+        pipes.error.write_all(b"Usage: undo-clobber\n")?;
+        return Ok(2);
+    }
+
+    match trash::restore_last() {
+        Ok(Some(path)) => {
+            pipes
+                .output
+                .write_all(format!("restored {}\n", path.to_string_lossy()).as_bytes())?;
+            Ok(0)
+        }
+        Ok(None) => {
+            pipes
+                .error
+                .write_all(b"undo-clobber: nothing to restore\n")?;
+            Ok(1)
+        }
+        Err(err) => {
+            pipes
+                .error
+                .write_all(format!("undo-clobber: {}\n", error_message(&err)).as_bytes())?;
+            Ok(1)
         }
-        return true;
     }
-    false
 }
 
-fn history_append(append_history: Arc<Mutex<Vec<String>>>, append_path: Option<&String>) -> bool {
-    if let Some(file_path) = append_path {
-        let mut file = OpenOptions::new()
-            .append(true)
-            .open(file_path)
-            .expect(format!("Failed to open '{}'", file_path).as_str());
-        let mut append_history = append_history
-            .lock()
-            .expect("Failed to lock append history!");
-        for line in append_history.iter() {
-            file.write_all(format!("{}\n", line).as_bytes())
-                .unwrap_or_else(|e| panic!("Failed to append to '{}': {}", file_path, e));
+fn handle_snippet(args: Vec<String>, pipes: &mut IOPipes, session: Session) -> io::Result<i32> {
+    let help_msg = "Usage: snippet [list]\n\
+      snippet save <name> [command...]   saves the command, or stdin if none is given\n\
+      snippet run <name> [args...]       runs it with $1.. and $@ set to the args\n\
+      snippet show <name>\n\
+      snippet rm <name>\n"
+        .as_bytes();
+
+    let (action, name, rest) = match args.as_slice() {
+        [] => ("list", "", &[][..]),
+        [action] => (action.as_str(), "", &[][..]),
+        [action, name, rest @ ..] => (action.as_str(), name.as_str(), rest),
+    };
+    if action != "list" && !snippets::valid_name(name) {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    }
+
+    let result = match action {
+        "list" => {
+            for name in snippets::names() {
+                pipes.output.write_all(format!("{}\n", name).as_bytes())?;
+            }
+            Ok(0)
+        }
+        "save" => {
+            let mut body = rest.join(" ");
+            if rest.is_empty() {
+                pipes.input.read_to_string(&mut body)?;
+            }
+            if !body.ends_with('\n') {
+                body.push('\n');
+            }
+            snippets::save(name, &body).map(|_| 0)
+        }
+        "show" => snippets::load(name).and_then(|body| {
+            pipes.output.write_all(body.as_bytes())?;
+            Ok(0)
+        }),
+        "rm" => snippets::remove(name).map(|_| 0),
+        "run" => match snippets::load(name) {
+            Ok(body) => return run_lines(&snippets::substitute(&body, rest), &session, pipes),
+            Err(err) => Err(err),
+        },
+        _ => {
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+    };
+
+    result.or_else(|err| {
+        pipes
+            .error
+            .write_all(format!("snippet: {}: {}\n", name, error_message(&err)).as_bytes())?;
+        Ok(1)
+    })
+}
+
+fn handle_wslpath(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: wslpath [-u|-w|-m] path\n\
+      -u turns a Windows path into a Linux one (default).\n\
+      -w turns a Linux path into a Windows one, -m does the same with forward slashes.\n"
+        .as_bytes();
+
+    let (mode, path) = match args.iter().map(String::as_str).collect_vec().as_slice() {
+        [path] if !path.starts_with('-') => ("-u", *path),
+        ["--", path] => ("-u", *path),
+        [mode @ ("-u" | "-w" | "-m"), path] | [mode @ ("-u" | "-w" | "-m"), "--", path] => {
+            (*mode, *path)
+        }
+        _ => {
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+    };
+
+    let Some(flavor) = winpath::Flavor::detect() else {
+        pipes
+            .error
+            .write_all(b"wslpath: not running under WSL or MSYS\n")?;
+        return Ok(1);
+    };
+
+    let translated = match mode {
+        "-u" => Some(winpath::to_unix(path, flavor)),
+        mode => winpath::to_windows(path, flavor, mode == "-m"),
+    };
+    match translated {
+        Some(translated) => {
+            pipes
+                .output
+                .write_all(format!("{}\n", translated).as_bytes())?;
+            Ok(0)
+        }
+        None => {
+            pipes
+                .error
+                .write_all(format!("wslpath: {}: no Windows path for this\n", path).as_bytes())?;
+            Ok(1)
         }
-        append_history.clear();
-        return true;
     }
-    false
 }
 
-fn handle_echo(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<()> {
-    pipes
-        .output
-        .write_all(format!("{}\n", args.join(" ")).as_bytes())
+fn handle_calc(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    if args.is_empty() {
+        pipes
+            .error
+            .write_all(b"Usage: calc expression\n  Prints the value of an arithmetic expression, e.g. calc 'sqrt(2) * 3'.\n")?;
+        return Ok(2);
+    }
+
+    match arith::eval(&args.join(" ")) {
+        Ok(value) => {
+            pipes.output.write_all(format!("{}\n", value).as_bytes())?;
+            Ok(0)
+        }
+        Err(err) => {
+            pipes
+                .error
+                .write_all(format!("calc: {}\n", err).as_bytes())?;
+            Ok(1)
+        }
+    }
+}
+
+fn handle_explain(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    if args.is_empty() {
+        pipes
+            .error
+            .write_all(b"Usage: explain command-line\n  Shows how a line is tokenized, expanded and run, e.g. explain 'ls *.rs | wc -l'.\n")?;
+        return Ok(2);
+    }
+
+    match explain::explain(&args.join(" ")) {
+        Ok(explanation) => {
+            pipes.output.write_all(explanation.as_bytes())?;
+            Ok(0)
+        }
+        Err(err) => {
+            pipes
+                .error
+                .write_all(format!("explain: {}\n", err).as_bytes())?;
+            Ok(1)
+        }
+    }
 }
 
 fn handle_history(
@@ -298,17 +944,47 @@ fn handle_history(
     pipes: &mut IOPipes,
     editor: Arc<Mutex<Shell>>,
     append_history: Arc<Mutex<Vec<String>>>,
-) -> io::Result<()> {
+) -> io::Result<i32> {
     let help_msg = "Usage: history [optional arguments]\n\
       If no arguments are given, it will list all the command history it has.\n\
       If <number> is given, it will list the last x commands in the command history.\n\
       If -r <path> is given, it will load the lines in that path as command history.\n\
-      If -w <path> is given, it will write all command history in that path.\n\"
-      If -a <path> is given, it will append all command history in that path.\n"
+      If -w <path> is given, it will write all command history in that path.\n\
+      If -a <path> is given, it will append all command history in that path.\n\
+      If -c is given, it will clear the command history.\n\
+      If -d <number> is given, it will delete that entry from the command history.\n\
+      If $HISTTIMEFORMAT is set, each entry shows when it was run, formatted by strftime.\n"
         .as_bytes();
 
     if args.len() > 2 {
-        return pipes.error.write_all(help_msg);
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    }
+
+    match args.as_slice() {
+        [flag] if flag == "-c" => {
+            append_history
+                .lock()
+                .expect("Failed to lock append history!")
+                .clear();
+            editor
+                .lock()
+                .expect("Failed to lock the editor!")
+                .clear_history()
+                .map_err(io::Error::other)?;
+            history::clear_times();
+            return Ok(0);
+        }
+        [flag, number] if flag == "-d" => {
+            if history_delete(&editor, number.parse().unwrap_or(0))? {
+                return Ok(0);
+            }
+            pipes.error.write_all(
+                format!("history: {}: history position out of range\n", number).as_bytes(),
+            )?;
+            return Ok(1);
+        }
+        _ => {}
     }
 
     let number = args.first().and_then(|a| a.parse().ok());
@@ -331,13 +1007,14 @@ fn handle_history(
         None
     };
 
-    if args.first().is_some()
+    if !args.is_empty()
         && number.is_none()
         && read_path.is_none()
         && write_path.is_none()
         && append_path.is_none()
     {
-        return pipes.error.write_all(help_msg);
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
     }
 
     let history = editor
@@ -348,16 +1025,20 @@ fn handle_history(
         .cloned()
         .collect_vec();
 
-    if history_read(Arc::clone(&editor), read_path) {
-        return Ok(());
-    }
-
-    if history_write(Arc::clone(&editor), write_path) {
-        return Ok(());
-    }
-
-    if history_append(append_history, append_path) {
-        return Ok(());
+    let done = match (read_path, write_path) {
+        (Some(_), _) => history_read(Arc::clone(&editor), read_path),
+        (_, Some(_)) => history_write(Arc::clone(&editor), write_path),
+        _ => history_append(append_history, append_path),
+    };
+    match done {
+        Ok(true) => return Ok(0),
+        Ok(false) => {}
+        Err(err) => {
+            pipes
+                .error
+                .write_all(format!("history: {}: {}\n", args[1], err).as_bytes())?;
+            return Ok(1);
+        }
     }
 
     let entries = if let Some(num) = number {
@@ -372,361 +1053,3450 @@ fn handle_history(
         history.iter().enumerate().collect_vec()
     };
 
+    // Like in bash, $HISTTIMEFORMAT puts when each entry went in before it.
+    let format = vars::get("HISTTIMEFORMAT");
     for (index, entry) in entries {
+        let time = match (&format, history::time(index)) {
+            (Some(format), Some(time)) => history::format_time(time, format),
+            (Some(_), None) => "?? ".to_string(),
+            (None, _) => String::new(),
+        };
         pipes
             .output
-            .write_all(format!("    {}  {}\n", index + 1, entry).as_bytes())?;
+            .write_all(format!("    {}  {}{}\n", index + 1, time, entry).as_bytes())?;
     }
-    Ok(())
+    Ok(0)
 }
 
-fn handle_type(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<()> {
-    let help_msg = "Usage: type [command: required]\n".as_bytes();
+fn handle_fc(args: Vec<String>, pipes: &mut IOPipes, session: Session) -> io::Result<i32> {
+    let help_msg = "Usage: fc -s [old=new...] [command]\n\
+      Reruns the last command, or the last one starting with command or with\n\
+      that history number, with every old replaced by new, printing it first.\n"
+        .as_bytes();
 
-    if args.len() != 1 {
-        return pipes.error.write_all(help_msg);
+    let Some((_, args)) = args.split_first().filter(|(flag, _)| *flag == "-s") else {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    };
+    let split = args
+        .iter()
+        .position(|arg| !arg.contains('='))
+        .unwrap_or(args.len());
+    let (substitutions, which) = args.split_at(split);
+    if which.len() > 1 {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
     }
 
-    let Some(cmd) = args.first() else {
-        return pipes.error.write_all(help_msg);
+    let mut history = session
+        .editor
+        .lock()
+        .expect("Couldn't lock the editor!")
+        .history()
+        .iter()
+        .cloned()
+        .collect_vec();
+    // The line that ran this is in the history already.
+    if session.interactive {
+        history.pop();
+    }
+
+    let found = match which.first() {
+        None => history.last(),
+        Some(which) => match which.parse::<usize>() {
+            Ok(number) => number.checked_sub(1).and_then(|index| history.get(index)),
+            Err(_) => history
+                .iter()
+                .rev()
+                .find(|line| line.starts_with(which.as_str())),
+        },
+    };
+    let Some(command) = found else {
+        let which = which.first().map(String::as_str).unwrap_or("-s");
+        pipes
+            .error
+            .write_all(format!("fc: {}: no command found\n", which).as_bytes())?;
+        return Ok(1);
+    };
+
+    let mut command = command.clone();
+    for substitution in substitutions {
+        let (old, new) = substitution.split_once('=').expect("Split at an =");
+        if !old.is_empty() {
+            command = command.replace(old, new);
+        }
+    }
+    pipes.error.write_all(format!("{}\n", command).as_bytes())?;
+    if session.interactive {
+        let mut editor = session.editor.lock().expect("Couldn't lock the editor!");
+        add_history_entry(&mut editor, &command, history::now());
+    }
+    run_lines(&command, &session, pipes)
+}
+
+fn handle_alias(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: alias [name[=value]...]\n\
+      Makes name stand for value when it starts a command. With no arguments,\n\
+      lists every alias; with just a name, shows that one.\n"
+        .as_bytes();
+
+    if args.is_empty() {
+        for (name, value) in aliases::snapshot() {
+            let line = format!("alias {}={}\n", name, quote_for_display(&value));
+            pipes.output.write_all(line.as_bytes())?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !aliases::is_valid(name) {
+            pipes
+                .error
+                .write_all(format!("alias: {}: invalid alias name\n", name).as_bytes())?;
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+        match (value, aliases::get(name)) {
+            (Some(value), _) => aliases::set(name, value.to_string()),
+            (None, Some(value)) => {
+                let line = format!("alias {}={}\n", name, quote_for_display(&value));
+                pipes.output.write_all(line.as_bytes())?;
+            }
+            (None, None) => {
+                pipes
+                    .error
+                    .write_all(format!("alias: {}: not found\n", name).as_bytes())?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+fn handle_unalias(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: unalias [-a] name...\n\
+      Removes the aliases named, or with -a, every alias.\n"
+        .as_bytes();
+
+    match args.as_slice() {
+        [] => {
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+        [flag] if flag == "-a" => {
+            aliases::clear();
+            return Ok(0);
+        }
+        _ => {}
+    }
+
+    let mut status = 0;
+    for name in &args {
+        if !aliases::remove(name) {
+            pipes
+                .error
+                .write_all(format!("unalias: {}: not found\n", name).as_bytes())?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+fn handle_tt(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: tt name...\n\
+      Renames the tmux or screen window, or outside of them, titles the terminal.\n"
+        .as_bytes();
+
+    if args.is_empty() {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    }
+    if let Err(err) = terminal::set_window_name(&args.join(" ")) {
+        pipes.error.write_all(format!("tt: {}\n", err).as_bytes())?;
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+fn handle_jobs(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: jobs [-l|-p] [-r|-s] [%job...] or jobs -o [%job...]\n\
+      Lists the jobs (default: all) started with &, and whether they are still running.\n\
+      -r lists only the running ones, -s only the stopped ones.\n\
+      -l adds the process group, how long each has run, and its CPU share and resident memory.\n\
+      -p prints just the process group of each, which kill takes negated.\n\
+      -o prints what the jobs printed while set -o bufferjobs held it back instead.\n"
+        .as_bytes();
+
+    let (mut held, mut long, mut pids, mut only) = (false, false, false, None);
+    let mut specs = args.as_slice();
+    // Scripts run them together as bash allows, like -rp.
+    while let Some((flag, tail)) = specs.split_first() {
+        if flag == "--" {
+            specs = tail;
+            break;
+        }
+        let Some(letters) = flag.strip_prefix('-').filter(|letters| !letters.is_empty()) else {
+            break;
+        };
+        for letter in letters.chars() {
+            match letter {
+                'o' => held = true,
+                'l' => long = true,
+                'p' => pids = true,
+                'r' => only = Some(State::Running),
+                's' => only = Some(State::Stopped),
+                _ => {
+                    pipes.error.write_all(help_msg)?;
+                    return Ok(2);
+                }
+            }
+        }
+        specs = tail;
+    }
+    if held {
+        if long || pids || only.is_some() {
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+        return print_held(specs, pipes);
+    }
+    if !pids && only.is_none() && specs.is_empty() {
+        for line in jobs::list(true, long) {
+            pipes.output.write_all(format!("{}\n", line).as_bytes())?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    let ids = match specs {
+        [] => jobs::ids(),
+        specs => {
+            let mut ids = Vec::new();
+            for spec in specs {
+                match find_job("jobs", Some(spec), pipes)? {
+                    Some(id) => ids.push(id),
+                    None => status = 1,
+                }
+            }
+            ids
+        }
     };
+    for id in ids {
+        if only.is_some_and(|only| jobs::state(id) != Some(only)) {
+            continue;
+        }
+        // A job that only ran builtins has no processes to show.
+        let line = match pids {
+            true => jobs::pgid(id).map(|pgid| pgid.to_string()),
+            false => jobs::describe(id, long),
+        };
+        if let Some(line) = line {
+            pipes.output.write_all(format!("{}\n", line).as_bytes())?;
+        }
+    }
+    Ok(status)
+}
+
+// `jobs -o`: what the jobs named (default: all) printed while `bufferjobs`
+// held it back.
+fn print_held(specs: &[String], pipes: &mut IOPipes) -> io::Result<i32> {
+    let named = match specs {
+        [] => jobs::ids()
+            .into_iter()
+            .map(|id| (String::new(), Some(id)))
+            .collect_vec(),
+        specs => specs
+            .iter()
+            .map(|spec| (spec.clone(), jobs::resolve(spec).ok()))
+            .collect_vec(),
+    };
+    let mut status = 0;
+    for (spec, id) in named {
+        match id.and_then(jobs::take_held) {
+            Some(held) => pipes.output.write_all(&held)?,
+            // Gone since it was listed.
+            None if spec.is_empty() => {}
+            None => {
+                pipes
+                    .error
+                    .write_all(format!("jobs: {}: no such job\n", spec).as_bytes())?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+// The job a builtin's argument names, or without one the current job,
+// complaining like bash if there is no such job.
+fn find_job(name: &str, spec: Option<&str>, pipes: &mut IOPipes) -> io::Result<Option<JobId>> {
+    match jobs::resolve(spec.unwrap_or("%+")) {
+        Ok(id) => Ok(Some(id)),
+        Err(err) => {
+            pipes.error.write_all(
+                format!("{}: {}: {}\n", name, spec.unwrap_or("current"), err).as_bytes(),
+            )?;
+            Ok(None)
+        }
+    }
+}
+
+fn handle_fg(args: Vec<String>, pipes: &mut IOPipes, session: Session) -> io::Result<i32> {
+    let help_msg = "Usage: fg [%job]\n\
+      Brings a job (default: the current one) back to the foreground, continuing it if it was stopped.\n"
+        .as_bytes();
 
-    let externals = get_external_executables();
+    let spec = match args.as_slice() {
+        [] => None,
+        [spec] => Some(spec.as_str()),
+        _ => {
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+    };
+    let Some(id) = find_job("fg", spec, pipes)? else {
+        return Ok(1);
+    };
+    if let Some(State::Done(_)) = jobs::state(id) {
+        pipes.error.write_all(b"fg: job has terminated\n")?;
+        return Ok(1);
+    }
+    let command = jobs::command(id).unwrap_or_default();
+    pipes
+        .output
+        .write_all(format!("{}\n", command).as_bytes())?;
 
-    if BUILTINS.contains(&cmd.as_str()) {
+    // Ctrl-C and Ctrl-Z are for the job now, if it has processes to get them.
+    let pgid = jobs::pgid(id).filter(|_| session.interactive && io::stdin().is_terminal());
+    if let Some(pgid) = pgid {
+        terminal::save_modes();
+        _ = terminal::give_to(pgid);
+    }
+    let resumed = jobs::resume(id);
+    let state = resumed.as_ref().ok().and_then(|_| jobs::wait(id, true));
+    if pgid.is_some() {
+        _ = terminal::take_back();
+    }
+    if let Err(err) = resumed {
         pipes
-            .output
-            .write_all(format!("{} is a shell builtin\n", cmd).as_bytes())
-    } else if let Some(path) = externals.get(cmd) {
+            .error
+            .write_all(format!("fg: {}\n", error_message(&err)).as_bytes())?;
+        return Ok(1);
+    }
+
+    match state {
+        Some(State::Done(status)) => {
+            jobs::forget(id);
+            Ok(status)
+        }
+        Some(State::Stopped) => {
+            terminal::restore_modes();
+            jobs::touch(id);
+            jobs::told_stopped(id);
+            if let Some(line) = jobs::describe(id, false) {
+                pipes.error.write_all(format!("\n{}\n", line).as_bytes())?;
+            }
+            Ok(128 + libc::SIGTSTP)
+        }
+        // Ctrl-C, for a job without processes of its own to send it to.
+        _ => Ok(128 + libc::SIGINT),
+    }
+}
+
+fn handle_bg(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let specs = match args.is_empty() {
+        true => vec![None],
+        false => args.iter().map(|spec| Some(spec.as_str())).collect_vec(),
+    };
+    let mut status = 0;
+    for spec in specs {
+        let Some(id) = find_job("bg", spec, pipes)? else {
+            status = 1;
+            continue;
+        };
+        let number = jobs::number(id).unwrap_or_default();
+        match jobs::state(id) {
+            Some(State::Running) => pipes
+                .error
+                .write_all(format!("bg: job {} already in background\n", number).as_bytes())?,
+            Some(State::Stopped) => {
+                if let Err(err) = jobs::resume(id) {
+                    pipes
+                        .error
+                        .write_all(format!("bg: {}\n", error_message(&err)).as_bytes())?;
+                    status = 1;
+                    continue;
+                }
+                let command = jobs::command(id).unwrap_or_default();
+                pipes
+                    .output
+                    .write_all(format!("[{}]+ {} &\n", number, command).as_bytes())?;
+            }
+            _ => {
+                pipes.error.write_all(b"bg: job has terminated\n")?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+fn handle_wait(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    // Like bash, a job waited for is done with, and gets no notice.
+    let wait = |id| match jobs::wait(id, false) {
+        Some(State::Done(status)) => {
+            jobs::forget(id);
+            status
+        }
+        _ if signals::interrupted() => 128 + libc::SIGINT,
+        _ => 127,
+    };
+
+    if args.is_empty() {
+        for id in jobs::ids() {
+            wait(id);
+            if signals::interrupted() {
+                return Ok(128 + libc::SIGINT);
+            }
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in &args {
+        let id = if arg.starts_with('%') {
+            find_job("wait", Some(arg), pipes)?
+        } else if let Ok(pid) = arg.parse::<u32>() {
+            let id = jobs::with_pid(pid);
+            if id.is_none() {
+                pipes.error.write_all(
+                    format!("wait: pid {} is not a child of this shell\n", pid).as_bytes(),
+                )?;
+            }
+            id
+        } else {
+            pipes
+                .error
+                .write_all(format!("wait: `{}': not a pid or valid job spec\n", arg).as_bytes())?;
+            return Ok(2);
+        };
+        status = match id {
+            Some(id) => wait(id),
+            None => 127,
+        };
+        if signals::interrupted() {
+            break;
+        }
+    }
+    Ok(status)
+}
+
+fn handle_disown(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: disown [-a] [%job...]\n\
+      Takes jobs (default: the current one, with -a: all) out of the table, leaving them running.\n"
+        .as_bytes();
+
+    if args.first().is_some_and(|arg| arg == "-a") {
+        if args.len() > 1 {
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+        jobs::ids().into_iter().for_each(jobs::forget);
+        return Ok(0);
+    }
+    let specs = match args.is_empty() {
+        true => vec![None],
+        false => args.iter().map(|spec| Some(spec.as_str())).collect_vec(),
+    };
+    let mut status = 0;
+    for spec in specs {
+        match find_job("disown", spec, pipes)? {
+            Some(id) => jobs::forget(id),
+            None => status = 1,
+        }
+    }
+    Ok(status)
+}
+
+fn handle_kill(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg =
+        "Usage: kill [-s sigspec | -n signum | -sigspec] pid | %job... or kill -l [sigspec...]\n\
+      Sends a signal (default: TERM) to processes, or to all of a job's.\n\
+      -l: list the signals, or give the name or number of each one named\n"
+            .as_bytes();
+
+    let (spec, targets) = match args.as_slice() {
+        [] => {
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+        [flag, specs @ ..] if flag == "-l" => return list_signals(specs, pipes),
+        [flag, spec, targets @ ..] if flag == "-s" || flag == "-n" => (spec.as_str(), targets),
+        [flag, targets @ ..] if flag == "--" => ("TERM", targets),
+        [flag, targets @ ..] if flag.len() > 1 && flag.starts_with('-') => (&flag[1..], targets),
+        targets => ("TERM", targets),
+    };
+    let Some(signal) = signals::number(spec) else {
         pipes
-            .output
-            .write_all(format!("{} is {}\n", cmd, path.to_string_lossy()).as_bytes())
+            .error
+            .write_all(format!("kill: {}: invalid signal specification\n", spec).as_bytes())?;
+        return Ok(1);
+    };
+    let targets = match targets {
+        [end, targets @ ..] if end == "--" => targets,
+        targets => targets,
+    };
+    if targets.is_empty() {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    }
+
+    let mut status = 0;
+    for target in targets {
+        let sent = if target.starts_with('%') {
+            let Some(id) = find_job("kill", Some(target), pipes)? else {
+                status = 1;
+                continue;
+            };
+            jobs::signal(id, signal).map_err(|err| (target.clone(), err))
+        } else if let Ok(pid) = target.parse::<i32>() {
+            match unsafe { libc::kill(pid, signal) } {
+                0 => Ok(()),
+                _ => Err((format!("({})", pid), io::Error::last_os_error())),
+            }
+        } else {
+            pipes.error.write_all(
+                format!("kill: {}: arguments must be process or job IDs\n", target).as_bytes(),
+            )?;
+            status = 1;
+            continue;
+        };
+        if let Err((target, err)) = sent {
+            pipes
+                .error
+                .write_all(format!("kill: {} - {}\n", target, error_message(&err)).as_bytes())?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+// `kill -l`: every signal, or the name of each signal number or exit status
+// from one and the number of each name.
+fn list_signals(specs: &[String], pipes: &mut IOPipes) -> io::Result<i32> {
+    if specs.is_empty() {
+        for (number, name) in signals::list() {
+            pipes
+                .output
+                .write_all(format!("{:>2}) SIG{}\n", number, name).as_bytes())?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for spec in specs {
+        let found = match spec.parse::<i32>() {
+            // What a process a signal ended exits with is 128 past it.
+            Ok(number) => {
+                signals::name(if number > 128 { number - 128 } else { number }).map(str::to_string)
+            }
+            Err(_) => signals::number(spec).map(|number| number.to_string()),
+        };
+        match found {
+            Some(found) => pipes.output.write_all(format!("{}\n", found).as_bytes())?,
+            None => {
+                pipes.error.write_all(
+                    format!("kill: {}: invalid signal specification\n", spec).as_bytes(),
+                )?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// How many sourced files can be sourcing one another at once.
+const MAX_SOURCE_DEPTH: usize = 64;
+
+fn handle_source(
+    name: &str,
+    args: Vec<String>,
+    pipes: &mut IOPipes,
+    session: Session,
+) -> io::Result<i32> {
+    let Some((path, args)) = args.split_first() else {
+        let help_msg = format!(
+            "Usage: {} file [arg...]\n\
+            Runs the file's commands in this shell, with the args as $1 onwards.\n",
+            name
+        );
+        pipes.error.write_all(help_msg.as_bytes())?;
+        return Ok(2);
+    };
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let refusal = if session.sourcing.contains(&canonical) {
+        let cycle = session
+            .sourcing
+            .iter()
+            .skip_while(|sourced| **sourced != canonical)
+            .chain(iter::once(&canonical))
+            .map(|sourced| sourced.to_string_lossy())
+            .join(" -> ");
+        Some(format!("already being sourced: {}", cycle))
+    } else if session.sourcing.len() >= MAX_SOURCE_DEPTH {
+        Some(format!(
+            "sourced files nested over {} deep",
+            MAX_SOURCE_DEPTH
+        ))
     } else {
+        None
+    };
+    if let Some(refusal) = refusal {
         pipes
             .error
-            .write_all(format!("{}: not found\n", cmd).as_bytes())
+            .write_all(format!("{}: {}: {}\n", name, path, refusal).as_bytes())?;
+        return Ok(1);
+    }
+
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(err) => {
+            pipes
+                .error
+                .write_all(format!("{}: {}: {}\n", name, path, err).as_bytes())?;
+            return Ok(1);
+        }
+    };
+
+    // Without args of its own, the file sees the caller's.
+    let saved = (!args.is_empty()).then(params::positional);
+    if saved.is_some() {
+        params::set_positional(args.to_vec());
+    }
+    let status = source_lines(Path::new(path), &script, &session, pipes, false)?;
+    if let Some(saved) = saved {
+        params::set_positional(saved);
+    }
+    match status {
+        ControlFlow::Continue(status) | ControlFlow::Break(status) => Ok(status),
+    }
+}
+
+fn handle_replay(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: replay [history number: required]\n\
+      Shows the directory, expanded argv and environment set -o replay recorded for a line.\n"
+        .as_bytes();
+
+    let Some(index) = args.first().and_then(|a| a.parse().ok()) else {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    };
+    if args.len() != 1 {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    }
+
+    match replay::find(index) {
+        Some(snapshot) => {
+            pipes
+                .output
+                .write_all(replay::describe(&snapshot).as_bytes())?;
+            Ok(0)
+        }
+        None => {
+            pipes
+                .error
+                .write_all(format!("replay: {}: no snapshot recorded\n", index).as_bytes())?;
+            Ok(1)
+        }
+    }
+}
+
+fn handle_clip(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: clip [--in | --out]\n\
+      With --in (the default), copy standard input to the clipboard.\n\
+      With --out, print the clipboard contents.\n"
+        .as_bytes();
+
+    match args.iter().map(String::as_str).collect_vec().as_slice() {
+        [] | ["--in"] => {
+            let mut data = Vec::new();
+            pipes.input.read_to_end(&mut data)?;
+            if let Err(err) = clipboard::copy(&data) {
+                pipes
+                    .error
+                    .write_all(format!("clip: {}\n", err).as_bytes())?;
+                return Ok(1);
+            }
+            Ok(0)
+        }
+        ["--out"] => match clipboard::paste() {
+            Ok(data) => {
+                pipes.output.write_all(&data)?;
+                Ok(0)
+            }
+            Err(err) => {
+                pipes
+                    .error
+                    .write_all(format!("clip: {}\n", err).as_bytes())?;
+                Ok(1)
+            }
+        },
+        _ => {
+            pipes.error.write_all(help_msg)?;
+            Ok(2)
+        }
+    }
+}
+
+fn handle_set(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: set [-o | +o] [option]\n\
+      With no option, list every option and whether it is on.\n\
+      -o <option> turns an option on, +o <option> turns it off.\n\
+      set -- [arg...] replaces $1, $2 and so on with the args.\n"
+        .as_bytes();
+
+    if let Some(("--", positional)) = args.split_first().map(|(a, rest)| (a.as_str(), rest)) {
+        params::set_positional(positional.to_vec());
+        return Ok(0);
+    }
+    match args.iter().map(String::as_str).collect_vec().as_slice() {
+        [] | ["-o"] | ["+o"] => {
+            for (name, description) in options::OPTIONS {
+                let state = if options::is_set(name) { "on" } else { "off" };
+                pipes
+                    .output
+                    .write_all(format!("{:<16}{:<5}{}\n", name, state, description).as_bytes())?;
+            }
+            Ok(0)
+        }
+        [flag @ ("-o" | "+o"), name] => {
+            if options::set(name, *flag == "-o") {
+                Ok(0)
+            } else {
+                pipes
+                    .error
+                    .write_all(format!("set: {}: invalid option name\n", name).as_bytes())?;
+                Ok(1)
+            }
+        }
+        _ => {
+            pipes.error.write_all(help_msg)?;
+            Ok(2)
+        }
+    }
+}
+
+/// How long `watch` waits between runs unless given -n.
+const DEFAULT_WATCH_SECS: f64 = 2.0;
+
+// Sleeps in small steps so Ctrl-C doesn't have to wait for the whole interval.
+fn sleep_unless_interrupted(duration: Duration) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        if signals::interrupted() {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(50).min(duration.saturating_sub(started.elapsed())));
+    }
+    !signals::interrupted()
+}
+
+// Splits `[-n value] [--] command...`. The command is kept as the words it
+// already is, not joined up and parsed again, which would lose its quoting.
+fn parse_repeated<T: FromStr>(args: &[String]) -> Option<(Option<T>, &[String])> {
+    match args {
+        [flag, command @ ..] if flag == "--" && !command.is_empty() => Some((None, command)),
+        [flag, value, command @ ..] if flag == "-n" => {
+            let command = skip_end_of_options(command);
+            match command.is_empty() {
+                true => None,
+                false => Some((Some(value.parse().ok()?), command)),
+            }
+        }
+        [first, ..] if first != "-n" => Some((None, args)),
+        _ => None,
+    }
+}
+
+// Runs a command that is already words, for `repeat` and the like, looked up
+// the way a line's command is.
+fn run_argv(argv: &[String], session: &Session, pipes: &IOPipes) -> io::Result<i32> {
+    let Some((cmd, args)) = argv.split_first() else {
+        return Ok(0);
+    };
+    handle_cmd(
+        cmd,
+        args.to_vec(),
+        Vec::new(),
+        session.clone(),
+        pipes.try_clone()?,
+    )?
+    .wait()
+}
+
+fn handle_watch(args: Vec<String>, pipes: &mut IOPipes, session: Session) -> io::Result<i32> {
+    let help_msg = "Usage: watch [-n seconds] command...\n\
+      Clears the screen and reruns the command every 2 seconds (or -n) until Ctrl-C.\n"
+        .as_bytes();
+
+    // Negative, infinite or too long to sleep for is a usage error, not a
+    // panic in the middle of the loop.
+    let parsed = parse_repeated::<f64>(&args).and_then(|(secs, command)| {
+        let secs = secs.unwrap_or(DEFAULT_WATCH_SECS);
+        let interval = Duration::try_from_secs_f64(secs).ok()?;
+        Some((secs, interval, command))
+    });
+    let Some((secs, interval, command)) = parsed else {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    };
+    let shown = command.iter().map(|word| quote_for_display(word)).join(" ");
+
+    loop {
+        pipes
+            .output
+            .write_all(format!("\x1b[H\x1b[2JEvery {:.1}s: {}\n\n", secs, shown).as_bytes())?;
+        run_argv(command, &session, pipes)?;
+        if !sleep_unless_interrupted(interval) {
+            return Ok(130);
+        }
+    }
+}
+
+fn handle_repeat(args: Vec<String>, pipes: &mut IOPipes, session: Session) -> io::Result<i32> {
+    let help_msg = "Usage: repeat -n count command...\n\
+      Runs the command count times in a row, stopping early on Ctrl-C.\n"
+        .as_bytes();
+
+    let Some((Some(count), command)) = parse_repeated::<u64>(&args) else {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    };
+
+    let mut status = 0;
+    for _ in 0..count {
+        if signals::interrupted() {
+            return Ok(130);
+        }
+        status = run_argv(command, &session, pipes)?;
+    }
+    Ok(status)
+}
+
+/// The longest `retry` waits between attempts, however far -b grows the delay.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn handle_retry(args: Vec<String>, pipes: &mut IOPipes, session: Session) -> io::Result<i32> {
+    let help_msg = "Usage: retry [-n attempts] [-d seconds] [-b factor] command...\n\
+      Reruns the command until it succeeds, at most -n times (default: 3).\n\
+      Waits -d seconds (default: 1) between attempts, multiplied by -b (default: 1) each time, up to a day.\n"
+        .as_bytes();
+
+    let mut attempts: u32 = 3;
+    let mut delay = Duration::from_secs(1);
+    let mut backoff = 1.0;
+
+    let mut rest = args.as_slice();
+    while let [flag, value, tail @ ..] = rest {
+        if flag == "--" {
+            rest = &rest[1..];
+            break;
+        }
+        let parsed = match flag.as_str() {
+            "-n" => value.parse().ok().filter(|n| *n > 0).map(|n| attempts = n),
+            "-d" => value
+                .parse()
+                .ok()
+                .and_then(|d| Duration::try_from_secs_f64(d).ok())
+                .filter(|d| *d <= MAX_RETRY_DELAY)
+                .map(|d| delay = d),
+            "-b" => value
+                .parse()
+                .ok()
+                .filter(|b: &f64| b.is_finite() && *b >= 1.0)
+                .map(|b| backoff = b),
+            _ => break,
+        };
+        if parsed.is_none() {
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+        rest = tail;
+    }
+
+    if rest.is_empty() {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    }
+
+    let mut status = 0;
+    for attempt in 1..=attempts {
+        status = run_argv(rest, &session, pipes)?;
+        if status == 0 || attempt == attempts {
+            break;
+        }
+
+        pipes.error.write_all(
+            format!(
+                "retry: attempt {}/{} exited with {}, retrying in {:.1}s\n",
+                attempt,
+                attempts,
+                status,
+                delay.as_secs_f64()
+            )
+            .as_bytes(),
+        )?;
+        if !sleep_unless_interrupted(delay) {
+            return Ok(130);
+        }
+        delay = Duration::try_from_secs_f64(delay.as_secs_f64() * backoff)
+            .map_or(MAX_RETRY_DELAY, |grown| grown.min(MAX_RETRY_DELAY));
+    }
+    Ok(status)
+}
+
+// What an argument takes out of ARG_MAX: its bytes, the NUL after them and
+// the pointer to them in argv.
+fn argument_size(arg: &OsStr) -> usize {
+    arg.len() + 1 + mem::size_of::<usize>()
+}
+
+fn arg_max() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_ARG_MAX) } {
+        max if max > 0 => max as usize,
+        _ => 128 * 1024,
+    }
+}
+
+fn environment_size() -> usize {
+    env::vars_os()
+        .map(|(name, value)| argument_size(&name) + value.len() + 1)
+        .sum()
+}
+
+// Room for arguments under the kernel's ARG_MAX once the environment is
+// counted, less the 2048 bytes of headroom POSIX has xargs leave.
+fn argument_space() -> usize {
+    arg_max()
+        .saturating_sub(environment_size())
+        .saturating_sub(2048)
+}
+
+// E2BIG on its own says nothing of how far over the limit a command went or
+// what to do about it.
+fn argument_list_too_long(cmd: &str, argv: &[String]) -> String {
+    let size: usize = argv.iter().map(|arg| argument_size(arg.as_ref())).sum();
+    format!(
+        "{cmd}: argument list too long: {} of arguments ({} of them) and {} of environment, over the {} limit\n  run it in batches with `chunked {cmd} -- ...`, or quote the glob if {cmd} can match it itself\n",
+        preview::size(size as u64),
+        argv.len(),
+        preview::size(environment_size() as u64),
+        preview::size(arg_max() as u64),
+    )
+}
+
+fn handle_chunked(args: Vec<String>, pipes: &mut IOPipes, session: Session) -> io::Result<i32> {
+    let help_msg = "Usage: chunked command [arg...] -- arg...\n\
+      Runs the command with the args after -- split across as many runs as it takes\n\
+      to stay under the system's limit on argument size, like xargs. Exits with the\n\
+      worst status.\n"
+        .as_bytes();
+
+    let Some(split) = args.iter().position(|arg| arg == "--").filter(|at| *at > 0) else {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    };
+    let (command, items) = (&args[..split], &args[split + 1..]);
+    let fixed: usize = command.iter().map(|arg| argument_size(arg.as_ref())).sum();
+    let space = argument_space().saturating_sub(fixed);
+
+    let mut batches = vec![Vec::new()];
+    let mut used = 0;
+    for item in items {
+        let size = argument_size(item.as_ref());
+        if used + size > space && batches.last().is_some_and(|batch| !batch.is_empty()) {
+            batches.push(Vec::new());
+            used = 0;
+        }
+        batches
+            .last_mut()
+            .expect("There is always a batch")
+            .push(item.clone());
+        used += size;
+    }
+
+    let mut worst = 0;
+    for batch in batches {
+        if signals::interrupted() {
+            return Ok(130);
+        }
+        let args = command[1..].iter().cloned().chain(batch).collect();
+        let status = handle_cmd(
+            &command[0],
+            args,
+            Vec::new(),
+            session.clone(),
+            pipes.try_clone()?,
+        )?
+        .wait()?;
+        worst = worst.max(status);
+    }
+    Ok(worst)
+}
+
+fn handle_type(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: type [command: required]\n".as_bytes();
+
+    if args.len() != 1 {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    }
+
+    let Some(cmd) = args.first() else {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    };
+
+    if let Some(value) = aliases::get(cmd) {
+        pipes
+            .output
+            .write_all(format!("{} is aliased to `{}'\n", cmd, value).as_bytes())?;
+    } else if BUILTINS.contains(&cmd.as_str()) {
+        pipes
+            .output
+            .write_all(format!("{} is a shell builtin\n", cmd).as_bytes())?;
+    } else if let Some(function) = functions::load(cmd) {
+        let line = format!(
+            "{} is a function, autoloaded from {}\n",
+            cmd,
+            function.path.to_string_lossy()
+        );
+        pipes.output.write_all(line.as_bytes())?;
+    } else if let Some(path) = find_executable(cmd) {
+        pipes
+            .output
+            .write_all(format!("{} is {}\n", cmd, path.to_string_lossy()).as_bytes())?;
+    } else {
+        pipes
+            .error
+            .write_all(format!("{}: not found\n", cmd).as_bytes())?;
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+// Every place a name could resolve to, in the order they are tried, and
+// whether it is there. The first hit is what runs.
+fn resolution_trace(name: &str) -> (Vec<String>, bool) {
+    let mut trace = Vec::new();
+    let mut found = None;
+    let hit = |trace: &mut Vec<String>, found: &mut Option<String>, what: String| match found {
+        Some(winner) => trace.push(format!("{}: hit, shadowed by {}", what, winner)),
+        None => {
+            trace.push(format!("{}: hit, this is what runs", what));
+            *found = Some(what);
+        }
+    };
+
+    if name.contains('/') {
+        let path = Path::new(name);
+        match is_executable(path) {
+            true => hit(&mut trace, &mut found, name.to_string()),
+            false if path.exists() => trace.push(format!("{}: not executable", name)),
+            false => trace.push(format!("{}: miss", name)),
+        }
+        return (trace, found.is_some());
+    }
+
+    // Only the first word of a line is looked up as an alias, and is then
+    // replaced before anything else is tried.
+    match aliases::get(name) {
+        Some(value) => hit(&mut trace, &mut found, format!("alias for `{}'", value)),
+        None => trace.push("alias: miss".to_string()),
+    }
+
+    // A function is only called when no builtin has its name.
+    let builtin = BUILTINS.contains(&name);
+    let function_hit =
+        |trace: &mut Vec<String>, found: &mut Option<String>, what: String| match builtin
+            && found.is_none()
+        {
+            true => trace.push(format!("{}: hit, shadowed by builtin", what)),
+            false => hit(trace, found, what),
+        };
+    // One read already is called from then on, whatever $FPATH says now.
+    match functions::loaded(name) {
+        Some(function) => function_hit(
+            &mut trace,
+            &mut found,
+            format!("function, loaded from {}", function.path.to_string_lossy()),
+        ),
+        None => trace.push("function: not loaded".to_string()),
+    }
+    let fpath = vars::get("FPATH").unwrap_or_default();
+    for dir in split_paths(&fpath) {
+        let candidate = dir.join(name);
+        let shown = format!("{} (autoload)", candidate.to_string_lossy());
+        match candidate.is_file() {
+            true => function_hit(&mut trace, &mut found, shown),
+            false => trace.push(format!("{}: miss", shown)),
+        }
+    }
+
+    match builtin {
+        true => hit(&mut trace, &mut found, "builtin".to_string()),
+        false => trace.push("builtin: miss".to_string()),
+    }
+
+    let path = env::var_os("PATH").unwrap_or_default();
+    for dir in split_paths(&path) {
+        let candidate = dir.join(name);
+        let shown = candidate.to_string_lossy().to_string();
+        if is_executable(&candidate) {
+            hit(&mut trace, &mut found, shown);
+        } else if candidate.exists() {
+            trace.push(format!("{}: not executable", shown));
+        } else {
+            trace.push(format!("{}: miss", shown));
+        }
+    }
+
+    if let Ok(cwd) = env::current_dir() {
+        let candidate = cwd.join(name);
+        let shown = candidate.to_string_lossy().to_string();
+        if !paths::trusted(&cwd) {
+            trace.push(format!("{}: skipped, . is not in $TRUSTED_DIRS", shown));
+        } else if is_executable(&candidate) {
+            hit(&mut trace, &mut found, shown);
+        } else {
+            trace.push(format!("{}: miss", shown));
+        }
+    }
+
+    (trace, found.is_some())
+}
+
+fn handle_whence(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let (verbose, names) = match args.split_first() {
+        Some((flag, names)) if flag == "-v" => (true, skip_end_of_options(names)),
+        _ => (false, skip_end_of_options(&args)),
+    };
+    if names.is_empty() {
+        pipes
+            .error
+            .write_all("Usage: whence [-v] name...\n".as_bytes())?;
+        return Ok(2);
+    }
+
+    let mut status = 0;
+    for name in names {
+        if verbose {
+            let (trace, found) = resolution_trace(name);
+            pipes.output.write_all(format!("{}:\n", name).as_bytes())?;
+            for step in trace {
+                pipes.output.write_all(format!("  {}\n", step).as_bytes())?;
+            }
+            if !found {
+                status = 1;
+            }
+        } else if BUILTINS.contains(&name.as_str()) {
+            pipes.output.write_all(format!("{}\n", name).as_bytes())?;
+        } else if let Some(path) = find_executable(name) {
+            pipes
+                .output
+                .write_all(format!("{}\n", path.to_string_lossy()).as_bytes())?;
+        } else {
+            pipes
+                .error
+                .write_all(format!("whence: {}: not found\n", name).as_bytes())?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+// `break [n]` and `continue [n]`, which leave word for the nth loop out.
+fn handle_jump(
+    name: &str,
+    args: Vec<String>,
+    pipes: &mut IOPipes,
+    session: Session,
+) -> io::Result<i32> {
+    let levels = match args.as_slice() {
+        [] => Some(1),
+        [levels] => levels.parse::<u32>().ok().filter(|levels| *levels > 0),
+        _ => None,
+    };
+    let Some(levels) = levels else {
+        pipes
+            .error
+            .write_all(format!("Usage: {} [n]\n", name).as_bytes())?;
+        return Ok(2);
+    };
+    if session.loops == 0 {
+        pipes.error.write_all(
+            format!(
+                "{}: only meaningful in a `for', `while', `until', or `select' loop\n",
+                name
+            )
+            .as_bytes(),
+        )?;
+        return Ok(0);
+    }
+
+    let levels = levels.min(session.loops);
+    *session.jump.lock().expect("Failed to lock jump!") = Some(match name {
+        "break" => Jump::Break(levels),
+        _ => Jump::Continue(levels),
+    });
+    Ok(0)
+}
+
+fn handle_return(args: Vec<String>, pipes: &mut IOPipes, session: Session) -> io::Result<i32> {
+    let status = match args.as_slice() {
+        [] => params::last_status(),
+        [status] => match status.parse::<i32>() {
+            // Only the low byte makes it into a status, like with exit.
+            Ok(status) => status & 0xff,
+            Err(_) => {
+                pipes.error.write_all(
+                    format!("return: {}: numeric argument required\n", status).as_bytes(),
+                )?;
+                return Ok(2);
+            }
+        },
+        _ => {
+            pipes.error.write_all("Usage: return [n]\n".as_bytes())?;
+            return Ok(2);
+        }
+    };
+    if !session.returnable {
+        pipes.error.write_all(
+            "return: can only `return' from a function or sourced script\n".as_bytes(),
+        )?;
+        return Ok(1);
+    }
+    *session.jump.lock().expect("Failed to lock jump!") = Some(Jump::Return(status));
+    Ok(status)
+}
+
+fn handle_read(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: read [-r] [name...]\n\
+      Reads a line from stdin and splits it on $IFS into the names, the last one\n\
+      getting the rest of the line (default: REPLY, the whole line).\n\
+      Without -r, a backslash escapes the next character or joins the next line.\n"
+        .as_bytes();
+
+    let (raw, names) = match args.split_first() {
+        Some((flag, names)) if flag == "-r" => (true, names),
+        _ => (false, args.as_slice()),
+    };
+    if names.iter().any(|name| !expand::is_name(name)) {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    }
+
+    // A byte at a time, so nothing after the line is taken from whatever
+    // reads next.
+    let mut line = String::new();
+    let mut bytes = Vec::new();
+    let mut byte = [0u8];
+    let mut escaped = false;
+    let complete = loop {
+        if pipes.input.read(&mut byte)? == 0 {
+            break false;
+        }
+        match byte[0] {
+            b'\n' if escaped => escaped = false,
+            b'\n' => break true,
+            b'\\' if !raw && !escaped => escaped = true,
+            byte => {
+                escaped = false;
+                bytes.push(byte);
+            }
+        }
+    };
+    line.push_str(&String::from_utf8_lossy(&bytes));
+    if !complete && line.is_empty() {
+        return Ok(1);
+    }
+
+    let Some((last, first)) = names.split_last() else {
+        vars::set("REPLY", line);
+        return Ok(if complete { 0 } else { 1 });
+    };
+    let ifs = vars::get("IFS").unwrap_or_else(|| " \t\n".to_string());
+    let is_ifs = |c: char| ifs.contains(c);
+    let mut rest = line.trim_start_matches(is_ifs);
+    for name in first {
+        let (field, after) = rest.split_once(is_ifs).unwrap_or((rest, ""));
+        vars::set(name, field.to_string());
+        rest = after.trim_start_matches(is_ifs);
+    }
+    vars::set(last, rest.trim_end_matches(is_ifs).to_string());
+    Ok(if complete { 0 } else { 1 })
+}
+
+fn handle_isatty(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg =
+        "Usage: isatty [fd]\n  Succeeds if the fd (default: 0) is a terminal, like test -t.\n"
+            .as_bytes();
+
+    let fd = match args.as_slice() {
+        [] => Some(0),
+        [fd] => fd.parse::<RawFd>().ok().filter(|fd| *fd >= 0),
+        _ => None,
+    };
+    let Some(fd) = fd else {
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    };
+
+    // The builtin's own streams, which redirections and pipes may have
+    // replaced, rather than the shell's.
+    let terminal = match fd {
+        0 => pipes.input.is_terminal(),
+        1 => pipes.output.is_terminal(),
+        2 => pipes.error.is_terminal(),
+        fd => match pipes.numbered.iter().find(|(number, _)| *number == fd) {
+            Some((_, source)) => source.is_terminal(),
+            None => unsafe { libc::isatty(fd) == 1 },
+        },
+    };
+    Ok(if terminal { 0 } else { 1 })
+}
+
+fn handle_trap(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: trap [-p] [action] [condition...]\n\
+      Runs the action before every simple command (DEBUG) or after one fails\n\
+      outside a condition or the left of && and || (ERR), with $TRAP_COMMAND\n\
+      holding the command. An action of - resets the conditions.\n"
+        .as_bytes();
+
+    let (action, conditions) = match args.as_slice() {
+        [] => (None, &[][..]),
+        [flag, conditions @ ..] if flag == "-p" => (None, conditions),
+        // A lone condition is reset, like with `-`.
+        [condition] => (Some("-"), slice::from_ref(condition)),
+        [action, conditions @ ..] => (Some(action.as_str()), conditions),
+    };
+    if let Some(unknown) = conditions
+        .iter()
+        .find(|condition| !traps::CONDITIONS.contains(&condition.as_str()))
+    {
+        pipes.error.write_all(
+            format!("trap: {}: only DEBUG and ERR can be trapped\n", unknown).as_bytes(),
+        )?;
+        pipes.error.write_all(help_msg)?;
+        return Ok(2);
+    }
+
+    let Some(action) = action else {
+        for (condition, action) in traps::snapshot() {
+            if conditions.is_empty() || conditions.contains(&condition) {
+                let line = format!("trap -- {} {}\n", quote_for_display(&action), condition);
+                pipes.output.write_all(line.as_bytes())?;
+            }
+        }
+        return Ok(0);
+    };
+    if let Err(err) = parser::parse(action) {
+        pipes
+            .error
+            .write_all(format!("trap: {}\n", err).as_bytes())?;
+        return Ok(1);
+    }
+    for condition in conditions {
+        match action {
+            "-" => traps::set(condition, None),
+            action => traps::set(condition, Some(action.to_string())),
+        }
+    }
+    Ok(0)
+}
+
+fn handle_let(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    if args.is_empty() {
+        pipes.error.write_all(
+            b"Usage: let expr...\n  Evaluates each arithmetic expression, setting the variables it assigns.\n  Succeeds if the last one is not zero.\n",
+        )?;
+        return Ok(2);
+    }
+
+    let mut value = arith::Value::Int(0);
+    for expr in &args {
+        value = match arith::eval_in(expr, &mut vars::Scope) {
+            Ok(value) => value,
+            Err(err) => {
+                pipes
+                    .error
+                    .write_all(format!("let: {}: {}\n", expr, err).as_bytes())?;
+                return Ok(1);
+            }
+        };
+    }
+    Ok(if value.is_true() { 0 } else { 1 })
+}
+
+fn handle_ulimit(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let help_msg = "Usage: ulimit -c [blocks | unlimited]\n\
+      Shows or sets the largest core file, in 1024-byte blocks, that commands\n\
+      killed by a crash may leave behind. 0 turns core dumps off.\n"
+        .as_bytes();
+
+    let limit = match args.iter().map(String::as_str).collect_vec().as_slice() {
+        ["-c"] => None,
+        ["-c", "unlimited"] => Some(None),
+        ["-c", blocks] => match blocks.parse::<u64>() {
+            Ok(blocks) => Some(Some(blocks.saturating_mul(1024))),
+            Err(_) => {
+                pipes.error.write_all(help_msg)?;
+                return Ok(2);
+            }
+        },
+        _ => {
+            pipes.error.write_all(help_msg)?;
+            return Ok(2);
+        }
+    };
+
+    let result = match limit {
+        Some(bytes) => crash::set_core_limit(bytes),
+        None => crash::core_limit().and_then(|limit| {
+            let line = match limit {
+                Some(bytes) => format!("{}\n", bytes / 1024),
+                None => "unlimited\n".to_string(),
+            };
+            pipes.output.write_all(line.as_bytes())
+        }),
+    };
+    if let Err(err) = result {
+        pipes
+            .error
+            .write_all(format!("ulimit: {}\n", error_message(&err)).as_bytes())?;
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+fn handle_envdiff(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    if !args.is_empty() {
+        pipes.error.write_all(
+            b"Usage: envdiff\n  Shows the variables added (+), changed (~) or removed (-) since the shell started.\n",
+        )?;
+        return Ok(2);
+    }
+
+    for change in envdiff::changes() {
+        let line = match change {
+            envdiff::Change::Added(name, value) => format!("+ {}={}\n", name, value),
+            envdiff::Change::Changed(name, old, new) => {
+                format!("~ {}={} -> {}\n", name, old, new)
+            }
+            envdiff::Change::Removed(name, value) => format!("- {}={}\n", name, value),
+        };
+        pipes.output.write_all(line.as_bytes())?;
+    }
+    Ok(0)
+}
+
+// Splits the leading -L/-P flags off, the last one wins.
+fn parse_symlink_flags(args: &[String]) -> Option<(bool, &[String])> {
+    let mut physical = paths::physical();
+    let mut rest = args;
+    while let Some((flag, tail)) = rest.split_first() {
+        match flag.as_str() {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            "--" => return Some((physical, tail)),
+            flag if flag.starts_with('-') && flag.len() > 1 => return None,
+            _ => break,
+        }
+        rest = tail;
+    }
+    Some((physical, rest))
+}
+
+fn handle_pwd(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let Some((physical, [])) = parse_symlink_flags(&args) else {
+        pipes.error.write_all("Usage: pwd [-L|-P]\n".as_bytes())?;
+        return Ok(2);
+    };
+
+    // With the directory removed from under us only $PWD remembers where we
+    // were, which is still worth printing, but not without saying so.
+    if let Err(err) = env::current_dir() {
+        pipes.error.write_all(
+            format!(
+                "pwd: error retrieving current directory: {}\n",
+                error_message(&err)
+            )
+            .as_bytes(),
+        )?;
+        if physical {
+            return Ok(1);
+        }
+    }
+    let cwd = match physical {
+        true => env::current_dir(),
+        false => paths::logical_cwd(),
+    };
+    match cwd {
+        Ok(cwd) => {
+            pipes
+                .output
+                .write_all(format!("{}\n", cwd.to_string_lossy()).as_bytes())?;
+            Ok(0)
+        }
+        Err(_) => Ok(1),
+    }
+}
+
+fn handle_cd(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<i32> {
+    let Some((physical, args)) = parse_symlink_flags(&args).filter(|(_, rest)| rest.len() <= 1)
+    else {
+        pipes
+            .error
+            .write_all("Usage: cd [-L|-P] [path: optional (default: ~)]\n".as_bytes())?;
+        return Ok(2);
+    };
+
+    let default_path = env::home_dir().expect("Couldn't find $HOME path!");
+    let path = args
+        .first()
+        .map(|s| {
+            if s == "~" {
+                default_path.clone()
+            } else {
+                PathBuf::from(s)
+            }
+        })
+        .unwrap_or(default_path);
+
+    let old_pwd = paths::logical_cwd();
+    let target = paths::resolve(&path, physical);
+    let result = env::set_current_dir(&target).and_then(|()| {
+        // A relative target means the kernel resolved it, so only it knows
+        // where we ended up.
+        if physical || target.is_relative() {
+            env::current_dir()
+        } else {
+            Ok(target)
+        }
+    });
+
+    match result {
+        Ok(pwd) => {
+            // `cd` may run on a thread of a pipeline while the others read the
+            // environment, so these only reach the programs started after.
+            if let Ok(old_pwd) = old_pwd {
+                vars::export("OLDPWD", old_pwd.to_string_lossy().to_string());
+            }
+            vars::export("PWD", pwd.to_string_lossy().to_string());
+            Ok(0)
+        }
+        Err(err) => {
+            let msg = err.to_string();
+            if msg == "No such file or directory (os error 2)" {
+                pipes.error.write_all(
+                    format!(
+                        "cd: {}: No such file or directory\n",
+                        path.to_string_lossy()
+                    )
+                    .as_bytes(),
+                )?;
+            } else {
+                pipes.error.write_all(format!("{}\n", msg).as_bytes())?;
+            }
+            Ok(1)
+        }
+    }
+}
+
+fn handle_exit(args: Vec<String>, pipes: &mut IOPipes, session: Session) -> io::Result<i32> {
+    if args.len() > 1 {
+        pipes
+            .error
+            .write_all("Usage: exit [exit_code: optional (default: 0)]\n".as_bytes())?;
+        return Ok(2);
+    }
+
+    let exit_code = args.first().and_then(|s| s.parse().ok()).unwrap_or(0);
+    if let Some(exit) = &session.exit {
+        _ = exit.set(exit_code);
+        return Ok(exit_code);
+    }
+    if session.interactive && jobs::hold_exit() {
+        pipes.error.write_all(b"There are stopped jobs.\n")?;
+        return Ok(1);
+    }
+
+    jobs::end_stopped();
+    let_watchdog_finish();
+    _ = history_write(session.editor, session.history_path.as_ref());
+    profile::report();
+    summary::report();
+    control::close();
+    process::exit(exit_code);
+}
+
+// Hands the child everything it gets from the shell besides its arguments:
+// the standard streams, process substitution pipes and numbered fds. The fds
+// given back have to stay open until the child is spawned.
+fn connect(command: &mut Command, pipes: IOPipes) -> io::Result<Vec<OwnedFd>> {
+    let numbered = pipes
+        .numbered
+        .into_iter()
+        .map(|(fd, source)| Ok((fd, source.into_fd()?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let numbered = fd::lift(numbered)?;
+
+    command
+        .stdin(pipes.input)
+        .stdout(pipes.output)
+        .stderr(pipes.error);
+
+    // Our copies are close-on-exec, so the child needs its own without the flag.
+    let fds = pipes.fds.iter().map(|fd| fd.as_raw_fd()).collect_vec();
+    let moves = numbered
+        .iter()
+        .map(|(target, source)| (*target, source.as_raw_fd()))
+        .collect_vec();
+    unsafe {
+        command.pre_exec(move || {
+            for &fd in &fds {
+                if libc::fcntl(fd, libc::F_SETFD, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            fd::place(&moves)
+        });
+    }
+
+    Ok(pipes
+        .fds
+        .into_iter()
+        .chain(numbered.into_iter().map(|(_, source)| source))
+        .collect())
+}
+
+fn handle_external(
+    cmd: &str,
+    args: Vec<String>,
+    vars: Vec<(String, String)>,
+    job: Option<JobId>,
+    mut pipes: IOPipes,
+) -> io::Result<Spawned> {
+    let Some(executable) = find_executable(cmd) else {
+        let path = Path::new(cmd);
+        let (reason, status) = match cmd.contains('/') {
+            true if path.is_dir() => ("Is a directory", 126),
+            true if path.exists() => ("Permission denied", 126),
+            true => ("No such file or directory", 127),
+            false => ("command not found", 127),
+        };
+        pipes
+            .error
+            .write_all(format!("{}: {}\n", cmd, reason).as_bytes())?;
+        return Ok(Spawned::Finished(status));
+    };
+
+    if options::is_set("dryrun") {
+        let line = vars
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, quote_for_display(value)))
+            .chain(
+                iter::once(cmd.to_string())
+                    .chain(args)
+                    .map(|word| quote_for_display(&word)),
+            )
+            .join(" ");
+        // Straight to the shell's stdout: down a pipe, the next command would
+        // be printed too and nothing would read it.
+        println!("{}", line);
+        return Ok(Spawned::Finished(0));
+    }
+
+    fd::audit_before_spawn(cmd);
+
+    let argv = iter::once(cmd.to_string())
+        .chain(args.clone())
+        .collect_vec();
+    let mut command = Command::new(&executable);
+    command
+        .arg0(cmd)
+        .args(winpath::translate_args(&executable, args))
+        .envs(vars::exported())
+        .envs(vars::prefixed())
+        .envs(vars);
+    let _fds = connect(&mut command, pipes)?;
+
+    let _ruleset = match sandbox::apply(&mut command) {
+        Ok(ruleset) => ruleset,
+        Err(err) => {
+            eprintln!("Failed to sandbox '{:?}': {}", executable, err);
+            return Ok(Spawned::Finished(126));
+        }
+    };
+
+    if let Some(job) = job {
+        command.process_group(jobs::group(job));
+        // In a group of its own, Ctrl-Z is for it to stop at.
+        unsafe {
+            command.pre_exec(|| {
+                signals::restore_defaults();
+                Ok(())
+            });
+        }
+    }
+
+    let running = unless_timed_out();
+    let spawned = command.spawn();
+    drop(running);
+    match spawned {
+        Ok(child) => {
+            if let Some(job) = job {
+                jobs::spawned(job, child.id());
+                if jobs::in_foreground(job) {
+                    give_terminal(job);
+                }
+            }
+            Ok(Spawned::Child(child, cmd.to_string()))
+        }
+        Err(err) if err.raw_os_error() == Some(libc::E2BIG) => {
+            eprint!("codecrafters-shell: {}", argument_list_too_long(cmd, &argv));
+            Ok(Spawned::Finished(126))
+        }
+        Err(err) => {
+            eprintln!("Failed to spawn '{:?}': {}", executable, err);
+            Ok(Spawned::Finished(126))
+        }
+    }
+}
+
+// Hands the terminal to the group of the pipeline the prompt is waiting for,
+// unless it has it already. What was just started may have tried to read it
+// first and been stopped for that, so the group is continued too.
+fn give_terminal(job: JobId) {
+    let Some(pgid) = jobs::pgid(job) else {
+        return;
+    };
+    if unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) } != pgid && terminal::give_to(pgid).is_ok() {
+        unsafe { libc::killpg(pgid, libc::SIGCONT) };
+    }
+}
+
+fn handle_cmd(
+    cmd: &str,
+    args: Vec<String>,
+    vars: Vec<(String, String)>,
+    session: Session,
+    mut pipes: IOPipes,
+) -> io::Result<Spawned> {
+    if !vars.is_empty() && (BUILTINS.contains(&cmd) || functions::load(cmd).is_some()) {
+        let cmd = cmd.to_string();
+        return Ok(Spawned::Builtin(vars::spawn_prefixed(vars, move || {
+            handle_cmd(&cmd, args, Vec::new(), session, pipes).and_then(Spawned::wait)
+        })));
+    }
+
+    let args = match BUILTINS.contains(&cmd) && !READS_FLAGS.contains(&cmd) {
+        true => skip_end_of_options(&args).to_vec(),
+        false => args,
+    };
+    let handle = match cmd {
+        "echo" => vars::spawn(move || handle_echo(args, &mut pipes)),
+        "type" => vars::spawn(move || handle_type(args, &mut pipes)),
+        "pwd" => vars::spawn(move || handle_pwd(args, &mut pipes)),
+        "cd" => vars::spawn(move || handle_cd(args, &mut pipes)),
+        "exit" => vars::spawn(move || handle_exit(args, &mut pipes, session)),
+        "clip" => vars::spawn(move || handle_clip(args, &mut pipes)),
+        "set" => vars::spawn(move || handle_set(args, &mut pipes)),
+        "replay" => vars::spawn(move || handle_replay(args, &mut pipes)),
+        "history" => vars::spawn(move || {
+            handle_history(args, &mut pipes, session.editor, session.append_history)
+        }),
+        "watch" => vars::spawn(move || handle_watch(args, &mut pipes, session)),
+        "repeat" => vars::spawn(move || handle_repeat(args, &mut pipes, session)),
+        "retry" => vars::spawn(move || handle_retry(args, &mut pipes, session)),
+        "calc" => vars::spawn(move || handle_calc(args, &mut pipes)),
+        "explain" => vars::spawn(move || handle_explain(args, &mut pipes)),
+        "printf" => vars::spawn(move || handle_printf(args, &mut pipes)),
+        "wslpath" => vars::spawn(move || handle_wslpath(args, &mut pipes)),
+        "snippet" => vars::spawn(move || handle_snippet(args, &mut pipes, session)),
+        "undo-clobber" => vars::spawn(move || handle_undo_clobber(args, &mut pipes)),
+        "exec" => vars::spawn(move || handle_exec(args, &mut pipes)),
+        "whence" => vars::spawn(move || handle_whence(args, &mut pipes)),
+        "envdiff" => vars::spawn(move || handle_envdiff(args, &mut pipes)),
+        "chunked" => vars::spawn(move || handle_chunked(args, &mut pipes, session)),
+        "isatty" => vars::spawn(move || handle_isatty(args, &mut pipes)),
+        "break" | "continue" => {
+            let name = cmd.to_string();
+            vars::spawn(move || handle_jump(&name, args, &mut pipes, session))
+        }
+        "read" => vars::spawn(move || handle_read(args, &mut pipes)),
+        "return" => vars::spawn(move || handle_return(args, &mut pipes, session)),
+        "trap" => vars::spawn(move || handle_trap(args, &mut pipes)),
+        "ulimit" => vars::spawn(move || handle_ulimit(args, &mut pipes)),
+        "let" => vars::spawn(move || handle_let(args, &mut pipes)),
+        "fc" => vars::spawn(move || handle_fc(args, &mut pipes, session)),
+        "alias" => vars::spawn(move || handle_alias(args, &mut pipes)),
+        "unalias" => vars::spawn(move || handle_unalias(args, &mut pipes)),
+        "tt" => vars::spawn(move || handle_tt(args, &mut pipes)),
+        "jobs" => vars::spawn(move || handle_jobs(args, &mut pipes)),
+        "fg" => vars::spawn(move || handle_fg(args, &mut pipes, session)),
+        "bg" => vars::spawn(move || handle_bg(args, &mut pipes)),
+        "wait" => vars::spawn(move || handle_wait(args, &mut pipes)),
+        "disown" => vars::spawn(move || handle_disown(args, &mut pipes)),
+        "kill" => vars::spawn(move || handle_kill(args, &mut pipes)),
+        "source" | "." => {
+            let name = cmd.to_string();
+            vars::spawn(move || handle_source(&name, args, &mut pipes, session))
+        }
+        _ => match functions::load(cmd) {
+            Some(function) => {
+                vars::spawn(move || run_function(&function, args, &mut pipes, session))
+            }
+            None => return handle_external(cmd, args, vars, session.job, pipes),
+        },
+    };
+    Ok(Spawned::Builtin(handle))
+}
+
+// `--` ends a builtin's options, so whatever follows is taken literally even
+// if it starts with a dash.
+fn skip_end_of_options(args: &[String]) -> &[String] {
+    match args.split_first() {
+        Some((first, rest)) if first == "--" => rest,
+        _ => args,
+    }
+}
+
+// io::Error's Display appends " (os error N)", which no shell prints.
+fn error_message(err: &io::Error) -> String {
+    let msg = err.to_string();
+    match msg.rfind(" (os error ") {
+        Some(pos) => msg[..pos].to_string(),
+        None => msg,
+    }
+}
+
+// Words are only quoted when they have to be, so dry-run output reads like
+// what was typed and can be pasted back.
+fn quote_for_display(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_./=:,+%@".contains(c));
+    match plain {
+        true => word.to_string(),
+        false => format!("'{}'", word.replace('\'', "'\\''")),
+    }
+}
+
+fn is_writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_encoded_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+// What the dry-run mode does instead of opening a redirect: fail the same way
+// opening would, without creating or truncating anything.
+fn check_redirect(redirect: &Redirect, path: &str) -> io::Result<()> {
+    let target = Path::new(path);
+    if redirect.mode == RedirectMode::Read {
+        return File::open(target).map(|_| ());
+    }
+
+    if redirect.mode == RedirectMode::Truncate && clobber_refused(target) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "cannot overwrite existing file",
+        ));
+    }
+
+    let denied = || io::Error::from_raw_os_error(libc::EACCES);
+    match fs::metadata(target) {
+        Ok(metadata) if metadata.is_dir() => Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        Ok(_) if !is_writable(target) => Err(denied()),
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        Err(err) => {
+            let parent = match target.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            match parent.is_dir() {
+                true if is_writable(parent) => Ok(()),
+                true => Err(denied()),
+                false => Err(err),
+            }
+        }
+    }
+}
+
+// With noclobber, `>` may still write to devices and pipes like /dev/null,
+// just not over an existing regular file.
+fn clobber_refused(path: &Path) -> bool {
+    options::is_set("noclobber") && fs::metadata(path).is_ok_and(|m| m.is_file())
+}
+
+fn open_redirect(redirect: &Redirect, path: &str) -> io::Result<File> {
+    match redirect.mode {
+        RedirectMode::Read => File::open(path),
+        RedirectMode::Append => OpenOptions::new().create(true).append(true).open(path),
+        RedirectMode::HereDoc | RedirectMode::HereString | RedirectMode::Duplicate => {
+            unreachable!("here-documents, here-strings and duplicates aren't files")
+        }
+        RedirectMode::Truncate | RedirectMode::Clobber => {
+            if redirect.mode == RedirectMode::Truncate && clobber_refused(Path::new(path)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "cannot overwrite existing file",
+                ));
+            }
+            if options::is_set("trash") {
+                trash::save(Path::new(path))?;
+            }
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(path)
+        }
+    }
+}
+
+// The body is written from its own thread, so one bigger than the pipe buffer
+// can't block the shell before the command starts reading.
+fn here_document(body: String) -> io::Result<IOSource> {
+    let (reader, mut writer) = pipe()?;
+    thread::spawn(move || _ = writer.write_all(body.as_bytes()));
+    Ok(IOSource::PipeReader(reader))
+}
+
+/// A command's fds while its redirections are applied, starting out with the
+/// three standard ones.
+type FdTable = BTreeMap<RawFd, IOSource>;
+
+// What `N>&M` puts on N: whatever M is right now, in the table or opened by
+// exec. A standard stream moved to another number needs a real handle.
+fn duplicate(table: &FdTable, fd: RawFd, target: &str) -> io::Result<IOSource> {
+    let bad = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: bad file descriptor", target),
+        )
+    };
+    let number: RawFd = target.parse().map_err(|_| bad())?;
+    let source = match table.get(&number) {
+        Some(source) => source.try_clone()?,
+        None => IOSource::File(File::from(fd::duplicate(number).map_err(|_| bad())?)),
+    };
+    match source.standard_fd() {
+        Some(standard) if standard != fd => Ok(IOSource::File(File::from(source.into_fd()?))),
+        _ => Ok(source),
+    }
+}
+
+// Applies a command's redirections in order, so the last one for an fd wins,
+// like in bash. Every target still gets opened (and truncated).
+fn apply_redirects(command: &parser::Command, table: &mut FdTable) -> io::Result<()> {
+    for redirect in &command.redirects {
+        let path = expand::word(&redirect.target)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        match redirect.mode {
+            RedirectMode::HereDoc => {
+                table.insert(redirect.fd, here_document(path)?);
+                continue;
+            }
+            RedirectMode::HereString => {
+                table.insert(redirect.fd, here_document(path + "\n")?);
+                continue;
+            }
+            RedirectMode::Duplicate => {
+                let source = duplicate(table, redirect.fd, &path)?;
+                table.insert(redirect.fd, source);
+                continue;
+            }
+            _ => {}
+        }
+
+        if options::is_set("dryrun") {
+            check_redirect(redirect, &path).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}: {}", path, error_message(&e)))
+            })?;
+            continue;
+        }
+
+        let file = open_redirect(redirect, &path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, error_message(&e))))?;
+        match redirect.fd {
+            parser::BOTH => {
+                table.insert(2, IOSource::File(file.try_clone()?));
+                table.insert(1, IOSource::File(file));
+            }
+            fd => _ = table.insert(fd, IOSource::File(file)),
+        }
+    }
+    Ok(())
+}
+
+// Starts the command of a `<(...)` or `>(...)` on one end of a pipe and gives
+// back the other end, which the outer command opens as /dev/fd/N.
+fn start_substitution(part: &WordPart, session: &Session) -> io::Result<(OwnedFd, Spawned)> {
+    let (reader, writer) = pipe()?;
+    let (command, inner, outer) = match part {
+        WordPart::ProcessIn(command) => (
+            command,
+            IOPipes {
+                output: IOSource::PipeWriter(writer),
+                ..IOPipes::standard()
+            },
+            OwnedFd::from(reader),
+        ),
+        WordPart::ProcessOut(command) => (
+            command,
+            IOPipes {
+                input: IOSource::PipeReader(reader),
+                ..IOPipes::standard()
+            },
+            OwnedFd::from(writer),
+        ),
+        _ => unreachable!("only process substitutions are started"),
+    };
+
+    let list = parser::parse(command)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let session = session.clone();
+    let handle = vars::spawn(move || execute(&list, &session, &inner));
+    Ok((outer, Spawned::Builtin(handle)))
+}
+
+// Swaps every process substitution in a command for the path of its pipe.
+fn substitute_processes(
+    command: &parser::Command,
+    session: &Session,
+    fds: &mut Vec<OwnedFd>,
+    substitutions: &mut Vec<Spawned>,
+) -> io::Result<parser::Command> {
+    let mut substitute = |word: &Word| -> io::Result<Word> {
+        let mut parts = Vec::new();
+        for part in &word.0 {
+            if !matches!(part, WordPart::ProcessIn(_) | WordPart::ProcessOut(_)) {
+                parts.push(part.clone());
+                continue;
+            }
+            let (fd, spawned) = start_substitution(part, session)?;
+            parts.push(WordPart::Literal(format!("/dev/fd/{}", fd.as_raw_fd())));
+            fds.push(fd);
+            substitutions.push(spawned);
+        }
+        Ok(Word(parts))
+    };
+
+    let mut substituted = command.clone();
+    for word in &mut substituted.words {
+        *word = substitute(word)?;
+    }
+    for redirect in &mut substituted.redirects {
+        redirect.target = substitute(&redirect.target)?;
+    }
+    Ok(substituted)
+}
+
+// The pre-exec hook for dangerous commands. Without a terminal to ask on,
+// anything that needs confirmation is refused.
+fn allowed_by_policy(args: &[String], session: &Session) -> bool {
+    let verdict = policy::check(args);
+    let allowed = match verdict {
+        Verdict::Allow => return true,
+        Verdict::Deny => false,
+        Verdict::Confirm => {
+            session.interactive
+                && terminal::ask(&format!("Run `{}`? Are you sure?", args.join(" ")))
+                    .unwrap_or(false)
+        }
+    };
+    if !allowed {
+        eprintln!("codecrafters-shell: {}: refused by policy", args[0]);
+    }
+    allowed
+}
+
+// A pipeline with all its commands started, for whoever runs it to wait on:
+// the shell itself, or for one ended by `&`, its job's own thread.
+struct Started {
+    spawned: Vec<Spawned>,
+    substitutions: Vec<Spawned>,
+    capture: Option<capture::Capture>,
+    to_terminal: bool,
+    // Simple commands and when they started, for `--profile`.
+    timed: Vec<(String, Instant)>,
+    // The hidden job its processes went in at the prompt, which has the
+    // terminal until they are done or Ctrl-Z stops them.
+    foreground: Option<JobId>,
+}
+
+impl Started {
+    fn wait(mut self, session: &Session) -> io::Result<i32> {
+        // Output sent elsewhere is already out of the way of the terminal.
+        let spinner = match session.interactive && self.to_terminal {
+            true => progress::start(),
+            false => None,
+        };
+        let status = match self.foreground.take() {
+            Some(id) => self.wait_in_foreground(id),
+            None => self.wait_all(),
+        };
+        if let Some(spinner) = spinner {
+            spinner.finish();
+        }
+        status
+    }
+
+    fn wait_all(self) -> io::Result<i32> {
+        let mut status = 0;
+        for process in self.spawned {
+            status = process.wait()?;
+        }
+        for (command, started) in self.timed {
+            profile::record(&command, started.elapsed());
+        }
+        for substitution in self.substitutions {
+            substitution.wait()?;
+        }
+
+        if let Some(capture) = self.capture {
+            capture.finish();
+        }
+        Ok(status)
+    }
+
+    // Waits on its own thread, so that if Ctrl-Z stops the pipeline the
+    // prompt can come back while the thread goes on waiting for it as a job.
+    fn wait_in_foreground(self, id: JobId) -> io::Result<i32> {
+        let (sender, done) = mpsc::channel();
+        thread::spawn(move || {
+            let status = self.wait_all();
+            jobs::finish(id, *status.as_ref().unwrap_or(&1));
+            _ = sender.send(status);
+        });
+        let finished = jobs::wait_in_foreground(id, &done);
+        _ = terminal::take_back();
+
+        let Some(status) = finished else {
+            terminal::restore_modes();
+            jobs::stopped_in_foreground(id);
+            jobs::told_stopped(id);
+            if let Some(line) = jobs::describe(id, false) {
+                eprintln!("\n{}", line);
+            }
+            return Ok(128 + libc::SIGTSTP);
+        };
+        jobs::forget(id);
+        // Whatever a killed command did to the terminal, it didn't get to undo.
+        if status.as_ref().is_ok_and(|&status| status > 128) {
+            terminal::restore_modes();
+        }
+        status
+    }
+}
+
+fn execute_pipeline(pipeline: &Pipeline, session: &Session, io: &IOPipes) -> io::Result<i32> {
+    start_pipeline(pipeline, session, io)?.wait(session)
+}
+
+// At the prompt, what a pipeline starts goes in a hidden job that is handed
+// the terminal, so that Ctrl-Z stops it and gives the prompt back.
+fn start_pipeline(pipeline: &Pipeline, session: &Session, io: &IOPipes) -> io::Result<Started> {
+    if !(session.interactive && session.job.is_none() && io::stdin().is_terminal()) {
+        return start_commands(pipeline, session, io);
+    }
+    terminal::save_modes();
+    let id = jobs::start_foreground(pipeline.commands.iter().map(command_text).join(" | "));
+    let session = Session {
+        job: Some(id),
+        ..session.clone()
+    };
+    match start_commands(pipeline, &session, io) {
+        Ok(started) => Ok(Started {
+            foreground: Some(id),
+            ..started
+        }),
+        Err(err) => {
+            _ = terminal::take_back();
+            jobs::forget(id);
+            Err(err)
+        }
+    }
+}
+
+fn start_commands(pipeline: &Pipeline, session: &Session, io: &IOPipes) -> io::Result<Started> {
+    let mut spawned = Vec::new();
+    let mut substitutions = Vec::new();
+    let mut capture = None;
+    let mut to_terminal = false;
+    let mut timed = Vec::new();
+
+    let mut next_input = Some(io.input.try_clone()?);
+    let last = pipeline.commands.len() - 1;
+
+    for (index, command) in pipeline.commands.iter().enumerate() {
+        drop(unless_timed_out());
+        let input = next_input.take().expect("Pipe reader should be there!");
+
+        let output = if index == last {
+            io.output.try_clone()?
+        } else {
+            let (reader, writer) = pipe()?;
+            next_input = Some(IOSource::PipeReader(reader));
+            IOSource::PipeWriter(writer)
+        };
+        let error = io.error.try_clone()?;
+
+        let mut fds = Vec::new();
+        let command = match substitute_processes(command, session, &mut fds, &mut substitutions) {
+            Ok(command) => command,
+            Err(err) => {
+                eprintln!("codecrafters-shell: {}", err);
+                spawned.push(Spawned::Finished(1));
+                continue;
+            }
+        };
+
+        let mut table = io
+            .numbered
+            .iter()
+            .map(|(fd, source)| Ok((*fd, source.try_clone()?)))
+            .collect::<io::Result<FdTable>>()?;
+        table.extend([(0, input), (1, output), (2, error)]);
+        if let Err(err) = apply_redirects(&command, &mut table) {
+            eprintln!("codecrafters-shell: {}", err);
+            spawned.push(Spawned::Finished(1));
+            continue;
+        }
+        let mut standard = |fd| table.remove(&fd).expect("Standard fds stay in the table!");
+        let (input, mut output, error) = (standard(0), standard(1), standard(2));
+        let numbered = table.into_iter().collect_vec();
+
+        if index == last {
+            to_terminal = matches!(output, IOSource::Stdout);
+        }
+        if index == last && matches!(output, IOSource::Stdout) && options::is_set("capture") {
+            let (writer, tee) = capture::start()?;
+            output = IOSource::PipeWriter(writer);
+            capture = Some(tee);
+        }
+
+        let pipes = IOPipes {
+            input,
+            output,
+            error,
+            fds,
+            numbered,
+        };
+        match command.group {
+            Some(Group::Subshell(list)) => {
+                spawned.push(run_subshell(list, session, pipes)?);
+                continue;
+            }
+            Some(Group::Braces(list)) => {
+                let session = session.clone();
+                let handle = vars::spawn(move || execute(&list, &session, &pipes));
+                spawned.push(Spawned::Builtin(handle));
+                continue;
+            }
+            Some(Group::Loop {
+                until,
+                condition,
+                body,
+            }) => {
+                let session = session.clone();
+                let handle =
+                    vars::spawn(move || execute_loop(until, &condition, &body, &session, &pipes));
+                spawned.push(Spawned::Builtin(handle));
+                continue;
+            }
+            Some(Group::If {
+                branches,
+                otherwise,
+            }) => {
+                let session = session.clone();
+                let handle = vars::spawn(move || {
+                    execute_if(&branches, otherwise.as_ref(), &session, &pipes)
+                });
+                spawned.push(Spawned::Builtin(handle));
+                continue;
+            }
+            Some(Group::Arith(expr)) => {
+                let status = match arithmetic(&expr) {
+                    Some(value) if value.is_true() => 0,
+                    _ => 1,
+                };
+                spawned.push(Spawned::Finished(status));
+                continue;
+            }
+            Some(Group::For {
+                init,
+                condition,
+                step,
+                body,
+            }) => {
+                let session = session.clone();
+                let handle = vars::spawn(move || {
+                    execute_for([&init, &condition, &step], &body, &session, &pipes)
+                });
+                spawned.push(Spawned::Builtin(handle));
+                continue;
+            }
+            Some(Group::Select { name, words, body }) => {
+                let session = session.clone();
+                let handle = vars::spawn(move || {
+                    execute_select(&name, words.as_deref(), &body, &session, &pipes)
+                });
+                spawned.push(Spawned::Builtin(handle));
+                continue;
+            }
+            Some(Group::Case { subject, arms }) => {
+                let session = session.clone();
+                let handle = vars::spawn(move || execute_case(&subject, &arms, &session, &pipes));
+                spawned.push(Spawned::Builtin(handle));
+                continue;
+            }
+            None => {}
+        }
+
+        run_trap("DEBUG", &command_text(&command), session, io)?;
+        if profile::enabled() {
+            timed.push((command_text(&command), Instant::now()));
+        }
+        let expanded = expand::words(&command.words).and_then(|args| {
+            let vars = command
+                .assignments
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), expand::word(value)?)))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((args, vars))
+        });
+        let (mut args, vars) = match expanded {
+            Ok((args, vars)) if !args.is_empty() => (args, vars),
+            // Without a command, the variables are the shell's to keep.
+            Ok((_, assigned)) => {
+                for (name, value) in assigned {
+                    vars::set(&name, value);
+                }
+                spawned.push(Spawned::Finished(0));
+                continue;
+            }
+            Err(err) => {
+                eprintln!("codecrafters-shell: {}", err);
+                spawned.push(Spawned::Finished(1));
+                continue;
+            }
+        };
+        if !allowed_by_policy(&args, session) {
+            spawned.push(Spawned::Finished(1));
+            continue;
+        }
+        replay::note_argv(&args);
+        let cmd = args.remove(0);
+
+        spawned.push(handle_cmd(&cmd, args, vars, session.clone(), pipes)?);
+    }
+
+    Ok(Started {
+        spawned,
+        substitutions,
+        capture,
+        to_terminal,
+        timed,
+        foreground: None,
+    })
+}
+
+// Runs the action trapped for the condition, if any, with $TRAP_COMMAND set
+// to the command that set it off. `$?` is left as the command had it.
+fn run_trap(condition: &str, command: &str, session: &Session, io: &IOPipes) -> io::Result<()> {
+    if session.trapped {
+        return Ok(());
+    }
+    let Some(action) = traps::get(condition) else {
+        return Ok(());
+    };
+    let session = Session {
+        trapped: true,
+        ..session.clone()
+    };
+    let status = params::last_status();
+    vars::set("TRAP_COMMAND", command.to_string());
+    // Checked when the trap was set.
+    if let Ok(list) = parser::parse(&action) {
+        execute(&list, &session, io)?;
+    }
+    params::set_last_status(status);
+    Ok(())
+}
+
+// Runs a `( list )` in a fork of the shell, so that nothing it changes, from
+// the working directory to the traps, reaches the shell or whatever else is
+// running beside it.
+fn run_subshell(list: List, session: &Session, pipes: IOPipes) -> io::Result<Spawned> {
+    // A `break` inside only ends the subshell, like in sh.
+    let session = Session {
+        interactive: false,
+        exit: Some(subshell::Exit::default()),
+        jump: Arc::default(),
+        ..session.clone()
+    };
+    let pid = fork_shell(&session, &pipes, || {
+        let status = execute(&list, &session, &pipes).unwrap_or(1);
+        session.exited().unwrap_or(status)
+    })?;
+    Ok(Spawned::Forked(pid))
+}
+
+// Forks the shell, with the child in the group of the session's job if it
+// has one, and gives the parent the child's pid. The child closes whatever
+// isn't in `pipes`, runs `run` and exits with the status it gives.
+fn fork_shell(
+    session: &Session,
+    pipes: &IOPipes,
+    run: impl FnOnce() -> i32,
+) -> io::Result<libc::pid_t> {
+    let group = session.job.map(|job| (job, jobs::group(job)));
+    let pid = {
+        // Control requests have an editor of their own, so this one is never
+        // held by the prompt waiting for a line.
+        let _editor = session.editor.lock().expect("Failed to lock the editor!");
+        let _appended = session
+            .append_history
+            .lock()
+            .expect("Failed to lock append history!");
+        let _jump = session.jump.lock().expect("Failed to lock jump!");
+        subshell::fork()?
+    };
+
+    if pid == 0 {
+        fd::close_all_but(&pipes.raw_fds());
+        jobs::drop_printer();
+        if let Some((job, group)) = group {
+            unsafe { libc::setpgid(0, group) };
+            jobs::spawned(job, process::id());
+            signals::default_in_job();
+        }
+        let status = run();
+        _ = io::stdout().flush();
+        unsafe { libc::_exit(status) };
+    }
+
+    // Set on both sides, so it is in place whichever of them runs first.
+    if let Some((job, group)) = group {
+        unsafe { libc::setpgid(pid, group) };
+        jobs::spawned(job, pid as u32);
+        if jobs::in_foreground(job) {
+            give_terminal(job);
+        }
+    }
+    Ok(pid)
+}
+
+// Runs the body of the first branch whose condition succeeds. With none, and
+// no `else`, the status is 0 like in sh.
+fn execute_if(
+    branches: &[(List, List)],
+    otherwise: Option<&List>,
+    session: &Session,
+    io: &IOPipes,
+) -> io::Result<i32> {
+    let tested = Session {
+        condition: true,
+        ..session.clone()
+    };
+    for (condition, body) in branches {
+        let status = execute(condition, &tested, io)?;
+        if let Some(status) = session.exited() {
+            return Ok(status);
+        }
+        if status == 0 {
+            return execute(body, session, io);
+        }
+    }
+    match otherwise {
+        Some(body) => execute(body, session, io),
+        None => Ok(0),
+    }
+}
+
+// Reads a line for `select`, a byte at a time so nothing after it is taken
+// from the body. Gives None at the end of the input.
+fn read_reply(input: &mut IOSource) -> io::Result<Option<String>> {
+    let mut bytes = Vec::new();
+    let mut byte = [0u8];
+    loop {
+        if input.read(&mut byte)? == 0 {
+            return Ok((!bytes.is_empty()).then(|| String::from_utf8_lossy(&bytes).to_string()));
+        }
+        match byte[0] {
+            b'\n' => return Ok(Some(String::from_utf8_lossy(&bytes).to_string())),
+            byte => bytes.push(byte),
+        }
+    }
+}
+
+// Shows the words as a numbered menu and prompts with $PS3, running the body
+// with the word picked in `name` and what was typed in $REPLY. An empty line
+// shows the menu again. Like in sh, only `break` or the end of the input
+// leaves it.
+fn execute_select(
+    name: &str,
+    words: Option<&[Word]>,
+    body: &List,
+    session: &Session,
+    io: &IOPipes,
+) -> io::Result<i32> {
+    let items = match words {
+        Some(words) => expand::words(words),
+        None => expand::fields(&Word(vec![WordPart::Double("$@".to_string())])).map(|(f, _)| f),
+    };
+    let items = match items {
+        Ok(items) => items,
+        Err(err) => {
+            eprintln!("codecrafters-shell: {}", err);
+            return Ok(1);
+        }
+    };
+    if items.is_empty() {
+        return Ok(0);
+    }
+
+    let session = Session {
+        loops: session.loops + 1,
+        ..session.clone()
+    };
+    let mut input = io.input.try_clone()?;
+    let mut menu = io.error.try_clone()?;
+    let width = items.len().to_string().len();
+    let mut show_menu = true;
+    let mut status = 0;
+    loop {
+        if show_menu {
+            for (index, item) in items.iter().enumerate() {
+                menu.write_all(format!("{:>width$}) {}\n", index + 1, item).as_bytes())?;
+            }
+        }
+        let prompt = vars::get("PS3").unwrap_or_else(|| "#? ".to_string());
+        menu.write_all(prompt.as_bytes())?;
+        menu.flush()?;
+
+        let Some(reply) = read_reply(&mut input)? else {
+            menu.write_all(b"\n")?;
+            break;
+        };
+        if signals::interrupted() {
+            return Ok(130);
+        }
+        show_menu = reply.trim().is_empty();
+        if show_menu {
+            continue;
+        }
+
+        let picked = reply
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| number.checked_sub(1))
+            .and_then(|index| items.get(index));
+        vars::set(name, picked.cloned().unwrap_or_default());
+        vars::set("REPLY", reply);
+        status = execute(body, &session, io)?;
+        if let Some(status) = session.exited() {
+            return Ok(status);
+        }
+        if leaves_loop(&session) {
+            break;
+        }
+    }
+    Ok(status)
+}
+
+// Runs the body of the first arm with a pattern matching the word, trying the
+// patterns in order. With none matching, the status is 0 like in sh.
+fn execute_case(
+    subject: &Word,
+    arms: &[(Vec<Word>, List)],
+    session: &Session,
+    io: &IOPipes,
+) -> io::Result<i32> {
+    let subject = match expand::word(subject) {
+        Ok(subject) => subject,
+        Err(err) => {
+            eprintln!("codecrafters-shell: {}", err);
+            return Ok(1);
+        }
+    };
+    for (patterns, body) in arms {
+        for pattern in patterns {
+            match expand::pattern(pattern) {
+                Ok(pattern) if glob::matches(&pattern, &subject) => {
+                    return execute(body, session, io);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    eprintln!("codecrafters-shell: {}", err);
+                    return Ok(1);
+                }
+            }
+        }
+    }
+    Ok(0)
+}
+
+// Reruns the body for as long as the condition succeeds, or with `until`,
+// fails. Ctrl-C ends it, like it does `watch` and `repeat`.
+fn execute_loop(
+    until: bool,
+    condition: &List,
+    body: &List,
+    session: &Session,
+    io: &IOPipes,
+) -> io::Result<i32> {
+    let session = Session {
+        loops: session.loops + 1,
+        ..session.clone()
+    };
+    let tested = Session {
+        condition: true,
+        ..session.clone()
+    };
+    let mut status = 0;
+    loop {
+        if signals::interrupted() {
+            return Ok(130);
+        }
+        let tested = execute(condition, &tested, io)?;
+        if !session.stopping() {
+            if (tested == 0) == until {
+                break;
+            }
+            status = execute(body, &session, io)?;
+        }
+        if let Some(status) = session.exited() {
+            return Ok(status);
+        }
+        if leaves_loop(&session) {
+            break;
+        }
+    }
+    Ok(status)
+}
+
+// Takes care of a `break` or `continue` left for the loop around the body that
+// just ran, and says whether that loop ends here.
+fn leaves_loop(session: &Session) -> bool {
+    let mut jump = session.jump.lock().expect("Failed to lock jump!");
+    match *jump {
+        None => false,
+        Some(Jump::Continue(1)) => {
+            *jump = None;
+            false
+        }
+        Some(Jump::Break(1)) => {
+            *jump = None;
+            true
+        }
+        // The loops further out take it from here.
+        Some(Jump::Continue(levels)) => {
+            *jump = Some(Jump::Continue(levels - 1));
+            true
+        }
+        Some(Jump::Break(levels)) => {
+            *jump = Some(Jump::Break(levels - 1));
+            true
+        }
+        // Only the sourced file or function around the loop takes this.
+        Some(Jump::Return(_)) => true,
     }
 }
 
-fn handle_pwd(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<()> {
-    if !args.is_empty() {
-        return pipes.error.write_all("Usage: pwd\n".as_bytes());
+// Takes the status a `return` left, once what it returned from is done.
+fn take_return(session: &Session) -> Option<i32> {
+    let mut jump = session.jump.lock().expect("Failed to lock jump!");
+    match *jump {
+        Some(Jump::Return(status)) => {
+            *jump = None;
+            Some(status)
+        }
+        _ => None,
     }
+}
 
-    pipes.output.write_all(
-        format!(
-            "{}\n",
-            env::current_dir()
-                .expect("Failed to get current working directory")
-                .to_string_lossy()
-        )
-        .as_bytes(),
-    )
+// Evaluates the expression of a `(( ))` or a `for (( ))`, with its `$NAME`s
+// expanded first like in sh. Errors are reported and give None.
+fn arithmetic(expr: &str) -> Option<arith::Value> {
+    let word = Word(vec![WordPart::Double(expr.to_string())]);
+    let value = expand::word(&word)
+        .map_err(|err| err.to_string())
+        .and_then(|expanded| {
+            arith::eval_in(&expanded, &mut vars::Scope).map_err(|err| err.to_string())
+        });
+    match value {
+        Ok(value) => Some(value),
+        Err(err) => {
+            eprintln!("codecrafters-shell: (({})): {}", expr, err);
+            None
+        }
+    }
 }
 
-fn handle_cd(args: Vec<String>, pipes: &mut IOPipes) -> io::Result<()> {
-    if args.len() > 1 {
-        return pipes
-            .error
-            .write_all("Usage: cd [path: optional (default: ~)]\n".as_bytes());
+// Runs `for (( init; condition; step ))`: the init once, then the body and
+// the step for as long as the condition is true. An empty part is skipped,
+// and an empty condition never ends the loop.
+fn execute_for(
+    [init, condition, step]: [&str; 3],
+    body: &List,
+    session: &Session,
+    io: &IOPipes,
+) -> io::Result<i32> {
+    let session = Session {
+        loops: session.loops + 1,
+        ..session.clone()
+    };
+    let holds = |expr: &str| expr.is_empty() || arithmetic(expr).is_some_and(|v| v.is_true());
+    let runs = |expr: &str| expr.is_empty() || arithmetic(expr).is_some();
+
+    if !runs(init) {
+        return Ok(1);
+    }
+    let mut status = 0;
+    while holds(condition) {
+        if signals::interrupted() {
+            return Ok(130);
+        }
+        status = execute(body, &session, io)?;
+        if let Some(status) = session.exited() {
+            return Ok(status);
+        }
+        if leaves_loop(&session) || !runs(step) {
+            break;
+        }
     }
+    Ok(status)
+}
 
-    let default_path = env::home_dir().expect("Couldn't find $HOME path!");
-    let path = args
-        .first()
-        .map(|s| {
-            if s == "~" {
-                default_path.clone()
-            } else {
-                PathBuf::from(s)
-            }
-        })
-        .unwrap_or(default_path);
+fn execute_and_or(and_or: &AndOr, session: &Session, io: &IOPipes) -> io::Result<i32> {
+    let first = start_pipeline(&and_or.first, session, io)?;
+    finish_and_or(and_or, first, session, io)
+}
 
-    match env::set_current_dir(&path) {
-        Ok(()) => Ok(()),
-        Err(err) => {
-            let msg = err.to_string();
-            if msg == "No such file or directory (os error 2)" {
-                pipes.error.write_all(
-                    format!(
-                        "cd: {}: No such file or directory\n",
-                        path.to_string_lossy()
-                    )
-                    .as_bytes(),
-                )
-            } else {
-                pipes.error.write_all(format!("{}\n", msg).as_bytes())
+// Waits for the first pipeline, which has been started, then runs whichever
+// of the rest its status calls for.
+fn finish_and_or(
+    and_or: &AndOr,
+    first: Started,
+    session: &Session,
+    io: &IOPipes,
+) -> io::Result<i32> {
+    let mut status = first.wait(session)?;
+    params::set_last_status(status);
+    if and_or.rest.is_empty() {
+        check_failure(&and_or.first, status, session, io)?;
+    }
+
+    for (index, (connector, pipeline)) in and_or.rest.iter().enumerate() {
+        if session.stopping() {
+            return Ok(session.exited().unwrap_or(status));
+        }
+        let run = match connector {
+            Connector::And => status == 0,
+            Connector::Or => status != 0,
+        };
+        if run {
+            status = execute_pipeline(pipeline, session, io)?;
+            params::set_last_status(status);
+            // Only the last pipeline's failure goes unchecked.
+            if index == and_or.rest.len() - 1 {
+                check_failure(pipeline, status, session, io)?;
             }
         }
     }
+    Ok(status)
 }
 
-fn handle_exit(
-    args: Vec<String>,
-    pipes: &mut IOPipes,
-    editor: Arc<Mutex<Shell>>,
-    history_path: Option<String>,
+// Runs the ERR trap for a pipeline that failed outside a condition. Groups
+// are left alone, since the command inside that failed has had its turn.
+fn check_failure(
+    pipeline: &Pipeline,
+    status: i32,
+    session: &Session,
+    io: &IOPipes,
 ) -> io::Result<()> {
-    if args.len() > 1 {
-        return pipes
-            .error
-            .write_all("Usage: exit [exit_code: optional (default: 0)]\n".as_bytes());
+    let last = pipeline.commands.last().expect("Pipelines have a command!");
+    if status == 0 || session.condition || session.stopping() || last.group.is_some() {
+        return Ok(());
     }
+    let text = pipeline.commands.iter().map(command_text).join(" | ");
+    run_trap("ERR", &text, session, io)
+}
 
-    _ = history_write(Arc::clone(&editor), history_path.as_ref());
-
-    let exit_code = args.first().and_then(|s| s.parse().ok()).unwrap_or(0);
-    process::exit(exit_code);
+// A simple command as written, give or take quotes, for $TRAP_COMMAND.
+fn command_text(command: &parser::Command) -> String {
+    let assignments = command
+        .assignments
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value.text()));
+    assignments
+        .chain(command.words.iter().map(Word::text))
+        .join(" ")
 }
-fn handle_external(
-    cmd: &str,
-    args: Vec<String>,
-    input: IOSource,
-    output: IOSource,
-    mut error: IOSource,
-) -> io::Result<Option<Child>> {
-    let externals = get_external_executables();
-    let Some(executable) = externals.get(cmd) else {
-        error.write_all(format!("{}: command not found\n", cmd).as_bytes())?;
-        return Ok(None);
+
+// Starts a list ended by `&` as a job, in a fork of the shell that leads the
+// job's process group, and a thread that waits for it. Like a subshell,
+// nothing it changes reaches the shell, and `exit` and `break` inside only
+// end the job. Its input is /dev/null unless redirected, as in sh. At the
+// prompt what it prints is relayed above the line being typed instead of
+// over it, and with `bufferjobs` it is held back until the job is done. The
+// fork waits for every lock the shell has, so a job started from any thread,
+// a control request's included, never comes up with one held for good.
+fn start_job(and_or: &AndOr, text: &str, session: &Session, io: &IOPipes) -> io::Result<()> {
+    let id = jobs::start(text.to_string());
+    let job_session = Session {
+        interactive: false,
+        exit: Some(subshell::Exit::default()),
+        jump: Arc::default(),
+        job: Some(id),
+        ..session.clone()
     };
+    let mut pipes = io.try_clone()?;
+    if matches!(pipes.input, IOSource::Stdin) {
+        pipes.input = IOSource::File(File::open("/dev/null")?);
+    }
+    let diverted = match options::is_set("bufferjobs") {
+        true => Some(jobs::hold(id)?),
+        false if session.interactive => jobs::relay()?,
+        false => None,
+    };
+    if let Some(writer) = diverted {
+        if matches!(pipes.output, IOSource::Stdout) {
+            pipes.output = IOSource::PipeWriter(writer.try_clone()?);
+        }
+        if matches!(pipes.error, IOSource::Stderr) {
+            pipes.error = IOSource::PipeWriter(writer);
+        }
+    }
 
-    let child = match Command::new(executable)
-        .arg0(cmd)
-        .args(args)
-        .stdin(input)
-        .stdout(output)
-        .stderr(error)
-        .spawn()
-    {
-        Ok(output) => output,
+    let pid = fork_shell(&job_session, &pipes, || {
+        signals::pass_on_to_job();
+        let status = execute_and_or(and_or, &job_session, &pipes).unwrap_or(1);
+        job_session.exited().unwrap_or(status)
+    });
+    // Our ends of its output have to close for what it held to drain.
+    drop(pipes);
+    let pid = match pid {
+        Ok(pid) => pid,
         Err(err) => {
-            eprintln!("Failed to spawn '{:?}': {}", executable, err);
-            return Ok(None);
+            jobs::forget(id);
+            return Err(err);
         }
     };
 
-    Ok(Some(child))
+    params::set_last_background(pid as u32);
+    if session.interactive {
+        let number = jobs::number(id).unwrap_or_default();
+        eprintln!("[{}] {}", number, pid);
+    }
+    thread::spawn(move || {
+        let status = Spawned::Forked(pid).wait().unwrap_or(1);
+        jobs::release(id);
+        jobs::finish(id, status);
+    });
+    Ok(())
 }
 
-fn handle_cmd(
-    cmd: &str,
-    args: Vec<String>,
-    editor: Arc<Mutex<Shell>>,
-    append_history: Arc<Mutex<Vec<String>>>,
-    input: IOSource,
-    output: IOSource,
-    error: IOSource,
-    history_path: Option<String>,
-) -> io::Result<(Option<Child>, Option<IOJoinHandle>)> {
-    match cmd {
-        "echo" => {
-            let handle = thread::spawn(move || {
-                handle_echo(
-                    args,
-                    &mut IOPipes {
-                        input,
-                        output,
-                        error,
-                    },
-                )
-            });
-            Ok((None, Some(handle)))
-        }
-        "type" => {
-            let handle = thread::spawn(move || {
-                handle_type(
-                    args,
-                    &mut IOPipes {
-                        input,
-                        output,
-                        error,
-                    },
-                )
-            });
-            Ok((None, Some(handle)))
-        }
-        "pwd" => {
-            let handle = thread::spawn(move || {
-                handle_pwd(
-                    args,
-                    &mut IOPipes {
-                        input,
-                        output,
-                        error,
-                    },
-                )
-            });
-            Ok((None, Some(handle)))
-        }
-        "cd" => {
-            let handle = thread::spawn(move || {
-                handle_cd(
-                    args,
-                    &mut IOPipes {
-                        input,
-                        output,
-                        error,
-                    },
-                )
-            });
-            Ok((None, Some(handle)))
-        }
-        "exit" => {
-            let handle = thread::spawn(move || {
-                handle_exit(
-                    args,
-                    &mut IOPipes {
-                        input,
-                        output,
-                        error,
-                    },
-                    Arc::clone(&editor),
-                    history_path,
-                )
-            });
-            Ok((None, Some(handle)))
-        }
-        "history" => {
-            let handle = thread::spawn(move || {
-                handle_history(
-                    args,
-                    &mut IOPipes {
-                        input,
-                        output,
-                        error,
-                    },
-                    Arc::clone(&editor),
-                    Arc::clone(&append_history),
-                )
-            });
-            Ok((None, Some(handle)))
+fn execute(list: &List, session: &Session, io: &IOPipes) -> io::Result<i32> {
+    let mut status = 0;
+    for and_or in &list.items {
+        if session.stopping() {
+            return Ok(session.exited().unwrap_or(status));
         }
-        _ => handle_external(cmd, args, input, output, error).map(|c| (c, None)),
+        status = match &and_or.background {
+            Some(text) => start_job(and_or, text, session, io).map(|()| 0)?,
+            None => execute_and_or(and_or, session, io)?,
+        };
     }
+    Ok(session.exited().unwrap_or(status))
 }
 
-fn checks_redirects(
-    redirect_path: Option<String>,
-    append_path: Option<String>,
-) -> io::Result<Option<File>> {
-    if let Some(ref path) = append_path {
-        return OpenOptions::new()
-            .create(true)
-            .append(true)
-            .truncate(false)
-            .open(path)
-            .map(Some);
-    };
+struct Options {
+    command: Option<String>,
+    script: Option<PathBuf>,
+    timeout: Option<Duration>,
+    sandbox: Option<sandbox::Profile>,
+    login: bool,
+    profile: bool,
+    strict_rc: bool,
+    // `--send PID REQUEST...`, which makes this a client of another shell.
+    send: Option<(u32, Vec<String>)>,
+    // `$0` and the positional parameters.
+    name: String,
+    args: Vec<String>,
+}
 
-    if let Some(ref path) = redirect_path {
-        return OpenOptions::new()
-            .create(true)
-            .append(false)
-            .write(true)
-            .truncate(true)
-            .open(path)
-            .map(Some);
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        command: None,
+        script: None,
+        timeout: None,
+        sandbox: None,
+        login: env::args().next().is_some_and(|arg0| arg0.starts_with('-')),
+        profile: false,
+        strict_rc: false,
+        send: None,
+        name: env::args().next().unwrap_or_default(),
+        args: Vec::new(),
     };
 
-    Ok(None)
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" => {
+                let command = args.next().ok_or("-c: option requires an argument")?;
+                options.command = Some(command);
+                if let Some(name) = args.next() {
+                    options.name = name;
+                }
+                options.args = args.collect();
+                break;
+            }
+            "--timeout" => {
+                let secs = args
+                    .next()
+                    .ok_or("--timeout: option requires an argument")?;
+                let timeout = secs
+                    .parse()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .filter(|timeout| !timeout.is_zero())
+                    .ok_or(format!("--timeout: invalid number of seconds: {}", secs))?;
+                options.timeout = Some(timeout);
+            }
+            "--sandbox" => {
+                let name = args
+                    .next()
+                    .ok_or("--sandbox: option requires an argument")?;
+                let profile = sandbox::Profile::parse(&name)
+                    .ok_or(format!("--sandbox: unknown profile: {}", name))?;
+                options.sandbox = Some(profile);
+            }
+            "--send" => {
+                let pid = args.next().ok_or("--send: option requires a pid")?;
+                let pid = pid
+                    .parse()
+                    .map_err(|_| format!("--send: invalid pid: {}", pid))?;
+                let request: Vec<String> = args.collect();
+                if request.is_empty() {
+                    return Err("--send: option requires a request".to_string());
+                }
+                options.send = Some((pid, request));
+                break;
+            }
+            "-l" | "--login" => options.login = true,
+            "--profile" => options.profile = true,
+            "--strict-rc" => options.strict_rc = true,
+            "--dry-run" => _ = crate::options::set("dryrun", true),
+            "-o" => {
+                let name = args.next().ok_or("-o: option requires an argument")?;
+                if !crate::options::set(&name, true) {
+                    return Err(format!("-o: {}: invalid option name", name));
+                }
+            }
+            // Like `sh -- script`, for a script whose name starts with a dash.
+            "--" => {
+                if let Some(script) = args.next() {
+                    options.script = Some(PathBuf::from(&script));
+                    options.name = script;
+                    options.args = args.collect();
+                }
+                break;
+            }
+            _ if arg.starts_with('-') => return Err(format!("{}: invalid option", arg)),
+            _ => {
+                options.script = Some(PathBuf::from(&arg));
+                options.name = arg;
+                options.args = args.collect();
+                break;
+            }
+        }
+    }
+
+    if options.timeout.is_some() && options.command.is_none() && options.script.is_none() {
+        return Err("--timeout: only supported with -c or a script".to_string());
+    }
+    if options.profile && options.command.is_none() && options.script.is_none() {
+        return Err("--profile: only supported with -c or a script".to_string());
+    }
+
+    Ok(options)
 }
 
-fn handle(
-    inputs: Vec<String>,
-    editor: Arc<Mutex<Shell>>,
-    append_history: Arc<Mutex<Vec<String>>>,
-    history_path: Option<String>,
-) -> io::Result<()> {
-    let mut children = Vec::new();
-    let mut handles = Vec::new();
+// Held by the watchdog from when it fires until it exits, and read before
+// anything the script starts.
+static TIMING_OUT: RwLock<()> = RwLock::new(());
 
-    let mut pipe_readers = Vec::new();
-    let mut pipe_writers = Vec::new();
+// Blocks for good once the watchdog has fired, so the script stops where it
+// is. Held around starting a process, so the watchdog doesn't look for what
+// to kill until that process is there to be found.
+fn unless_timed_out() -> RwLockReadGuard<'static, ()> {
+    TIMING_OUT.read().unwrap_or_else(PoisonError::into_inner)
+}
 
-    for _ in 0..inputs.len() - 1 {
-        let (reader, writer) = pipe()?;
-        pipe_readers.push(Some(reader));
-        pipe_writers.push(Some(writer));
-    }
+// Once the watchdog has fired, the script ending on its own, which killing
+// its commands makes likely, still leaves with the watchdog's 124.
+fn let_watchdog_finish() {
+    drop(unless_timed_out());
+}
 
-    for (index, input) in inputs.iter().enumerate() {
-        let mut parsed = parse_args(input.clone());
-        let command = parsed.remove(0);
-        let mut args = parsed;
-        let redirect_path = get_redirect(&mut args, vec![">".to_string(), "1>".to_string()]);
-        let err_redirect_path = get_redirect(&mut args, vec!["2>".to_string()]);
-        let append_path = get_redirect(&mut args, vec![">>".to_string(), "1>>".to_string()]);
-        let err_append_path = get_redirect(&mut args, vec!["2>>".to_string()]);
+/// Waits out the watchdog for `subshell::fork`, first of all, since what it
+/// runs as it fires needs the other locks.
+pub fn hold_for_fork() -> impl Sized {
+    TIMING_OUT.read().unwrap_or_else(PoisonError::into_inner)
+}
 
-        let input_reader = if index == 0 {
-            IOSource::Stdin
-        } else {
-            IOSource::PipeReader(
-                pipe_readers[index - 1]
-                    .take()
-                    .expect("Pipe reader should be there!"),
-            )
-        };
+/// How long what the watchdog sent SIGTERM gets to exit before SIGKILL.
+const TIMEOUT_GRACE: Duration = Duration::from_secs(2);
 
-        let output_writer = match checks_redirects(redirect_path, append_path)? {
-            Some(file) => IOSource::File(file),
-            None => {
-                if index + 1 == inputs.len() {
-                    IOSource::Stdout
-                } else {
-                    IOSource::PipeWriter(
-                        pipe_writers[index]
-                            .take()
-                            .expect("Pipe writer should be there!"),
-                    )
-                }
+// Every process still running, with its parent and process group.
+fn running_processes() -> Vec<(u32, u32, i32)> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse().ok()?;
+            let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+            // The name before the fields is in parentheses and may hold anything.
+            let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+            let state = fields.next()?;
+            let ppid = fields.next()?.parse().ok()?;
+            let pgrp = fields.next()?.parse().ok()?;
+            (state != "Z").then_some((pid, ppid, pgrp))
+        })
+        .collect()
+}
+
+// What the watchdog has to take down: everything descended from the shell,
+// and with a process group of its own, whatever else is in it.
+fn left_behind(own_group: bool) -> Vec<u32> {
+    let shell = process::id();
+    let group = unsafe { libc::getpgrp() };
+    let processes = running_processes();
+    let mut ours = vec![shell];
+    loop {
+        let more = processes
+            .iter()
+            .filter(|(pid, ppid, pgrp)| {
+                !ours.contains(pid) && (ours.contains(ppid) || (own_group && *pgrp == group))
+            })
+            .map(|(pid, _, _)| *pid)
+            .collect_vec();
+        if more.is_empty() {
+            break;
+        }
+        ours.extend(more);
+    }
+    ours.retain(|&pid| pid != shell);
+    ours
+}
+
+// Without a terminal the shell takes a process group of its own, so the
+// watchdog also finds what left the shell's children behind, and never the
+// CI runner that started us. On a terminal the group is the one the job
+// control of the shell that started us made, which has the terminal and may
+// hold the rest of a pipeline, so it stays.
+fn spawn_timeout_watchdog(timeout: Duration) {
+    let own_group = !io::stdin().is_terminal();
+    if own_group {
+        unsafe { libc::setpgid(0, 0) };
+    }
+
+    thread::spawn(move || {
+        thread::sleep(timeout);
+        let _timing_out = TIMING_OUT.write().expect("Failed to lock the watchdog!");
+        eprintln!("timed out after {}s", timeout.as_secs_f64());
+        let signal_all = |signal| {
+            for pid in left_behind(own_group) {
+                unsafe { libc::kill(pid as i32, signal) };
             }
         };
+        signal_all(libc::SIGTERM);
+        // Whatever ignores SIGTERM gets SIGKILL once the grace period is up.
+        let sent = Instant::now();
+        while sent.elapsed() < TIMEOUT_GRACE && !left_behind(own_group).is_empty() {
+            thread::sleep(Duration::from_millis(50));
+        }
+        signal_all(libc::SIGKILL);
+        profile::report();
+        process::exit(124);
+    });
+}
 
-        let error_writer = match checks_redirects(err_redirect_path, err_append_path)? {
-            Some(file) => IOSource::File(file),
-            None => IOSource::Stderr,
-        };
+// Held while a line runs, and while a control request does, so a request
+// waits for the command in the foreground instead of changing the directory
+// and variables under it. It is left out of `subshell::fork`, which is most
+// often called by the thread holding it, and a subshell never runs a line.
+static RUNNING: Mutex<()> = Mutex::new(());
 
-        handle_cmd(
-            command.trim(),
-            args,
-            Arc::clone(&editor),
-            Arc::clone(&append_history),
-            input_reader,
-            output_writer,
-            error_writer,
-            history_path.clone(),
-        )
-        .map(|(child, handle)| {
-            if let Some(c) = child {
-                children.push(c);
+// Answers `set -o control`'s socket. What a request runs can't touch the
+// prompt's history, since it isn't typed there, so it gets an editor and
+// history of its own.
+fn start_control(session: &Session) {
+    let session = Session {
+        editor: Arc::new(Mutex::new(
+            Shell::new().expect("Failed to setup the control editor"),
+        )),
+        append_history: Arc::new(Mutex::new(Vec::new())),
+        history_path: None,
+        interactive: false,
+        ..session.clone()
+    };
+    control::listen(Box::new(move |command, output| {
+        let mut pipes = IOPipes {
+            input: IOSource::File(File::open("/dev/null")?),
+            output: IOSource::File(output.try_clone()?),
+            error: IOSource::File(output),
+            fds: Vec::new(),
+            numbered: Vec::new(),
+        };
+        match parser::parse(command) {
+            Ok(list) => {
+                let _running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+                execute(&list, &session, &pipes)
+            }
+            Err(err) => {
+                pipes
+                    .error
+                    .write_all(format!("codecrafters-shell: {}\n", err).as_bytes())?;
+                Ok(2)
             }
+        }
+    }));
+}
 
-            if let Some(h) = handle {
-                handles.push(h);
+fn run_line(line: &str, session: &Session) -> io::Result<i32> {
+    let _running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+    // `= expr` is a shorthand for calc that skips word splitting and quoting.
+    let status = if let Some(expr) = line.trim_start().strip_prefix('=') {
+        handle_calc(vec![expr.to_string()], &mut IOPipes::standard())?
+    } else {
+        match parser::parse(line) {
+            Ok(list) => execute(&list, session, &IOPipes::standard())?,
+            Err(err) => {
+                eprintln!("codecrafters-shell: {}", err);
+                2
             }
-        })?;
+        }
+    };
+    params::set_last_status(status);
+    Ok(status)
+}
+
+/// Commands that run at least this long get `notify`'s title update.
+const DEFAULT_NOTIFY_SECS: f64 = 10.0;
+
+// The terminal can't tell us whether it has focus while a command owns it, so
+// this fires for every slow command and leaves the title for when we look back.
+fn notify_finished(line: &str, status: i32, elapsed: Duration) {
+    if !options::is_set("notify") {
+        return;
     }
 
-    for handle in handles {
-        handle.join().expect("Failed joining handle")?;
+    let threshold = vars::get("NOTIFY_SECS")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_NOTIFY_SECS);
+    if elapsed.as_secs_f64() < threshold {
+        return;
+    }
+
+    let outcome = if status == 0 {
+        "done".to_string()
+    } else {
+        format!("exit {}", status)
+    };
+    _ = terminal::set_title(&format!("[{}] {} ({}s)", outcome, line, elapsed.as_secs()));
+
+    if options::is_set("notifybell") {
+        _ = terminal::bell();
+    }
+}
+
+// Starts the line's snapshot for `replay`, if it went into the history.
+fn begin_snapshot(line: &str, editor: Arc<Mutex<Shell>>) {
+    let editor = editor.lock().expect("Couldn't lock the editor!");
+    let history = editor.history();
+    if history.iter().last().map(String::as_str) == Some(line) {
+        replay::begin(history.len(), line);
+    }
+}
+
+/// Expands `!!` and the like in a line just typed, echoing what it became,
+/// and adds the result to the history, like bash. A reference to nothing gives None, leaving the line
+/// unrun and out of the history.
+fn expand_history(line: String, editor: &Arc<Mutex<Shell>>) -> Option<String> {
+    let mut editor = editor.lock().expect("Couldn't lock the editor!");
+    let expanded = match history::wanted(&line) {
+        true => {
+            let earlier: Vec<String> = editor.history().iter().cloned().collect();
+            history::expand(&line, &earlier)
+        }
+        false => Ok(None),
+    };
+    let line = match expanded {
+        // Shown before it runs, so what ran is never a surprise.
+        Ok(Some(expanded)) => {
+            eprintln!("{}", expanded);
+            expanded
+        }
+        Ok(None) => line,
+        Err(err) => {
+            eprintln!("codecrafters-shell: {}", err);
+            return None;
+        }
+    };
+    apply_history_settings(&mut editor);
+    add_history_entry(&mut editor, &line, history::now());
+    Some(line)
+}
+
+/// How many entries the history keeps without $HISTSIZE, which is rustyline's
+/// own default.
+const DEFAULT_HISTSIZE: usize = 100;
+
+// Puts $HISTSIZE and $HISTCONTROL into effect, right before a line goes into
+// the history, since either can change at any prompt. Without $HISTCONTROL,
+// lines starting with a space still stay out, and repeats go in.
+fn apply_history_settings(editor: &mut Shell) {
+    // A negative size keeps everything, like in bash.
+    let size = match vars::get("HISTSIZE").and_then(|size| size.parse::<i64>().ok()) {
+        Some(size) => usize::try_from(size).unwrap_or(usize::MAX),
+        None => DEFAULT_HISTSIZE,
+    };
+    _ = editor.set_max_history_size(size);
+
+    let (ignore_space, ignore_dups) = match vars::get("HISTCONTROL") {
+        Some(control) => {
+            let has = |word| control.split(':').any(|c| c == word || c == "ignoreboth");
+            (has("ignorespace"), has("ignoredups"))
+        }
+        None => (true, false),
+    };
+    editor.set_history_ignore_space(ignore_space);
+    _ = editor.set_history_ignore_dups(ignore_dups);
+}
+
+/// Keeps asking for lines while the command is still open, like a
+/// here-document waiting for its delimiter. Gives back what was read as an
+/// error if the input ran out first.
+fn read_continuation(
+    mut line: String,
+    mut next_line: impl FnMut() -> Option<String>,
+) -> Result<String, String> {
+    while parser::parse(&line).is_err_and(|err| err.is_incomplete()) {
+        let Some(more) = next_line() else {
+            return Err(line);
+        };
+        line.push('\n');
+        line.push_str(&more);
+    }
+    Ok(line)
+}
+
+// Like run_script, but for a builtin, so the commands use the builtin's pipes.
+fn run_lines(script: &str, session: &Session, pipes: &mut IOPipes) -> io::Result<i32> {
+    let mut status = 0;
+    let mut lines = script.lines();
+    while let Some(line) = lines.next() {
+        // Past an `exit`, `break` or `return`, the rest isn't even parsed.
+        if session.stopping() {
+            break;
+        }
+        let line = read_continuation(line.to_string(), || lines.next().map(String::from))
+            .unwrap_or_else(|partial| partial);
+        status = match parser::parse(&line) {
+            Ok(list) => execute(&list, session, pipes)?,
+            Err(err) => {
+                pipes
+                    .error
+                    .write_all(format!("codecrafters-shell: {}\n", err).as_bytes())?;
+                params::set_last_status(2);
+                2
+            }
+        };
     }
+    Ok(status)
+}
 
-    for mut child in children {
-        child.wait()?;
+// Runs a sourced file's lines in this shell, with the file and line put in
+// front of whatever each prints on stderr, like bash: "rc: line 3: alias: ...".
+// A line that fails doesn't stop the rest, unless `strict`, which breaks off
+// with its status.
+fn source_lines(
+    path: &Path,
+    script: &str,
+    session: &Session,
+    pipes: &IOPipes,
+    strict: bool,
+) -> io::Result<ControlFlow<i32, i32>> {
+    let mut sourcing = session.sourcing.clone();
+    sourcing.push(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    let session = &Session {
+        sourcing,
+        returnable: true,
+        ..session.clone()
+    };
+    let mut status = 0;
+    let mut lines = script.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line = read_continuation(line.to_string(), || {
+            lines.next().map(|(_, line)| line.to_string())
+        })
+        .unwrap_or_else(|partial| partial);
+        let location = format!("{}: line {}: ", path.to_string_lossy(), index + 1);
+        status = run_located(&line, location, session, pipes)?;
+        // `return` stops the file, and isn't a failure even if its status is.
+        if let Some(status) = take_return(session) {
+            return Ok(ControlFlow::Continue(status));
+        }
+        if strict && status != 0 {
+            return Ok(ControlFlow::Break(status));
+        }
     }
+    Ok(ControlFlow::Continue(status))
+}
 
+// Runs one line with `location` before every line it writes on stderr.
+fn run_located(
+    line: &str,
+    location: String,
+    session: &Session,
+    pipes: &IOPipes,
+) -> io::Result<i32> {
+    let (reader, writer) = pipe()?;
+    let mut error = pipes.error.try_clone()?;
+    let prefixer = thread::spawn(move || -> io::Result<()> {
+        for message in BufReader::new(reader).lines() {
+            error.write_all(format!("{}{}\n", location, message?).as_bytes())?;
+        }
+        Ok(())
+    });
+
+    // The pipe closes once these are gone, which ends the prefixing.
+    let mut located = pipes.try_clone()?;
+    located.error = IOSource::PipeWriter(writer);
+    let status = match parser::parse(line) {
+        Ok(list) => execute(&list, session, &located),
+        Err(err) => located
+            .error
+            .write_all(format!("{}\n", err).as_bytes())
+            .map(|_| 2),
+    };
+    drop(located);
+    _ = prefixer.join();
+    let status = status?;
+    params::set_last_status(status);
+    Ok(status)
+}
+
+// Runs the rc file, if there is one, before the first prompt. With
+// `--strict-rc`, the first line to fail ends the shell.
+fn run_rc(session: &Session, strict: bool) -> io::Result<()> {
+    let Ok(path) = paths::rc_file() else {
+        return Ok(());
+    };
+    let script = match fs::read_to_string(&path) {
+        Ok(script) => script,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            eprintln!("codecrafters-shell: {}: {}", path.to_string_lossy(), err);
+            return Ok(());
+        }
+    };
+    if let ControlFlow::Break(status) =
+        source_lines(&path, &script, session, &IOPipes::standard(), strict)?
+    {
+        eprintln!(
+            "codecrafters-shell: {}: stopped at the first error, as --strict-rc asks",
+            path.to_string_lossy()
+        );
+        process::exit(status);
+    }
     Ok(())
 }
 
-// TODO: status codes
-// TODO: input redirection
-// TODO: variable expansion
+// Runs an autoloaded function with its own `$1` onwards, giving the caller's
+// back after.
+fn run_function(
+    function: &functions::Function,
+    args: Vec<String>,
+    pipes: &mut IOPipes,
+    session: Session,
+) -> io::Result<i32> {
+    let session = Session {
+        returnable: true,
+        ..session
+    };
+    let saved = params::positional();
+    params::set_positional(args);
+    let status = run_lines(&function.body, &session, pipes);
+    params::set_positional(saved);
+    Ok(take_return(&session).unwrap_or(status?))
+}
+
+fn run_script(script: &str, session: &Session) -> io::Result<i32> {
+    let mut status = 0;
+    let mut lines = script.lines();
+    while let Some(line) = lines.next() {
+        // Whatever is left still runs, so its syntax error gets reported.
+        let line = read_continuation(line.to_string(), || lines.next().map(String::from))
+            .unwrap_or_else(|partial| partial);
+        status = run_line(&line, session)?;
+    }
+    Ok(status)
+}
+
 fn main() -> io::Result<()> {
-    let shell_helper = ShellHelper {};
+    envdiff::snapshot();
+    let options = parse_options().unwrap_or_else(|err| {
+        eprintln!("codecrafters-shell: {}", err);
+        eprintln!(
+            "Usage: codecrafters-shell [-l] [-o option] [--strict-rc] [--dry-run] [--timeout SECS] [--profile] [--sandbox basic|strict] [-c command [name [arg...]] | script [arg...] | --send pid request...]"
+        );
+        process::exit(2);
+    });
+    if let Some((pid, request)) = &options.send {
+        let status = control::send(*pid, request).unwrap_or_else(|err| {
+            eprintln!("codecrafters-shell: --send: {}: {}", pid, err);
+            1
+        });
+        process::exit(status);
+    }
+    params::set_arguments(options.name.clone(), options.args.clone());
+
+    if let Some(profile) = options.sandbox
+        && let Err(err) = sandbox::enable(profile)
+    {
+        eprintln!("codecrafters-shell: {}", err);
+        process::exit(2);
+    }
+
+    let shell_helper = ShellHelper::default();
     let config = Config::builder()
         .bell_style(BellStyle::Audible)
         .completion_type(CompletionType::List)
@@ -734,33 +4504,98 @@ fn main() -> io::Result<()> {
     let mut editor = Editor::with_config(config).expect("Failed to setup the prompt");
 
     editor.set_helper(Some(shell_helper));
-    editor.set_history_ignore_space(true);
-    _ = editor.set_history_ignore_dups(false);
-    editor.set_auto_add_history(true);
+    apply_history_settings(&mut editor);
+    bindings::install(&mut editor);
 
     let editor = Arc::new(Mutex::new(editor));
     let append_history = Arc::new(Mutex::new(Vec::new()));
 
-    let history_file = env::var("HISTFILE").ok();
+    if let Some(timeout) = options.timeout {
+        spawn_timeout_watchdog(timeout);
+    }
+    if options.profile {
+        profile::enable();
+    }
+
+    let mut session = Session {
+        editor: Arc::clone(&editor),
+        append_history: Arc::clone(&append_history),
+        history_path: None,
+        interactive: false,
+        exit: None,
+        jump: Arc::default(),
+        loops: 0,
+        condition: false,
+        trapped: false,
+        sourcing: Vec::new(),
+        returnable: false,
+        job: None,
+    };
+
+    if let Some(command) = options.command {
+        let status = run_script(&command, &session)?;
+        let_watchdog_finish();
+        profile::report();
+        process::exit(status);
+    }
+
+    if let Some(path) = options.script {
+        let script = fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("codecrafters-shell: {}: {}", path.to_string_lossy(), e);
+            process::exit(127);
+        });
+        let status = run_script(&script, &session)?;
+        let_watchdog_finish();
+        profile::report();
+        process::exit(status);
+    }
+
+    if options.login && crate::options::is_set("agents") {
+        agents::setup();
+    }
+
+    session.interactive = true;
+    signals::install();
+    run_rc(&session, options.strict_rc)?;
+
+    // The rc file may well have set it.
+    let history_file = vars::get("HISTFILE");
+    session.history_path = history_file.clone();
 
     _ = history_read(Arc::clone(&editor), history_file.as_ref());
+    summary::start();
+    replay::load(history_file.as_ref());
+    terminal::set_pane_title(&terminal::cwd_title());
 
+    let mut status = 0;
     loop {
-        let line = match editor
-            .lock()
-            .expect("Couldn't lock the editor!")
-            .readline("$ ")
-        {
+        for line in jobs::take_changed() {
+            eprintln!("{}", line);
+        }
+        let line = {
+            let mut editor = editor.lock().expect("Couldn't lock the editor!");
+            // Only made once a job needs it: while there is one, the editor
+            // misses keys typed ahead of the prompt until the next one comes.
+            if jobs::wants_printer()
+                && let Ok(printer) = editor.create_external_printer()
+            {
+                jobs::set_printer(Box::new(printer));
+            }
+            palette::remember(editor.history().iter());
+            terminal::update_size_vars();
+            if options::is_set("control") {
+                start_control(&session);
+            }
+            editor.readline("$ ")
+        };
+        // The window may have been resized while the line was typed.
+        terminal::update_size_vars();
+        let line = match line {
             Ok(line) => {
                 if line.is_empty() {
                     continue;
-                } else {
-                    append_history
-                        .lock()
-                        .expect("Tried to lock!")
-                        .push(line.clone());
-                    line
                 }
+                line
             }
             Err(ReadlineError::Interrupted) => {
                 println!("^C");
@@ -768,6 +4603,11 @@ fn main() -> io::Result<()> {
             }
             Err(ReadlineError::Eof) => {
                 println!("^D");
+                jobs::count_line();
+                if jobs::hold_exit() {
+                    eprintln!("There are stopped jobs.");
+                    continue;
+                }
                 break;
             }
             Err(err) => {
@@ -776,15 +4616,42 @@ fn main() -> io::Result<()> {
             }
         };
 
-        let inputs = line.split("|").map(|s| s.trim().to_string()).collect_vec();
-        handle(
-            inputs,
-            Arc::clone(&editor),
-            Arc::clone(&append_history),
-            history_file.clone(),
-        )?;
+        // Ctrl-C or Ctrl-D at the secondary prompt drops the whole command.
+        let Ok(line) = read_continuation(line, || {
+            let mut editor = editor.lock().expect("Couldn't lock the editor!");
+            let prompt = vars::get("PS2").unwrap_or_else(|| "> ".to_string());
+            editor.readline(&prompt).ok()
+        }) else {
+            continue;
+        };
+        // Only the whole command goes into the history, so it comes back
+        // as something that runs.
+        let Some(line) = expand_history(line, &editor) else {
+            continue;
+        };
+        append_history
+            .lock()
+            .expect("Tried to lock!")
+            .push(line.clone());
+
+        begin_snapshot(&line, Arc::clone(&editor));
+
+        signals::clear_interrupt();
+        jobs::count_line();
+        let started = Instant::now();
+        terminal::set_pane_title(&line);
+        status = run_line(&line, &session)?;
+        if let Err(err) = replay::finish(history_file.as_ref()) {
+            eprintln!("replay: failed to save snapshot: {}", err);
+        }
+        terminal::set_pane_title(&terminal::cwd_title());
+        summary::record(&line, status, started.elapsed());
+        notify_finished(&line, status, started.elapsed());
     }
 
+    jobs::end_stopped();
     _ = history_write(Arc::clone(&editor), history_file.as_ref());
-    Ok(())
+    summary::report();
+    control::close();
+    process::exit(status);
 }
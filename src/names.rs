@@ -0,0 +1,16 @@
+use std::borrow::Cow;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::options;
+
+/// Prepares a name for comparison. With the `normalize` option on, both sides
+/// are brought to NFC, since macOS hands out NFD filenames while keyboards type
+/// NFC and the two otherwise never match.
+pub fn fold(name: &str) -> Cow<'_, str> {
+    if options::is_set("normalize") {
+        Cow::Owned(name.nfc().collect())
+    } else {
+        Cow::Borrowed(name)
+    }
+}
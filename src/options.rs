@@ -0,0 +1,119 @@
+use std::{collections::BTreeSet, sync::Mutex};
+
+/// Every option `set -o` knows about, with a short description for the listing.
+pub const OPTIONS: [(&str, &str); 21] = [
+    (
+        "capture",
+        "keep the last command's output for $LAST_OUTPUT and !!:out",
+    ),
+    (
+        "notify",
+        "put the status of commands slower than $NOTIFY_SECS in the title",
+    ),
+    ("notifybell", "also ring the bell when notify fires"),
+    (
+        "normalize",
+        "match typed names against NFD filenames (as on macOS)",
+    ),
+    (
+        "physical",
+        "resolve symlinks in cd, pwd and path completion",
+    ),
+    (
+        "winpaths",
+        "translate path arguments between WSL/MSYS and Windows programs",
+    ),
+    (
+        "agents",
+        "at login, export GPG_TTY and start or reuse an ssh-agent",
+    ),
+    (
+        "trash",
+        "keep what > overwrites so undo-clobber can bring it back",
+    ),
+    (
+        "dryrun",
+        "print external commands instead of running them, and only check redirections",
+    ),
+    (
+        "preview",
+        "show the size, age and first line of a completed file under the prompt",
+    ),
+    (
+        "noclobber",
+        "refuse to let > overwrite an existing file; >| still does",
+    ),
+    (
+        "icons",
+        "put Nerd Font icons before file names in completion listings",
+    ),
+    (
+        "fzf",
+        "use fzf for Ctrl-R history, Ctrl-T files and Alt-C directories",
+    ),
+    (
+        "progress",
+        "show a spinner on stderr while a pipeline runs past $PROGRESS_SECS",
+    ),
+    (
+        "exportsize",
+        "export $COLUMNS and $LINES, so programs see the terminal size too",
+    ),
+    (
+        "panetitles",
+        "in tmux or screen, put the running command or the directory in the pane title",
+    ),
+    (
+        "summary",
+        "at exit, print how many commands ran and failed, how long, and the longest",
+    ),
+    (
+        "control",
+        "take eval, get-var and list-jobs requests from --send on a socket",
+    ),
+    (
+        "bufferjobs",
+        "hold what background jobs print until they finish or jobs -o shows it",
+    ),
+    (
+        "replay",
+        "record each line's directory, expanded argv and environment for replay",
+    ),
+    (
+        "enterfg",
+        "on an empty line, Enter offers fg when just one job is stopped",
+    ),
+];
+
+static ENABLED: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+fn lookup(name: &str) -> Option<&'static str> {
+    OPTIONS.iter().map(|(n, _)| *n).find(|n| *n == name)
+}
+
+pub fn is_set(name: &str) -> bool {
+    ENABLED
+        .lock()
+        .expect("Failed to lock options!")
+        .contains(name)
+}
+
+/// Returns false if there is no option with that name.
+pub fn set(name: &str, enabled: bool) -> bool {
+    let Some(name) = lookup(name) else {
+        return false;
+    };
+
+    let mut options = ENABLED.lock().expect("Failed to lock options!");
+    if enabled {
+        options.insert(name);
+    } else {
+        options.remove(name);
+    }
+    true
+}
+
+/// Locks the options for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    ENABLED.lock().expect("Failed to lock options!")
+}
@@ -0,0 +1,227 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use crate::{BUILTINS, snippets};
+
+/// How many matches the palette shows at once.
+const ROWS: usize = 10;
+
+// The editor is locked while it reads a line, so the palette gets its own copy
+// of the history, taken right before each prompt.
+static HISTORY: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn remember<'h>(history: impl DoubleEndedIterator<Item = &'h String>) {
+    let mut recent = HISTORY.lock().expect("Failed to lock palette history!");
+    recent.clear();
+    for line in history.rev() {
+        if !recent.contains(line) {
+            recent.push(line.clone());
+        }
+    }
+}
+
+/// The history as of the last prompt, most recent first and without repeats.
+pub fn recent() -> Vec<String> {
+    HISTORY
+        .lock()
+        .expect("Failed to lock palette history!")
+        .clone()
+}
+
+struct Entry {
+    kind: &'static str,
+    text: String,
+}
+
+// Recent history first, since that is what gets picked most.
+fn entries() -> Vec<Entry> {
+    let history = HISTORY.lock().expect("Failed to lock palette history!");
+    let history = history.iter().map(|line| Entry {
+        kind: "history",
+        text: line.clone(),
+    });
+    let builtins = BUILTINS.iter().map(|name| Entry {
+        kind: "builtin",
+        text: name.to_string(),
+    });
+    let snippets = snippets::names().into_iter().map(|name| Entry {
+        kind: "snippet",
+        text: format!("snippet run {}", name),
+    });
+    history.chain(builtins).chain(snippets).collect()
+}
+
+/// Whether the query's characters all appear in order in the text, ignoring
+/// case. Runs of adjacent characters and matches at word starts score higher.
+fn score(query: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.chars().collect();
+    let mut score = 0;
+    let mut at = 0;
+    let mut last = None;
+
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let found = (at..text.len()).find(|&i| text[i].to_lowercase().eq([q]))?;
+        score += match found {
+            0 => 8,
+            i if !text[i - 1].is_alphanumeric() => 6,
+            _ => 1,
+        };
+        if last.is_some_and(|last| last + 1 == found) {
+            score += 4;
+        }
+        score -= (found - at) as i64 / 4;
+        last = Some(found);
+        at = found + 1;
+    }
+    Some(score)
+}
+
+enum Key {
+    Char(char),
+    Backspace,
+    Up,
+    Down,
+    Enter,
+    Cancel,
+}
+
+fn read_byte() -> io::Result<u8> {
+    let mut byte = 0u8;
+    match unsafe { libc::read(0, (&mut byte as *mut u8).cast(), 1) } {
+        1 => Ok(byte),
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+// Whether more input arrives soon, which tells a lone Esc from the start of
+// an arrow key.
+fn pending() -> bool {
+    let mut fds = libc::pollfd {
+        fd: 0,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, 50) > 0 }
+}
+
+// The terminal is still in the raw mode the line editor put it in.
+fn read_key() -> io::Result<Key> {
+    let byte = read_byte()?;
+    Ok(match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x10 => Key::Up,
+        0x0e => Key::Down,
+        0x1b if !pending() => Key::Cancel,
+        0x1b => match (read_byte()?, read_byte()?) {
+            (b'[' | b'O', b'A') => Key::Up,
+            (b'[' | b'O', b'B') => Key::Down,
+            _ => return read_key(),
+        },
+        byte if byte < 0x20 => Key::Cancel,
+        byte => {
+            let len = match byte {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                0xc0.. => 2,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(read_byte()?);
+            }
+            match String::from_utf8_lossy(&bytes).chars().next() {
+                Some(c) => Key::Char(c),
+                None => return read_key(),
+            }
+        }
+    })
+}
+
+fn columns() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    match unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 => size.ws_col as usize,
+        _ => 80,
+    }
+}
+
+fn clip(text: &str, width: usize) -> String {
+    let text = text.replace(|c: char| c.is_control(), " ");
+    match text.chars().count() > width {
+        true => {
+            text.chars()
+                .take(width.saturating_sub(1))
+                .collect::<String>()
+                + "…"
+        }
+        false => text,
+    }
+}
+
+// Draws the query and the matches under the line being edited, then puts the
+// cursor back on the line's row so the editor can redraw it in place.
+fn draw(tty: &mut File, query: &str, matches: &[&Entry], selected: usize) -> io::Result<()> {
+    let width = columns().saturating_sub(10).max(10);
+    let mut frame = format!("\r\n\x1b[K> {}", query);
+    let top = selected.saturating_sub(ROWS - 1);
+    for (index, entry) in matches.iter().enumerate().skip(top).take(ROWS) {
+        let line = format!("{:<8} {}", entry.kind, clip(&entry.text, width));
+        match index == selected {
+            true => frame += &format!("\r\n\x1b[K\x1b[7m{}\x1b[0m", line),
+            false => frame += &format!("\r\n\x1b[K{}", line),
+        }
+    }
+    let rows = matches.len().saturating_sub(top).min(ROWS) + 1;
+    frame += &format!("\x1b[J\x1b[{}A\r", rows);
+    tty.write_all(frame.as_bytes())?;
+    tty.flush()
+}
+
+/// Lets the user fuzzy-find a builtin, snippet or history line under the
+/// prompt. Gives None if they backed out.
+pub fn pick() -> io::Result<Option<String>> {
+    let mut tty = OpenOptions::new().write(true).open("/dev/tty")?;
+    let entries = entries();
+    let mut query = String::new();
+    let mut selected = 0;
+
+    let picked = loop {
+        let mut matches: Vec<(i64, &Entry)> = entries
+            .iter()
+            .filter_map(|entry| Some((score(&query, &entry.text)?, entry)))
+            .collect();
+        matches.sort_by_key(|(score, _)| -score);
+        let matches: Vec<&Entry> = matches.into_iter().map(|(_, entry)| entry).collect();
+        selected = selected.min(matches.len().saturating_sub(1));
+        draw(&mut tty, &query, &matches, selected)?;
+
+        match read_key()? {
+            Key::Char(c) => {
+                query.push(c);
+                selected = 0;
+            }
+            Key::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            Key::Up => selected = selected.saturating_sub(1),
+            Key::Down => selected += 1,
+            Key::Enter => break matches.get(selected).map(|entry| entry.text.clone()),
+            Key::Cancel => break None,
+        }
+    };
+
+    tty.write_all(b"\r\n\x1b[J\x1b[A\r")?;
+    tty.flush()?;
+    Ok(picked)
+}
+
+/// Locks the palette's history for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    HISTORY.lock().expect("Failed to lock palette history!")
+}
@@ -0,0 +1,122 @@
+use std::{
+    process,
+    sync::{
+        Mutex,
+        atomic::{AtomicI32, AtomicU32, Ordering},
+    },
+};
+
+use crate::vars;
+
+/// Where `$@` and unquoted `$*` break into separate arguments. No argument can
+/// hold a NUL, so it can't be mistaken for part of one.
+pub const FIELD_BREAK: char = '\0';
+
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+
+// The last process started in the background, or 0 before there is one.
+static LAST_BACKGROUND: AtomicU32 = AtomicU32::new(0);
+
+// `$0` first, then `$1` onwards.
+static ARGUMENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Records the status of the pipeline that just finished, for `$?`.
+pub fn set_last_status(status: i32) {
+    LAST_STATUS.store(status, Ordering::SeqCst);
+}
+
+/// Records the process a job just started, for `$!`.
+pub fn set_last_background(pid: u32) {
+    LAST_BACKGROUND.store(pid, Ordering::SeqCst);
+}
+
+/// Sets `$0` and the positional parameters, once at startup.
+pub fn set_arguments(name: String, args: Vec<String>) {
+    let mut arguments = ARGUMENTS.lock().expect("Failed to lock arguments!");
+    *arguments = [name].into_iter().chain(args).collect();
+}
+
+/// Replaces `$1` onwards, keeping `$0`, for `set --`.
+pub fn set_positional(args: Vec<String>) {
+    let mut arguments = ARGUMENTS.lock().expect("Failed to lock arguments!");
+    arguments.truncate(1);
+    arguments.extend(args);
+}
+
+/// The status `$?` holds, for putting it back after a trap runs.
+pub fn last_status() -> i32 {
+    LAST_STATUS.load(Ordering::SeqCst)
+}
+
+/// `$1` onwards, for putting them back after a function runs.
+pub fn positional() -> Vec<String> {
+    ARGUMENTS
+        .lock()
+        .expect("Failed to lock arguments!")
+        .iter()
+        .skip(1)
+        .cloned()
+        .collect()
+}
+
+/// Whether `name` is one of the parameters the shell sets itself rather than
+/// a variable: `?`, `$`, `!`, `#`, `@`, `*` or a number.
+pub fn is_special(name: &str) -> bool {
+    matches!(name, "?" | "$" | "!" | "#" | "@" | "*")
+        || !name.is_empty() && name.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The value of a special parameter. `quoted` is whether it appeared inside
+/// double quotes, which is all that tells `"$*"` (one argument, joined by the
+/// first character of $IFS) from `$*` and `$@` (one argument each).
+pub fn get(name: &str, quoted: bool) -> String {
+    match name {
+        "?" => LAST_STATUS.load(Ordering::SeqCst).to_string(),
+        "$" => process::id().to_string(),
+        "!" => match LAST_BACKGROUND.load(Ordering::SeqCst) {
+            0 => String::new(),
+            pid => pid.to_string(),
+        },
+        "#" => positional().len().to_string(),
+        "*" if quoted => {
+            let separator = match vars::get("IFS") {
+                Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                None => " ".to_string(),
+            };
+            positional().join(&separator)
+        }
+        "@" | "*" => positional().join(&FIELD_BREAK.to_string()),
+        number => {
+            let index: usize = number.parse().unwrap_or(usize::MAX);
+            ARGUMENTS
+                .lock()
+                .expect("Failed to lock arguments!")
+                .get(index)
+                .cloned()
+                .unwrap_or_default()
+        }
+    }
+}
+
+/// Whether a special parameter has a value, for operators like `${1:-x}`.
+pub fn is_set(name: &str) -> bool {
+    match name {
+        "!" => LAST_BACKGROUND.load(Ordering::SeqCst) != 0,
+        name if name.chars().all(|c| c.is_ascii_digit()) => {
+            let index: usize = name.parse().unwrap_or(usize::MAX);
+            index < ARGUMENTS.lock().expect("Failed to lock arguments!").len()
+        }
+        _ => true,
+    }
+}
+
+/// Whether `"$@"` has nothing to expand to, in which case it disappears
+/// instead of leaving an empty argument.
+pub fn no_positional() -> bool {
+    positional().is_empty()
+}
+
+/// Locks the positional parameters for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    ARGUMENTS.lock().expect("Failed to lock arguments!")
+}
@@ -0,0 +1,619 @@
+use std::{collections::BTreeSet, iter::Peekable, vec};
+
+use thiserror::Error;
+
+use crate::{
+    aliases, expand,
+    lexer::{self, LexError, Token, Word, WordPart},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectMode {
+    Read,
+    Truncate,
+    /// `>|`, which truncates even with noclobber set.
+    Clobber,
+    Append,
+    /// Feeds the target, a here-document body, to the command's stdin.
+    HereDoc,
+    /// Feeds the expanded target and a newline to the command's stdin.
+    HereString,
+    /// Makes the fd share whatever the fd named by the target currently points
+    /// at, so `> log 2>&1` and `2>&1 > log` differ like they do in sh.
+    Duplicate,
+}
+
+/// The fd `&>` redirects: stdout and stderr, sharing one file.
+pub const BOTH: i32 = -1;
+
+/// Every operator a word can be, with the fd it redirects when no number
+/// comes before it.
+const REDIRECTIONS: [(&str, i32, RedirectMode); 9] = [
+    ("<", 0, RedirectMode::Read),
+    (">", 1, RedirectMode::Truncate),
+    (">|", 1, RedirectMode::Clobber),
+    (">>", 1, RedirectMode::Append),
+    ("<<<", 0, RedirectMode::HereString),
+    ("<&", 0, RedirectMode::Duplicate),
+    (">&", 1, RedirectMode::Duplicate),
+    ("&>", BOTH, RedirectMode::Truncate),
+    ("&>>", BOTH, RedirectMode::Append),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub mode: RedirectMode,
+    pub target: Word,
+}
+
+/// Commands grouped so they share one set of redirections.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Group {
+    /// `( list )`, whose `cd`s and variables don't reach the shell around it.
+    Subshell(List),
+    /// `{ list; }`, which runs in the current shell.
+    Braces(List),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`: the
+    /// body of the first condition that succeeds, or `otherwise`.
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while list; do list; done`, or with `until`, which runs the body
+    /// for as long as the condition fails instead.
+    Loop {
+        until: bool,
+        condition: List,
+        body: List,
+    },
+    /// `(( expr ))`, which succeeds if the expression comes out non-zero.
+    Arith(String),
+    /// `for (( init; condition; step )); do list; done`, like C's. An empty
+    /// condition is always true.
+    For {
+        init: String,
+        condition: String,
+        step: String,
+        body: List,
+    },
+    /// `select name [in word...]; do list; done`: a numbered menu of the
+    /// words, or of `"$@"` without `in`, which runs the body with the pick in
+    /// `name` until it breaks out or the input ends.
+    Select {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: List,
+    },
+    /// `case word in pattern[|pattern]...) list;; ... esac`: the body of the
+    /// first arm with a glob pattern that matches the word.
+    Case {
+        subject: Word,
+        arms: Vec<(Vec<Word>, List)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Command {
+    /// `NAME=value` words in front of the command, set in its environment
+    /// only. With no command after them, they are set in the shell's.
+    pub assignments: Vec<(String, Word)>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+    /// Runs in place of words.
+    pub group: Option<Group>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+/// Pipelines joined by `&&`/`||`, each one only run if the status so far allows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    /// Set when it ends in `&`, so the shell goes on without waiting for it,
+    /// to the text it is shown by in `jobs`.
+    pub background: Option<String>,
+}
+
+/// `;`-separated commands, run one after another.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseError {
+    #[error(transparent)]
+    Lex(#[from] LexError),
+    #[error("syntax error near unexpected token `{0}'")]
+    UnexpectedToken(String),
+    #[error("syntax error: unexpected end of file")]
+    UnexpectedEof,
+    #[error("unexpected EOF while looking for matching `{0}'")]
+    Unclosed(&'static str),
+}
+
+impl ParseError {
+    /// Whether more input lines could still complete the command, as when it
+    /// ends in `|`, `&&` or `||` and the command after is on the next line.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ParseError::Lex(
+                LexError::UnterminatedHereDoc(_)
+                    | LexError::UnterminatedQuote(_)
+                    | LexError::TrailingBackslash
+            ) | ParseError::Unclosed(_)
+                | ParseError::UnexpectedEof
+        )
+    }
+}
+
+fn redirection(word: &Word) -> Option<(i32, RedirectMode)> {
+    let word = word.bare()?;
+    let op = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    let (_, default, mode) = REDIRECTIONS.iter().find(|(name, _, _)| *name == op)?;
+
+    let number = &word[..word.len() - op.len()];
+    if number.is_empty() {
+        return Some((*default, *mode));
+    }
+    // `&>` always means stdout and stderr, so there is no fd to give it.
+    if *default == BOTH {
+        return None;
+    }
+    Some((number.parse().ok()?, *mode))
+}
+
+type Tokens = Peekable<vec::IntoIter<Token>>;
+
+/// Where a list ends: at the end of the input, or at what closes the group
+/// it is in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Until {
+    Eof,
+    RParen,
+    /// Any of these reserved words, the last of which ends the whole group.
+    Reserved(&'static [&'static str]),
+    /// The `;;` or `esac` after an arm of a `case`.
+    Arm,
+}
+
+impl Until {
+    // Only checked where a command would start, so `echo }` is just an
+    // argument, like in sh.
+    fn closes(self, token: &Token) -> bool {
+        match self {
+            Until::Eof => false,
+            Until::RParen => *token == Token::RParen,
+            Until::Reserved(words) => words.iter().any(|word| is_reserved(token, word)),
+            Until::Arm => *token == Token::DoubleSemicolon || is_reserved(token, "esac"),
+        }
+    }
+
+    fn closer(self) -> &'static str {
+        match self {
+            Until::Reserved(words) => words.last().copied().unwrap_or(")"),
+            Until::Arm => "esac",
+            _ => ")",
+        }
+    }
+}
+
+/// Reserved words that only mean something partway through an `if`, a loop or
+/// a `case`.
+const INNER_WORDS: [&str; 7] = ["then", "elif", "else", "fi", "do", "done", "esac"];
+
+fn is_reserved(token: &Token, word: &str) -> bool {
+    matches!(token, Token::Word(w) if w.bare() == Some(word))
+}
+
+// Splits `NAME=value` into the name and the value, which can be quoted like
+// any word: `FOO="a b"` is `FOO=` followed by a double-quoted part.
+fn assignment(word: &Word) -> Option<(String, Word)> {
+    let Some(WordPart::Bare(first)) = word.0.first() else {
+        return None;
+    };
+    let (name, value) = first.split_once('=')?;
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return None;
+    }
+
+    let mut parts = word.0[1..].to_vec();
+    if !value.is_empty() {
+        parts.insert(0, WordPart::Bare(value.to_string()));
+    }
+    Some((name.to_string(), Word(parts)))
+}
+
+// Reads the list of a group whose opener was taken, and the closer after it,
+// which is given back for an `if` to tell `elif`, `else` and `fi` apart.
+fn parse_group(tokens: &mut Tokens, until: Until) -> Result<(List, Token), ParseError> {
+    // A reserved word is a word to the lexer, so a newline after it turns
+    // into `;`.
+    if let Until::Reserved(_) = until {
+        while tokens.next_if_eq(&Token::Semicolon).is_some() {}
+    }
+    let list = parse_list(tokens, until)?;
+    match tokens.next() {
+        Some(token) if list.items.is_empty() => Err(ParseError::UnexpectedToken(token.to_string())),
+        Some(token) if until.closes(&token) => Ok((list, token)),
+        Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+        None => Err(ParseError::Unclosed(until.closer())),
+    }
+}
+
+// Reads an `if` whose `if` was taken, up to and including its `fi`.
+fn parse_if(tokens: &mut Tokens) -> Result<Group, ParseError> {
+    let mut branches = Vec::new();
+    loop {
+        let (condition, _) = parse_group(tokens, Until::Reserved(&["then"]))?;
+        let (body, closer) = parse_group(tokens, Until::Reserved(&["elif", "else", "fi"]))?;
+        branches.push((condition, body));
+        if is_reserved(&closer, "elif") {
+            continue;
+        }
+        let otherwise = match is_reserved(&closer, "else") {
+            true => Some(parse_group(tokens, Until::Reserved(&["fi"]))?.0),
+            false => None,
+        };
+        return Ok(Group::If {
+            branches,
+            otherwise,
+        });
+    }
+}
+
+// Reads a `case` whose `case` was taken, up to and including its `esac`.
+fn parse_case(tokens: &mut Tokens) -> Result<Group, ParseError> {
+    let subject = match tokens.next() {
+        Some(Token::Word(word)) => word,
+        Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+        None => return Err(ParseError::Unclosed("esac")),
+    };
+    while tokens.next_if_eq(&Token::Semicolon).is_some() {}
+    match tokens.next() {
+        Some(token) if is_reserved(&token, "in") => {}
+        Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+        None => return Err(ParseError::Unclosed("esac")),
+    }
+
+    let mut arms = Vec::new();
+    loop {
+        while tokens.next_if_eq(&Token::Semicolon).is_some() {}
+        if tokens.next_if(|token| is_reserved(token, "esac")).is_some() {
+            return Ok(Group::Case { subject, arms });
+        }
+
+        // The `(` before the patterns is optional, as in sh.
+        tokens.next_if_eq(&Token::LParen);
+        let mut patterns = Vec::new();
+        loop {
+            match tokens.next() {
+                Some(Token::Word(pattern)) => patterns.push(pattern),
+                Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+                None => return Err(ParseError::Unclosed("esac")),
+            }
+            match tokens.next() {
+                Some(Token::Pipe) => {}
+                Some(Token::RParen) => break,
+                Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+                None => return Err(ParseError::Unclosed("esac")),
+            }
+        }
+
+        // Unlike other groups, an arm may have nothing to run.
+        while tokens.next_if_eq(&Token::Semicolon).is_some() {}
+        let body = parse_list(tokens, Until::Arm)?;
+        arms.push((patterns, body));
+        match tokens.next() {
+            Some(Token::DoubleSemicolon) => {}
+            Some(_) => return Ok(Group::Case { subject, arms }),
+            None => return Err(ParseError::Unclosed("esac")),
+        }
+    }
+}
+
+// Reads a `for` whose `for` was taken, up to and including its `done`. Only
+// the arithmetic kind is supported.
+fn parse_for(tokens: &mut Tokens) -> Result<Group, ParseError> {
+    let header = match tokens.next() {
+        Some(Token::Arith(header)) => header,
+        Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+        None => return Err(ParseError::Unclosed("done")),
+    };
+    let [init, condition, step] = header
+        .split(';')
+        .map(|expr| expr.trim().to_string())
+        .collect::<Vec<_>>()
+        .try_into()
+        .map_err(|_| ParseError::UnexpectedToken(format!("(({}))", header)))?;
+
+    Ok(Group::For {
+        init,
+        condition,
+        step,
+        body: parse_do(tokens)?,
+    })
+}
+
+// Reads a `select` whose `select` was taken, up to and including its `done`.
+fn parse_select(tokens: &mut Tokens) -> Result<Group, ParseError> {
+    let name = match tokens.next() {
+        Some(Token::Word(word)) if word.bare().is_some_and(expand::is_name) => word.text(),
+        Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+        None => return Err(ParseError::Unclosed("done")),
+    };
+    while tokens.next_if_eq(&Token::Semicolon).is_some() {}
+    let words = match tokens.next_if(|token| is_reserved(token, "in")) {
+        Some(_) => {
+            let mut words = Vec::new();
+            while let Some(Token::Word(word)) = tokens.next_if(|t| matches!(t, Token::Word(_))) {
+                words.push(word);
+            }
+            Some(words)
+        }
+        None => None,
+    };
+    Ok(Group::Select {
+        name,
+        words,
+        body: parse_do(tokens)?,
+    })
+}
+
+// Reads the `do list; done` ending a loop, after the `;` or newlines before
+// it.
+fn parse_do(tokens: &mut Tokens) -> Result<List, ParseError> {
+    while tokens.next_if_eq(&Token::Semicolon).is_some() {}
+    match tokens.next() {
+        Some(token) if is_reserved(&token, "do") => {}
+        Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+        None => return Err(ParseError::Unclosed("done")),
+    }
+    Ok(parse_group(tokens, Until::Reserved(&["done"]))?.0)
+}
+
+// The alias a token names, if it is one that hasn't been expanded yet in this
+// command. Quoting any of the word, like `\ls`, keeps it from being one.
+fn alias(token: &Token, expanded: &BTreeSet<String>) -> Option<(String, String)> {
+    let Token::Word(word) = token else {
+        return None;
+    };
+    let name = word.bare().filter(|name| !expanded.contains(*name))?;
+    Some((name.to_string(), aliases::get(name)?))
+}
+
+// Replaces an alias starting a command with the tokens it stands for, over
+// and over, so one alias can use another. An alias is never expanded twice in
+// one command, which ends cycles like `alias ls='ls -F'` or `a` and `b`
+// standing for each other. When an alias ends in a blank, the word after it
+// is checked as well, so `alias sudo='sudo '` lets `sudo ll` work.
+fn expand_aliases(tokens: &mut Tokens) -> Result<(), ParseError> {
+    let mut expanded = BTreeSet::new();
+    if tokens
+        .peek()
+        .is_none_or(|token| alias(token, &expanded).is_none())
+    {
+        return Ok(());
+    }
+
+    // The tokens taken off the front so far, and where the next word to check
+    // is in them. The words after aliases ending in a blank wait their turn.
+    let mut front: Vec<Token> = Vec::new();
+    let mut at = 0;
+    let mut pending: Vec<usize> = Vec::new();
+    loop {
+        if at == front.len() {
+            front.extend(tokens.next());
+        }
+        let Some((name, value)) = front.get(at).and_then(|token| alias(token, &expanded)) else {
+            match pending.pop() {
+                Some(next) => at = next,
+                None => break,
+            }
+            continue;
+        };
+
+        let replacement = lexer::tokenize(&value)?;
+        let len = replacement.len();
+        front.splice(at..=at, replacement);
+        for next in &mut pending {
+            *next = *next + len - 1;
+        }
+        let next = at + len;
+        if value.ends_with(char::is_whitespace) && !pending.contains(&next) {
+            pending.push(next);
+        }
+        expanded.insert(name);
+    }
+
+    let rest: Vec<Token> = front.into_iter().chain(tokens.by_ref()).collect();
+    *tokens = rest.into_iter().peekable();
+    Ok(())
+}
+
+fn parse_command(tokens: &mut Tokens) -> Result<Command, ParseError> {
+    expand_aliases(tokens)?;
+    let mut command = Command::default();
+    if tokens.next_if_eq(&Token::LParen).is_some() {
+        command.group = Some(Group::Subshell(parse_group(tokens, Until::RParen)?.0));
+    } else if tokens.next_if(|token| is_reserved(token, "{")).is_some() {
+        command.group = Some(Group::Braces(
+            parse_group(tokens, Until::Reserved(&["}"]))?.0,
+        ));
+    } else if tokens.next_if(|token| is_reserved(token, "if")).is_some() {
+        command.group = Some(parse_if(tokens)?);
+    } else if tokens.next_if(|token| is_reserved(token, "case")).is_some() {
+        command.group = Some(parse_case(tokens)?);
+    } else if tokens.next_if(|token| is_reserved(token, "for")).is_some() {
+        command.group = Some(parse_for(tokens)?);
+    } else if tokens
+        .next_if(|token| is_reserved(token, "select"))
+        .is_some()
+    {
+        command.group = Some(parse_select(tokens)?);
+    } else if let Some(Token::Arith(expr)) = tokens.next_if(|t| matches!(t, Token::Arith(_))) {
+        command.group = Some(Group::Arith(expr));
+    } else if let Some(token) =
+        tokens.next_if(|token| is_reserved(token, "while") || is_reserved(token, "until"))
+    {
+        command.group = Some(Group::Loop {
+            until: is_reserved(&token, "until"),
+            condition: parse_group(tokens, Until::Reserved(&["do"]))?.0,
+            body: parse_group(tokens, Until::Reserved(&["done"]))?.0,
+        });
+    } else if let Some(token) =
+        tokens.next_if(|token| INNER_WORDS.iter().any(|w| is_reserved(token, w)))
+    {
+        return Err(ParseError::UnexpectedToken(token.to_string()));
+    }
+
+    while let Some(token) = tokens.next_if(|t| matches!(t, Token::Word(_) | Token::HereDoc(_))) {
+        let word = match token {
+            Token::HereDoc(body) => {
+                command.redirects.push(Redirect {
+                    fd: 0,
+                    mode: RedirectMode::HereDoc,
+                    target: body,
+                });
+                continue;
+            }
+            Token::Word(word) => word,
+            _ => unreachable!("only words and here-documents are taken"),
+        };
+        let Some((fd, mode)) = redirection(&word) else {
+            // Only redirections may follow the `)` or `}`.
+            if command.group.is_some() {
+                return Err(ParseError::UnexpectedToken(word.text()));
+            }
+            if command.words.is_empty()
+                && let Some(assignment) = assignment(&word)
+            {
+                command.assignments.push(assignment);
+                continue;
+            }
+            command.words.push(word);
+            continue;
+        };
+
+        let target = match tokens.next() {
+            Some(Token::Word(target)) if redirection(&target).is_none() => target,
+            Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+            // Like in sh, the target has to be on the same line.
+            None => return Err(ParseError::UnexpectedToken("newline".to_string())),
+        };
+        // `>& file` is the older spelling of `&> file`.
+        let (fd, mode) = match mode {
+            RedirectMode::Duplicate
+                if fd == 1
+                    && !target
+                        .bare()
+                        .is_some_and(|s| s.chars().all(|c| c.is_ascii_digit())) =>
+            {
+                (BOTH, RedirectMode::Truncate)
+            }
+            _ => (fd, mode),
+        };
+        command.redirects.push(Redirect { fd, mode, target });
+    }
+
+    // Redirections can come anywhere, even on their own: `> file` just
+    // creates the file.
+    let empty = command.assignments.is_empty()
+        && command.words.is_empty()
+        && command.redirects.is_empty()
+        && command.group.is_none();
+    match tokens.peek() {
+        Some(token) if empty => Err(ParseError::UnexpectedToken(token.to_string())),
+        None if empty => Err(ParseError::UnexpectedEof),
+        _ => Ok(command),
+    }
+}
+
+fn parse_pipeline(tokens: &mut Tokens) -> Result<Pipeline, ParseError> {
+    let mut commands = vec![parse_command(tokens)?];
+
+    while tokens.next_if_eq(&Token::Pipe).is_some() {
+        commands.push(parse_command(tokens)?);
+    }
+
+    Ok(Pipeline { commands })
+}
+
+fn parse_and_or(tokens: &mut Tokens) -> Result<AndOr, ParseError> {
+    let first = parse_pipeline(tokens)?;
+    let mut rest = Vec::new();
+
+    loop {
+        let connector = match tokens.next_if(|t| matches!(t, Token::And | Token::Or)) {
+            Some(Token::And) => Connector::And,
+            Some(_) => Connector::Or,
+            None => break,
+        };
+        rest.push((connector, parse_pipeline(tokens)?));
+    }
+
+    Ok(AndOr {
+        first,
+        rest,
+        background: None,
+    })
+}
+
+// Tokens put back together, spaced about the way they would be typed.
+fn text(tokens: impl Iterator<Item = Token>) -> String {
+    let mut text = String::new();
+    for token in tokens {
+        if !text.is_empty() && !matches!(token, Token::Semicolon | Token::DoubleSemicolon) {
+            text.push(' ');
+        }
+        text.push_str(&token.source());
+    }
+    text
+}
+
+// Inside a group, the list stops at the closer and leaves it for the caller.
+fn parse_list(tokens: &mut Tokens, until: Until) -> Result<List, ParseError> {
+    let mut items = Vec::new();
+
+    while tokens.peek().is_some_and(|token| !until.closes(token)) {
+        let start = tokens.clone();
+        let mut and_or = parse_and_or(tokens)?;
+
+        match tokens.next_if(|token| !until.closes(token)) {
+            Some(Token::Semicolon) | None => {}
+            Some(Token::Background) => {
+                let used = start.len() - tokens.len() - 1;
+                and_or.background = Some(text(start.take(used)));
+            }
+            Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+        }
+        items.push(and_or);
+    }
+
+    Ok(List { items })
+}
+
+/// Parses one input line. Blank lines give an empty list.
+pub fn parse(input: &str) -> Result<List, ParseError> {
+    parse_list(
+        &mut lexer::tokenize(input)?.into_iter().peekable(),
+        Until::Eof,
+    )
+}
@@ -0,0 +1,96 @@
+use std::{
+    env, fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{options, vars};
+
+/// Drops `.` and folds `..` into its parent without touching the filesystem,
+/// which is what keeps `cd link/..` next to `link` instead of its target.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// The working directory as the user got there, symlinks included. $PWD is
+/// only trusted while it still names the directory we are actually in, or
+/// when that directory has been removed and $PWD is all that says where it
+/// was.
+pub fn logical_cwd() -> io::Result<PathBuf> {
+    let pwd = vars::get("PWD")
+        .map(PathBuf::from)
+        .filter(|pwd| pwd.is_absolute());
+    let physical = match env::current_dir() {
+        Ok(physical) => physical,
+        Err(err) => return pwd.ok_or(err),
+    };
+    match pwd {
+        Some(pwd) if same_file(&pwd, &physical) => Ok(pwd),
+        _ => Ok(physical),
+    }
+}
+
+/// Whether `cd`, `pwd` and completion should see through symlinks by default.
+pub fn physical() -> bool {
+    options::is_set("physical")
+}
+
+/// Where a path typed at the prompt points. Logically `..` undoes the last
+/// component as typed; physically it is left for the kernel to resolve, as it
+/// also is when nothing knows where we are any more.
+pub fn resolve(path: &Path, physical: bool) -> PathBuf {
+    match logical_cwd() {
+        Ok(cwd) if !physical => normalize(&cwd.join(path)),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Whether bare command names may run executables from `dir`. Nothing is
+/// trusted unless it is listed in the colon-separated $TRUSTED_DIRS.
+pub fn trusted(dir: &Path) -> bool {
+    let Some(trusted) = env::var_os("TRUSTED_DIRS") else {
+        return false;
+    };
+    let Ok(dir) = dir.canonicalize() else {
+        return false;
+    };
+
+    env::split_paths(&trusted)
+        .filter(|path| path.is_absolute())
+        .filter_map(|path| path.canonicalize().ok())
+        .any(|path| path == dir)
+}
+
+/// Where the shell keeps files the user edits, like snippets.
+pub fn config_dir() -> io::Result<PathBuf> {
+    let config = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => env::home_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?
+            .join(".config"),
+    };
+    Ok(config.join("codecrafters-shell"))
+}
+
+/// The file an interactive shell runs before its first prompt, like bash's
+/// ~/.bashrc.
+pub fn rc_file() -> io::Result<PathBuf> {
+    Ok(config_dir()?.join("rc"))
+}
@@ -0,0 +1,61 @@
+use std::{fs, io};
+
+use crate::{glob, paths};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// Runs only after the user says yes.
+    Confirm,
+    Deny,
+}
+
+/// Used when there is no policy file. Patterns are globs matched against the
+/// expanded command, its words joined by single spaces.
+const DEFAULTS: [(Verdict, &str); 4] = [
+    (Verdict::Confirm, "rm -[rRf]* /"),
+    (Verdict::Confirm, "mkfs*"),
+    (Verdict::Confirm, "dd * of=/dev/*"),
+    (Verdict::Deny, ":(){*"),
+];
+
+// One rule per line, `deny PATTERN` or `confirm PATTERN`. Blank lines and
+// lines starting with `#` are skipped.
+fn parse(text: &str) -> Vec<(Verdict, String)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (verdict, pattern) = line.split_once(char::is_whitespace)?;
+            let verdict = match verdict {
+                "deny" => Verdict::Deny,
+                "confirm" => Verdict::Confirm,
+                _ => return None,
+            };
+            Some((verdict, pattern.trim().to_string()))
+        })
+        .collect()
+}
+
+fn rules() -> io::Result<Vec<(Verdict, String)>> {
+    match fs::read_to_string(paths::config_dir()?.join("policy")) {
+        Ok(text) => Ok(parse(&text)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(DEFAULTS
+            .iter()
+            .map(|(verdict, pattern)| (*verdict, pattern.to_string()))
+            .collect()),
+        Err(err) => Err(err),
+    }
+}
+
+/// What to do with a command about to run. The first matching rule wins.
+pub fn check(args: &[String]) -> Verdict {
+    let line = args.join(" ");
+    let Ok(rules) = rules() else {
+        return Verdict::Allow;
+    };
+    rules
+        .iter()
+        .find(|(_, pattern)| glob::matches(pattern, &line))
+        .map_or(Verdict::Allow, |(verdict, _)| *verdict)
+}
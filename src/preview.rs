@@ -0,0 +1,105 @@
+use std::{
+    ffi::CStr,
+    fs::{self, File, Metadata},
+    io::Read,
+    os::unix::fs::MetadataExt,
+    path::Path,
+};
+
+use rustyline::hint::Hint;
+
+use crate::{options, paths};
+
+/// A line about a file, shown under the prompt. Unlike a plain string hint it
+/// has nothing for the right arrow to insert.
+pub struct Preview(String);
+
+impl Hint for Preview {
+    fn display(&self) -> &str {
+        &self.0
+    }
+
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+pub fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn local_time(secs: i64) -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let mut buf = [0 as libc::c_char; 32];
+    let written = unsafe {
+        if libc::localtime_r(&secs, &mut tm).is_null() {
+            return "?".to_string();
+        }
+        libc::strftime(buf.as_mut_ptr(), buf.len(), c"%Y-%m-%d %H:%M".as_ptr(), &tm)
+    };
+    match written {
+        0 => "?".to_string(),
+        _ => unsafe { CStr::from_ptr(buf.as_ptr()) }
+            .to_string_lossy()
+            .to_string(),
+    }
+}
+
+// Only the start of the file is read, so a huge one doesn't stall typing.
+fn first_line(path: &Path) -> String {
+    let mut buf = [0; 512];
+    let Ok(read) = File::open(path).and_then(|mut file| file.read(&mut buf)) else {
+        return "unreadable".to_string();
+    };
+    let start = &buf[..read];
+    if start.contains(&0) {
+        return "binary".to_string();
+    }
+
+    let text = String::from_utf8_lossy(start);
+    let line: String = text
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(60)
+        .collect();
+    format!("\"{}\"", line)
+}
+
+fn describe(path: &Path, metadata: &Metadata) -> String {
+    format!(
+        "{}, modified {}, {}",
+        size(metadata.len()),
+        local_time(metadata.mtime()),
+        first_line(path)
+    )
+}
+
+/// With the `preview` option on, describes the file named by the argument
+/// just before the cursor, which is where completing a file leaves it.
+pub fn hint(line: &str, pos: usize) -> Option<Preview> {
+    if pos != line.len() || !options::is_set("preview") {
+        return None;
+    }
+
+    let before = line.strip_suffix(' ').unwrap_or(line);
+    let word = &before[before.rfind(' ')? + 1..];
+    if word.is_empty() {
+        return None;
+    }
+    let path = paths::resolve(Path::new(word), paths::physical());
+    let metadata = fs::metadata(&path).ok().filter(Metadata::is_file)?;
+    Some(Preview(format!("\n{}", describe(&path, &metadata))))
+}
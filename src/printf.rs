@@ -0,0 +1,330 @@
+use std::{iter::Peekable, slice, str::Chars};
+
+use thiserror::Error;
+
+use crate::arith::{self, Value};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum FormatError {
+    #[error("{0}: invalid number")]
+    InvalidNumber(String),
+    #[error("%{0}: invalid directive")]
+    InvalidDirective(char),
+    #[error("%: missing format character")]
+    MissingDirective,
+}
+
+/// Everything between the `%` and the conversion character.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    /// `'`, which groups the integer part in thousands.
+    group: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn parse(chars: &mut Peekable<Chars>) -> Spec {
+        let mut spec = Spec::default();
+        while let Some(flag) = chars.next_if(|c| "-0+ #'".contains(*c)) {
+            match flag {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                _ => spec.group = true,
+            }
+        }
+
+        spec.width = digits(chars).unwrap_or(0);
+        if chars.next_if_eq(&'.').is_some() {
+            spec.precision = Some(digits(chars).unwrap_or(0));
+        }
+        spec
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        match negative {
+            true => "-",
+            false if self.plus => "+",
+            false if self.space => " ",
+            false => "",
+        }
+    }
+
+    fn pad(&self, prefix: &str, body: &str, numeric: bool) -> String {
+        let len = prefix.chars().count() + body.chars().count();
+        let fill = self.width.saturating_sub(len);
+
+        if self.left {
+            format!("{}{}{}", prefix, body, " ".repeat(fill))
+        } else if self.zero && numeric {
+            format!("{}{}{}", prefix, "0".repeat(fill), body)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), prefix, body)
+        }
+    }
+}
+
+fn digits(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        number.push(c);
+    }
+    number.parse().ok()
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+// Backslash escapes, shared by the format string itself and `%b` arguments.
+fn escape(chars: &mut Peekable<Chars>, out: &mut String) {
+    let Some(c) = chars.next() else {
+        out.push('\\');
+        return;
+    };
+
+    match c {
+        'n' => out.push('\n'),
+        't' => out.push('\t'),
+        'r' => out.push('\r'),
+        'a' => out.push('\x07'),
+        'e' => out.push('\x1b'),
+        '\\' => out.push('\\'),
+        '0' => {
+            let mut code = 0;
+            for _ in 0..3 {
+                match chars.next_if(|c| ('0'..='7').contains(c)) {
+                    Some(digit) => code = code * 8 + digit.to_digit(8).unwrap_or(0),
+                    None => break,
+                }
+            }
+            out.extend(char::from_u32(code));
+        }
+        _ => {
+            out.push('\\');
+            out.push(c);
+        }
+    }
+}
+
+fn unescape(arg: &str) -> String {
+    let mut out = String::new();
+    let mut chars = arg.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escape(&mut chars, &mut out),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn number(arg: &str) -> Result<Value, FormatError> {
+    // A leading quote gives the character's code, like in every other printf.
+    if let Some(c) = arg
+        .strip_prefix(['\'', '"'])
+        .and_then(|rest| rest.chars().next())
+    {
+        return Ok(Value::Int(c as i64));
+    }
+
+    let arg = arg.trim();
+    if arg.is_empty() {
+        return Ok(Value::Int(0));
+    }
+    let (negative, literal) = match arg.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, arg.strip_prefix('+').unwrap_or(arg)),
+    };
+
+    match arith::parse_number(literal) {
+        Ok(Value::Int(n)) if negative => Ok(Value::Int(-n)),
+        Ok(Value::Float(x)) if negative => Ok(Value::Float(-x)),
+        Ok(value) => Ok(value),
+        Err(_) => Err(FormatError::InvalidNumber(arg.to_string())),
+    }
+}
+
+fn integer(arg: &str) -> Result<i64, FormatError> {
+    match number(arg)? {
+        Value::Int(n) => Ok(n),
+        Value::Float(_) => Err(FormatError::InvalidNumber(arg.to_string())),
+    }
+}
+
+fn float(arg: &str) -> Result<f64, FormatError> {
+    match number(arg)? {
+        Value::Int(n) => Ok(n as f64),
+        Value::Float(x) => Ok(x),
+    }
+}
+
+// Rust writes exponents as `e2`, C as `e+02`.
+fn exponential(x: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, x);
+    match formatted.split_once('e') {
+        Some((mantissa, exp)) => {
+            let exp: i32 = exp.parse().unwrap_or(0);
+            format!(
+                "{}e{}{:02}",
+                mantissa,
+                if exp < 0 { '-' } else { '+' },
+                exp.abs()
+            )
+        }
+        None => formatted,
+    }
+}
+
+fn general(x: f64, precision: usize, alternate: bool) -> String {
+    let precision = precision.max(1);
+    let exp = if x == 0.0 {
+        0
+    } else {
+        x.abs().log10().floor() as i32
+    };
+
+    let formatted = if exp < -4 || exp >= precision as i32 {
+        exponential(x, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exp) as usize, x)
+    };
+    if alternate || !formatted.contains('.') {
+        return formatted;
+    }
+
+    let (mantissa, exp) = match formatted.split_once('e') {
+        Some((mantissa, exp)) => (mantissa, format!("e{}", exp)),
+        None => (formatted.as_str(), String::new()),
+    };
+    format!(
+        "{}{}",
+        mantissa.trim_end_matches('0').trim_end_matches('.'),
+        exp
+    )
+}
+
+fn convert(
+    conversion: char,
+    spec: &Spec,
+    arg: Option<&String>,
+    out: &mut String,
+) -> Result<(), FormatError> {
+    let arg = arg.map(String::as_str).unwrap_or_default();
+
+    let formatted = match conversion {
+        'd' | 'i' => {
+            let n = integer(arg)?;
+            let mut digits = n.unsigned_abs().to_string();
+            if let Some(precision) = spec.precision {
+                digits = format!("{:0>1$}", digits, precision);
+            }
+            if spec.group {
+                digits = group_thousands(&digits);
+            }
+            spec.pad(spec.sign(n < 0), &digits, spec.precision.is_none())
+        }
+        'u' | 'x' | 'X' | 'o' => {
+            // Negative numbers wrap around, as they do with C's unsigned types.
+            let n = integer(arg)? as u64;
+            let (digits, prefix) = match conversion {
+                'x' => (format!("{:x}", n), "0x"),
+                'X' => (format!("{:X}", n), "0X"),
+                'o' => (format!("{:o}", n), "0"),
+                _ => (n.to_string(), ""),
+            };
+            let prefix = if spec.alternate && n != 0 { prefix } else { "" };
+            let digits = match spec.group && conversion == 'u' {
+                true => group_thousands(&digits),
+                false => digits,
+            };
+            spec.pad(prefix, &digits, spec.precision.is_none())
+        }
+        'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+            let x = float(arg)?;
+            let precision = spec.precision.unwrap_or(6);
+            let mut body = match conversion.to_ascii_lowercase() {
+                'f' => format!("{:.*}", precision, x.abs()),
+                'e' => exponential(x.abs(), precision),
+                _ => general(x.abs(), precision, spec.alternate),
+            };
+            if conversion.is_ascii_uppercase() {
+                body = body.to_uppercase();
+            }
+            if spec.group && x.is_finite() {
+                let (whole, fraction) = body.split_at(body.find(['.', 'e']).unwrap_or(body.len()));
+                body = format!("{}{}", group_thousands(whole), fraction);
+            }
+            spec.pad(spec.sign(x.is_sign_negative()), &body, x.is_finite())
+        }
+        's' | 'b' => {
+            let text = match conversion {
+                'b' => unescape(arg),
+                _ => arg.to_string(),
+            };
+            let text: String = match spec.precision {
+                Some(precision) => text.chars().take(precision).collect(),
+                None => text,
+            };
+            spec.pad("", &text, false)
+        }
+        'c' => spec.pad("", &arg.chars().take(1).collect::<String>(), false),
+        c => return Err(FormatError::InvalidDirective(c)),
+    };
+
+    out.push_str(&formatted);
+    Ok(())
+}
+
+// One pass over the format string. Returns whether it used any arguments, so
+// the caller knows if another pass could make progress.
+fn render(
+    format: &str,
+    args: &mut slice::Iter<String>,
+    out: &mut String,
+) -> Result<bool, FormatError> {
+    let mut used = false;
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escape(&mut chars, out),
+            '%' if chars.next_if_eq(&'%').is_some() => out.push('%'),
+            '%' => {
+                let spec = Spec::parse(&mut chars);
+                let conversion = chars.next().ok_or(FormatError::MissingDirective)?;
+                let arg = args.next();
+                used |= arg.is_some();
+                convert(conversion, &spec, arg, out)?;
+            }
+            _ => out.push(c),
+        }
+    }
+
+    Ok(used)
+}
+
+/// Formats like printf(1): the format is reused until every argument has been
+/// consumed, and missing arguments count as empty or zero.
+pub fn format(format: &str, args: &[String]) -> Result<String, FormatError> {
+    let mut out = String::new();
+    let mut args = args.iter();
+
+    while render(format, &mut args, &mut out)? && args.len() > 0 {}
+    Ok(out)
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// How often each command ran and how long it took in all, by its text.
+static TIMES: Mutex<Option<Timings>> = Mutex::new(None);
+
+struct Timings {
+    started: Instant,
+    commands: HashMap<String, (u32, Duration)>,
+}
+
+/// Starts timing every simple command, for `--profile`.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+    *TIMES.lock().expect("Failed to lock profile!") = Some(Timings {
+        started: Instant::now(),
+        commands: HashMap::new(),
+    });
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Adds one run of a command to its total.
+pub fn record(command: &str, elapsed: Duration) {
+    let mut times = TIMES.lock().expect("Failed to lock profile!");
+    let Some(times) = times.as_mut() else {
+        return;
+    };
+    let (calls, total) = times.commands.entry(command.to_string()).or_default();
+    *calls += 1;
+    *total += elapsed;
+}
+
+/// Prints every command on stderr, slowest in all first, right before the
+/// shell exits. Commands in one pipeline run side by side, so each is timed
+/// until the whole pipeline is done.
+pub fn report() {
+    let times = TIMES.lock().expect("Failed to lock profile!");
+    let Some(times) = times.as_ref() else {
+        return;
+    };
+    let mut commands: Vec<_> = times.commands.iter().collect();
+    commands.sort_by(|(a, (_, a_total)), (b, (_, b_total))| b_total.cmp(a_total).then(a.cmp(b)));
+
+    let mut report = format!(
+        "profile: {} commands in {:.3}s\n{:>10} {:>7} {:>10}  command\n",
+        commands.iter().map(|(_, (calls, _))| calls).sum::<u32>(),
+        times.started.elapsed().as_secs_f64(),
+        "total",
+        "calls",
+        "mean",
+    );
+    for (command, (calls, total)) in commands {
+        report += &format!(
+            "{:>9.3}s {:>7} {:>9.3}s  {}\n",
+            total.as_secs_f64(),
+            calls,
+            total.as_secs_f64() / *calls as f64,
+            command.replace('\n', " "),
+        );
+    }
+    _ = io::stderr().write_all(report.as_bytes());
+}
+
+/// Locks the profile's timings for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    TIMES.lock().expect("Failed to lock profile!")
+}
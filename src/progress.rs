@@ -0,0 +1,86 @@
+use std::{
+    env,
+    io::{self, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::options;
+
+/// Pipelines that run at least this long get a spinner.
+const DEFAULT_PROGRESS_SECS: f64 = 3.0;
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+// Pipelines run inside builtins like `watch` or a group would draw over the
+// outer one's spinner, so only one spins at a time.
+static SPINNING: AtomicBool = AtomicBool::new(false);
+
+pub struct Spinner {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+// Full-screen programs like vim or less turn canonical mode off, and a
+// spinner would scribble over them.
+fn terminal_is_cooked() -> bool {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    unsafe { libc::tcgetattr(2, &mut termios) == 0 && termios.c_lflag & libc::ICANON != 0 }
+}
+
+/// Starts the clock on a foreground pipeline. With the `progress` option on
+/// and stderr a terminal, a spinner and the elapsed time show up on stderr
+/// once it has run for $PROGRESS_SECS.
+pub fn start() -> Option<Spinner> {
+    if !options::is_set("progress") || unsafe { libc::isatty(2) } != 1 {
+        return None;
+    }
+    if SPINNING.swap(true, Ordering::SeqCst) {
+        return None;
+    }
+
+    let threshold = env::var("PROGRESS_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_PROGRESS_SECS);
+    let threshold = Duration::from_secs_f64(threshold);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&stop);
+    let handle = thread::spawn(move || {
+        let started = Instant::now();
+        let mut drawn = false;
+        let mut frame = 0;
+        while !stopped.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+            if started.elapsed() < threshold || !terminal_is_cooked() {
+                continue;
+            }
+            let line = format!(
+                "\r\x1b[K\x1b[2m{} {}s\x1b[0m\r",
+                FRAMES[frame % FRAMES.len()],
+                started.elapsed().as_secs()
+            );
+            _ = io::stderr().write_all(line.as_bytes());
+            drawn = true;
+            frame += 1;
+        }
+        if drawn {
+            _ = io::stderr().write_all(b"\r\x1b[K");
+        }
+    });
+    Some(Spinner { stop, handle })
+}
+
+impl Spinner {
+    /// Stops the spinner and clears its line.
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        _ = self.handle.join();
+        SPINNING.store(false, Ordering::SeqCst);
+    }
+}
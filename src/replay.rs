@@ -0,0 +1,241 @@
+use std::{
+    env,
+    fs::{File, OpenOptions, Permissions},
+    io::{self, BufReader, Read, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::PathBuf,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use flate2::{Compression, read::MultiGzDecoder, write::GzEncoder};
+
+use crate::{options, vars};
+
+static SNAPSHOTS: Mutex<Vec<Snapshot>> = Mutex::new(Vec::new());
+
+// The line running now, which its commands add their argv to as they start.
+static RECORDING: Mutex<Option<Snapshot>> = Mutex::new(None);
+
+// The number the last snapshot got. They go by history numbers, but keep
+// counting once $HISTSIZE stops the history from growing, so two lines
+// never share one.
+static LAST_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+// Recorded next to the history file, one gzip member per command, so appending
+// never has to rewrite what is already there.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub index: usize,
+    pub line: String,
+    pub cwd: String,
+    pub argv: Vec<Vec<String>>,
+    pub env: Vec<(String, String)>,
+}
+
+impl Snapshot {
+    pub fn capture(index: usize, line: &str, argv: Vec<Vec<String>>) -> Self {
+        Self {
+            index,
+            line: line.to_string(),
+            cwd: env::current_dir()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            argv,
+            env: env::vars().chain(vars::exported()).collect(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut fields = vec![
+            self.index.to_string(),
+            self.line.clone(),
+            self.cwd.clone(),
+            self.argv.len().to_string(),
+        ];
+        for stage in &self.argv {
+            fields.push(stage.len().to_string());
+            fields.extend(stage.iter().cloned());
+        }
+        fields.push(self.env.len().to_string());
+        fields.extend(self.env.iter().map(|(k, v)| format!("{}={}", k, v)));
+
+        let mut bytes = Vec::new();
+        for field in fields {
+            bytes.extend_from_slice(field.as_bytes());
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn decode(fields: &mut impl Iterator<Item = String>) -> Option<Self> {
+        let index = fields.next()?.parse().ok()?;
+        let line = fields.next()?;
+        let cwd = fields.next()?;
+
+        let stages: usize = fields.next()?.parse().ok()?;
+        let mut argv = Vec::with_capacity(stages);
+        for _ in 0..stages {
+            let count: usize = fields.next()?.parse().ok()?;
+            argv.push(fields.by_ref().take(count).collect());
+        }
+
+        let count: usize = fields.next()?.parse().ok()?;
+        let env = fields
+            .by_ref()
+            .take(count)
+            .map(|entry| match entry.split_once('=') {
+                Some((k, v)) => (k.to_string(), v.to_string()),
+                None => (entry, String::new()),
+            })
+            .collect();
+
+        Some(Self {
+            index,
+            line,
+            cwd,
+            argv,
+            env,
+        })
+    }
+}
+
+fn replay_path(history_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.replay", history_path))
+}
+
+pub fn load(history_path: Option<&String>) {
+    let Some(history_path) = history_path else {
+        return;
+    };
+    let Ok(file) = File::open(replay_path(history_path)) else {
+        return;
+    };
+
+    let mut raw = Vec::new();
+    if MultiGzDecoder::new(BufReader::new(file))
+        .read_to_end(&mut raw)
+        .is_err()
+    {
+        eprintln!("replay: ignoring corrupt snapshot file");
+        return;
+    }
+
+    let mut fields = raw
+        .split(|b| *b == 0)
+        .map(|f| String::from_utf8_lossy(f).to_string());
+    let mut snapshots = SNAPSHOTS.lock().expect("Failed to lock snapshots!");
+    while let Some(snapshot) = Snapshot::decode(&mut fields) {
+        LAST_INDEX.fetch_max(snapshot.index, Ordering::SeqCst);
+        snapshots.push(snapshot);
+    }
+}
+
+/// Starts the snapshot of a line about to run, with `set -o replay`. The
+/// line is `history_number` in the history, unless the history is full.
+pub fn begin(history_number: usize, line: &str) {
+    if !options::is_set("replay") {
+        return;
+    }
+    let last = LAST_INDEX.fetch_max(history_number, Ordering::SeqCst);
+    let index = match history_number > last {
+        true => history_number,
+        false => LAST_INDEX.fetch_add(1, Ordering::SeqCst) + 1,
+    };
+    *RECORDING.lock().expect("Failed to lock the snapshot!") =
+        Some(Snapshot::capture(index, line, Vec::new()));
+}
+
+/// Adds a command's argv, as it is after expansion, to the line's snapshot.
+pub fn note_argv(argv: &[String]) {
+    if let Some(snapshot) = RECORDING
+        .lock()
+        .expect("Failed to lock the snapshot!")
+        .as_mut()
+    {
+        snapshot.argv.push(argv.to_vec());
+    }
+}
+
+/// Saves the line's snapshot once it has run.
+pub fn finish(history_path: Option<&String>) -> io::Result<()> {
+    let snapshot = RECORDING
+        .lock()
+        .expect("Failed to lock the snapshot!")
+        .take();
+    match snapshot {
+        Some(snapshot) => record(snapshot, history_path),
+        None => Ok(()),
+    }
+}
+
+fn record(snapshot: Snapshot, history_path: Option<&String>) -> io::Result<()> {
+    if let Some(history_path) = history_path {
+        // What every command ran with, tokens and all, is for the user only.
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(replay_path(history_path))?;
+        file.set_permissions(Permissions::from_mode(0o600))?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        encoder.write_all(&snapshot.encode())?;
+        encoder.finish()?;
+    }
+
+    SNAPSHOTS
+        .lock()
+        .expect("Failed to lock snapshots!")
+        .push(snapshot);
+    Ok(())
+}
+
+pub fn find(index: usize) -> Option<Snapshot> {
+    SNAPSHOTS
+        .lock()
+        .expect("Failed to lock snapshots!")
+        .iter()
+        .rev()
+        .find(|s| s.index == index)
+        .cloned()
+}
+
+fn quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+pub fn describe(snapshot: &Snapshot) -> String {
+    let mut out = format!("line: {}\ncwd:  {}\n", snapshot.line, quote(&snapshot.cwd));
+
+    for (stage, argv) in snapshot.argv.iter().enumerate() {
+        out += &format!(
+            "argv[{}]: {}\n",
+            stage,
+            argv.iter().map(|a| quote(a)).collect::<Vec<_>>().join(" ")
+        );
+    }
+
+    out += "env:\n";
+    for (key, value) in &snapshot.env {
+        out += &format!("    {}={}\n", key, quote(value));
+    }
+    out
+}
+
+/// Locks the snapshots for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    (
+        SNAPSHOTS.lock().expect("Failed to lock snapshots!"),
+        RECORDING.lock().expect("Failed to lock the snapshot!"),
+    )
+}
@@ -0,0 +1,160 @@
+use std::{
+    env,
+    ffi::CString,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{ffi::OsStrExt, process::CommandExt},
+    },
+    path::Path,
+    process::Command,
+    sync::OnceLock,
+};
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+// Every right known to landlock ABI v1, from EXECUTE up to MAKE_SYM.
+const ACCESS_FS_ALL: u64 = (1 << 13) - 1;
+
+const ACCESS_READ_ONLY: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+static PROFILE: OnceLock<Profile> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    /// Children run with no_new_privs, so setuid binaries can't escalate.
+    Basic,
+    /// Basic, plus landlock: the filesystem is read-only except the cwd and /tmp.
+    Strict,
+}
+
+impl Profile {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "basic" => Some(Self::Basic),
+            "strict" => Some(Self::Strict),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+pub fn enable(profile: Profile) -> Result<(), String> {
+    if profile == Profile::Strict && landlock_abi() < 1 {
+        return Err("--sandbox strict: landlock is not available on this kernel".to_string());
+    }
+    PROFILE
+        .set(profile)
+        .map_err(|_| "sandbox profile already set".to_string())
+}
+
+fn landlock_abi() -> libc::c_long {
+    unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    }
+}
+
+fn add_path_rule(ruleset: &OwnedFd, path: &Path, access: u64) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        // Nothing to allow if the directory doesn't exist.
+        return Ok(());
+    }
+    let parent = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: parent.as_raw_fd(),
+    };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.as_raw_fd(),
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0u32,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn build_ruleset() -> io::Result<OwnedFd> {
+    let attr = RulesetAttr {
+        handled_access_fs: ACCESS_FS_ALL,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            size_of::<RulesetAttr>(),
+            0u32,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+    add_path_rule(&ruleset, Path::new("/"), ACCESS_READ_ONLY)?;
+    add_path_rule(&ruleset, Path::new("/dev"), ACCESS_FS_WRITE_FILE)?;
+    add_path_rule(&ruleset, Path::new("/tmp"), ACCESS_FS_ALL)?;
+    if let Ok(cwd) = env::current_dir() {
+        add_path_rule(&ruleset, &cwd, ACCESS_FS_ALL)?;
+    }
+
+    Ok(ruleset)
+}
+
+// The returned ruleset has to outlive spawn(), the child only restricts itself
+// right before exec.
+pub fn apply(command: &mut Command) -> io::Result<Option<OwnedFd>> {
+    let Some(profile) = PROFILE.get().copied() else {
+        return Ok(None);
+    };
+
+    let ruleset = match profile {
+        Profile::Basic => None,
+        Profile::Strict => Some(build_ruleset()?),
+    };
+    let ruleset_fd = ruleset.as_ref().map(|fd| fd.as_raw_fd());
+
+    unsafe {
+        command.pre_exec(move || {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(fd) = ruleset_fd
+                && libc::syscall(libc::SYS_landlock_restrict_self, fd, 0u32) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    Ok(ruleset)
+}
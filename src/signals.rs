@@ -0,0 +1,169 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Starts out set, so the size is read once before the first prompt.
+static RESIZED: AtomicBool = AtomicBool::new(true);
+
+// The line editor's own SIGWINCH handler, which redraws the line being edited.
+static EDITOR_SIGWINCH: AtomicUsize = AtomicUsize::new(libc::SIG_DFL);
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Sent to the shell a background job runs in, it goes to all of the job. The
+// default comes back first, so the shell ends too once it gets it again.
+extern "C" fn on_ending(signal: libc::c_int) {
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        if libc::getpgrp() == libc::getpid() {
+            libc::killpg(0, signal);
+        } else {
+            libc::raise(signal);
+        }
+    }
+}
+
+extern "C" fn on_sigwinch(signal: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+    let editor = EDITOR_SIGWINCH.load(Ordering::SeqCst);
+    if editor != libc::SIG_DFL && editor != libc::SIG_IGN && editor != libc::SIG_ERR {
+        let editor: extern "C" fn(libc::c_int) = unsafe { std::mem::transmute(editor) };
+        editor(signal);
+    }
+}
+
+/// What the terminal sends for job control, which the shell leaves to the
+/// pipelines it hands the terminal to, as bash does.
+const JOB_CONTROL: [libc::c_int; 3] = [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+/// What `kill` ends a job with when it is not given one.
+const ENDING: [libc::c_int; 3] = [libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+// Ctrl-C while a command runs should kill the command, not the shell. Children
+// get the default disposition back on exec, since caught signals are reset.
+pub fn install() {
+    let handler = on_sigint as extern "C" fn(libc::c_int);
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+
+    // Ctrl-Z goes to the pipeline in the foreground, not the shell.
+    for signal in JOB_CONTROL {
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }
+
+    // Goes in front of the editor's handler rather than replacing it.
+    let handler = on_sigwinch as extern "C" fn(libc::c_int);
+    let editor = unsafe { libc::signal(libc::SIGWINCH, handler as libc::sighandler_t) };
+    EDITOR_SIGWINCH.store(editor, Ordering::SeqCst);
+}
+
+/// Gives the job control signals back their defaults, which ignoring them
+/// would otherwise pass on through exec. Only calls what is safe between
+/// fork and exec.
+pub fn restore_defaults() {
+    for signal in JOB_CONTROL {
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+/// Gives Ctrl-C and the rest of what ends a job their defaults too, in a
+/// forked copy of the shell that is part of a job, so they end it like the
+/// commands it runs.
+pub fn default_in_job() {
+    restore_defaults();
+    for signal in ENDING {
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+/// In the forked shell a background job runs in, passes what would end it
+/// on to the whole job. `$!` is that shell, where in bash it would be the
+/// command, so `kill $!` still ends what it runs.
+pub fn pass_on_to_job() {
+    let handler = on_ending as extern "C" fn(libc::c_int);
+    for signal in ENDING {
+        unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+    }
+}
+
+/// Acts on a Ctrl-C that went to a pipeline in the foreground instead of
+/// the shell, as if the shell had got it too.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Whether the terminal was resized since the last call.
+pub fn take_resized() -> bool {
+    RESIZED.swap(false, Ordering::SeqCst)
+}
+
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// The signals known by name, as `kill -l` lists them.
+const NAMES: [(&str, libc::c_int); 31] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("STKFLT", libc::SIGSTKFLT),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("PWR", libc::SIGPWR),
+    ("SYS", libc::SIGSYS),
+];
+
+/// The signal a name like TERM, SIGTERM or term, or a number like 15, is
+/// for. 0 is allowed, to check a process is there without signalling it.
+pub fn number(spec: &str) -> Option<libc::c_int> {
+    if let Ok(number) = spec.parse() {
+        return (number == 0 || name(number).is_some()).then_some(number);
+    }
+    let spec = spec.to_ascii_uppercase();
+    let spec = spec.strip_prefix("SIG").unwrap_or(&spec);
+    NAMES
+        .iter()
+        .find(|(name, _)| *name == spec)
+        .map(|&(_, number)| number)
+}
+
+/// A signal's name, without the SIG.
+pub fn name(number: libc::c_int) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|&&(_, known)| known == number)
+        .map(|(name, _)| *name)
+}
+
+/// Every signal, numbered, for `kill -l`.
+pub fn list() -> Vec<(libc::c_int, &'static str)> {
+    NAMES.iter().map(|&(name, number)| (number, name)).collect()
+}
@@ -0,0 +1,83 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::paths;
+
+/// Snippets live one per file, named after the snippet, so they are easy to
+/// share or keep in a dotfiles repo.
+fn dir() -> io::Result<PathBuf> {
+    Ok(paths::config_dir()?.join("snippets"))
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains('/')
+}
+
+fn path(name: &str) -> io::Result<PathBuf> {
+    Ok(dir()?.join(name))
+}
+
+pub fn save(name: &str, body: &str) -> io::Result<()> {
+    let dir = dir()?;
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(name), body)
+}
+
+pub fn load(name: &str) -> io::Result<String> {
+    fs::read_to_string(path(name)?)
+}
+
+pub fn remove(name: &str) -> io::Result<()> {
+    fs::remove_file(path(name)?)
+}
+
+pub fn names() -> Vec<String> {
+    let Ok(entries) = dir().and_then(fs::read_dir) else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| valid_name(name))
+        .collect();
+    names.sort();
+    names
+}
+
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Fills in `$1`..`$9` and `$@` with the arguments, each quoted so it stays a
+/// single word. Missing arguments become empty strings.
+pub fn substitute(body: &str, args: &[String]) -> String {
+    let mut out = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.peek() {
+            Some(&digit @ '1'..='9') => {
+                chars.next();
+                let index = digit as usize - '1' as usize;
+                out.push_str(&quote(args.get(index).map_or("", String::as_str)));
+            }
+            Some('@') => {
+                chars.next();
+                out.push_str(
+                    &args
+                        .iter()
+                        .map(|arg| quote(arg))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+            }
+            _ => out.push('$'),
+        }
+    }
+    out
+}
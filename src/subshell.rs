@@ -0,0 +1,116 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    aliases, capture, complete, fd, functions, history, jobs, options, palette, params, profile,
+    replay, summary, terminal, traps, trash, vars,
+};
+
+/// Forks the shell for a subshell, which that way has a working directory,
+/// environment, variables, options, traps and aliases of its own, and none
+/// of its changes reach the shell. Gives the parent the child's pid, and the
+/// child 0.
+///
+/// Only the calling thread carries on in the child, where any lock another
+/// thread held stays held for good. So every lock the shell has is taken
+/// first, stdout's last, once what it buffered is out so it isn't written
+/// twice. The test below holds every module with a lock of its own to that. The environment's own lock can't be taken from here, but nothing
+/// holds it for longer than a lookup.
+pub fn fork() -> io::Result<libc::pid_t> {
+    let held = (
+        crate::hold_for_fork(),
+        jobs::hold_for_fork(),
+        aliases::hold_for_fork(),
+        capture::hold_for_fork(),
+        complete::hold_for_fork(),
+        fd::hold_for_fork(),
+        functions::hold_for_fork(),
+        history::hold_for_fork(),
+        options::hold_for_fork(),
+        palette::hold_for_fork(),
+        params::hold_for_fork(),
+        profile::hold_for_fork(),
+        replay::hold_for_fork(),
+        summary::hold_for_fork(),
+        terminal::hold_for_fork(),
+        traps::hold_for_fork(),
+        trash::hold_for_fork(),
+        vars::hold_for_fork(),
+    );
+    let mut stdout = io::stdout().lock();
+    stdout.flush()?;
+    let stderr = io::stderr().lock();
+
+    let pid = unsafe { libc::fork() };
+    drop((stderr, stdout, held));
+    match pid {
+        -1 => Err(io::Error::last_os_error()),
+        pid => Ok(pid),
+    }
+}
+
+/// Where `exit` inside a subshell leaves its status, so it ends the subshell
+/// instead of the whole shell.
+pub type Exit = Arc<OnceLock<i32>>;
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    // Taken by the thread that forks, so it can't be held for the fork too.
+    const NOT_HELD: &[&str] = &["RUNNING"];
+
+    // The text of `fn name` up to its closing brace at the start of a line.
+    fn body<'a>(source: &'a str, name: &str) -> Option<&'a str> {
+        let start = source.find(&format!("fn {}(", name))?;
+        let end = source[start..].find("\n}")?;
+        Some(&source[start..start + end])
+    }
+
+    #[test]
+    fn fork_holds_every_lock() {
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let fork = fs::read_to_string(src.join("subshell.rs")).unwrap();
+        let fork = body(&fork, "fork").unwrap().to_string();
+
+        for entry in fs::read_dir(&src).unwrap() {
+            let path = entry.unwrap().path();
+            let source = fs::read_to_string(&path).unwrap();
+            let locks = source
+                .lines()
+                .filter_map(|line| {
+                    let line = line.trim_start().trim_start_matches("pub ");
+                    let (name, kind) = line.strip_prefix("static ")?.split_once(": ")?;
+                    (kind.starts_with("Mutex<") || kind.starts_with("RwLock<")).then_some(name)
+                })
+                .filter(|name| !NOT_HELD.contains(name))
+                .collect::<Vec<_>>();
+            if locks.is_empty() {
+                continue;
+            }
+
+            let module = match path.file_stem().unwrap().to_str().unwrap() {
+                "main" => "crate",
+                module => module,
+            };
+            let Some(hold) = body(&source, "hold_for_fork") else {
+                panic!("{} has locks but no hold_for_fork", path.display());
+            };
+            for lock in locks {
+                assert!(
+                    hold.contains(lock),
+                    "{}::hold_for_fork misses {}",
+                    module,
+                    lock
+                );
+            }
+            assert!(
+                fork.contains(&format!("{}::hold_for_fork()", module)),
+                "fork doesn't call {}::hold_for_fork",
+                module
+            );
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::options;
+
+// What the lines typed so far came to, for `summary` to print at exit.
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+struct Session {
+    started: Instant,
+    commands: u32,
+    failures: u32,
+    running: Duration,
+    longest: Option<(String, Duration)>,
+}
+
+/// Starts counting, as an interactive shell starts.
+pub fn start() {
+    *SESSION.lock().expect("Failed to lock the session!") = Some(Session {
+        started: Instant::now(),
+        commands: 0,
+        failures: 0,
+        running: Duration::ZERO,
+        longest: None,
+    });
+}
+
+/// Adds a line that was typed and ran to the session's totals.
+pub fn record(line: &str, status: i32, elapsed: Duration) {
+    let mut session = SESSION.lock().expect("Failed to lock the session!");
+    let Some(session) = session.as_mut() else {
+        return;
+    };
+    session.commands += 1;
+    if status != 0 {
+        session.failures += 1;
+    }
+    session.running += elapsed;
+    if session
+        .longest
+        .as_ref()
+        .is_none_or(|(_, longest)| elapsed > *longest)
+    {
+        session.longest = Some((line.replace('\n', " "), elapsed));
+    }
+}
+
+// Like 2h 5m, 4m 2s or 1.3s: no more detail than is worth reading.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{:.1}s", duration.as_secs_f64()),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// With `summary`, prints on stderr what the session came to as the shell
+/// exits: how many lines ran and failed, how long they ran, and the longest.
+pub fn report() {
+    if !options::is_set("summary") {
+        return;
+    }
+    let session = SESSION.lock().expect("Failed to lock the session!");
+    let Some(session) = session.as_ref() else {
+        return;
+    };
+    eprintln!(
+        "session: {} commands, {} failed, {} running over {}",
+        session.commands,
+        session.failures,
+        format_duration(session.running),
+        format_duration(session.started.elapsed()),
+    );
+    if let Some((line, elapsed)) = &session.longest {
+        eprintln!("longest: {} ({})", line, format_duration(*elapsed));
+    }
+}
+
+/// Locks the session's summary for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    SESSION.lock().expect("Failed to lock the session!")
+}
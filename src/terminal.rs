@@ -0,0 +1,156 @@
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{self, BufRead, BufReader, Write},
+    os::fd::AsRawFd,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use crate::{options, paths, signals, vars};
+
+// Escape sequences go straight to the controlling terminal so they still work
+// when stdout is a pipe or a file.
+pub fn emit(sequence: &str) -> io::Result<()> {
+    let mut tty = OpenOptions::new().write(true).open("/dev/tty")?;
+    tty.write_all(sequence.as_bytes())?;
+    tty.flush()
+}
+
+pub fn set_title(title: &str) -> io::Result<()> {
+    let title = title.replace(|c: char| c.is_control(), " ");
+    emit(&format!("\x1b]2;{}\x07", title))
+}
+
+/// Whether the shell runs inside tmux or screen, which keep a title for each
+/// pane and a name for each window.
+pub fn multiplexed() -> bool {
+    env::var_os("TMUX").is_some() || env::var_os("STY").is_some()
+}
+
+/// Names the tmux or screen window, or outside of them, titles the terminal.
+pub fn set_window_name(name: &str) -> io::Result<()> {
+    if !multiplexed() {
+        return set_title(name);
+    }
+    let name = name.replace(|c: char| c.is_control(), " ");
+    emit(&format!("\x1bk{}\x1b\\", name))
+}
+
+/// With `panetitles` inside tmux or screen, puts what the shell is doing in
+/// the pane's title: the command while it runs, the directory at the prompt.
+pub fn set_pane_title(title: &str) {
+    if options::is_set("panetitles") && multiplexed() {
+        _ = set_title(title);
+    }
+}
+
+/// The working directory as the pane title shows it, with `~` for $HOME.
+pub fn cwd_title() -> String {
+    let Ok(cwd) = paths::logical_cwd() else {
+        return String::new();
+    };
+    match env::home_dir().and_then(|home| cwd.strip_prefix(home).ok().map(PathBuf::from)) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.to_string_lossy()),
+        None => cwd.to_string_lossy().to_string(),
+    }
+}
+
+pub fn bell() -> io::Result<()> {
+    emit("\x07")
+}
+
+/// Asks a yes/no question on the terminal, even with stdin redirected.
+/// Anything but `y` or `yes` is a no.
+pub fn ask(question: &str) -> io::Result<bool> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    tty.write_all(format!("{} [y/N] ", question).as_bytes())?;
+    tty.flush()?;
+
+    let mut answer = String::new();
+    BufReader::new(tty).read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// The terminal's size, as columns and lines.
+pub fn size() -> io::Result<(u16, u16)> {
+    let tty = OpenOptions::new().read(true).open("/dev/tty")?;
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    match unsafe { libc::ioctl(tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } {
+        0 => Ok((size.ws_col, size.ws_row)),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Keeps $COLUMNS and $LINES in step with the terminal once it is resized,
+/// and exports them with the `exportsize` option.
+pub fn update_size_vars() {
+    let export = options::is_set("exportsize");
+    // A fresh `set -o exportsize` has nothing in the environment to update yet.
+    let unexported = export && env::var_os("COLUMNS").is_none();
+    if !(signals::take_resized() || unexported) {
+        return;
+    }
+    let Ok((columns, lines)) = size() else {
+        return;
+    };
+    for (name, value) in [("COLUMNS", columns), ("LINES", lines)] {
+        match export {
+            // Only called between commands, while nothing else runs.
+            true => unsafe { env::set_var(name, value.to_string()) },
+            false => vars::set(name, value.to_string()),
+        }
+    }
+}
+
+/// Hands the terminal to a process group, which then gets what is typed and
+/// the signals Ctrl-C and Ctrl-Z send.
+pub fn give_to(pgid: i32) -> io::Result<()> {
+    unsafe {
+        // Taking it back is done from outside the terminal's group, which
+        // SIGTTOU would stop the shell for.
+        let mut blocked: libc::sigset_t = std::mem::zeroed();
+        let mut old: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut blocked);
+        libc::sigaddset(&mut blocked, libc::SIGTTOU);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &blocked, &mut old);
+        let result = libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::pthread_sigmask(libc::SIG_SETMASK, &old, std::ptr::null_mut());
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Takes the terminal back for the shell's own process group.
+pub fn take_back() -> io::Result<()> {
+    give_to(unsafe { libc::getpgrp() })
+}
+
+// The shell's own terminal modes, from before it handed the terminal over.
+static MODES: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+/// Remembers the terminal's modes before handing it over, for
+/// `restore_modes`.
+pub fn save_modes() {
+    let mut modes: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut modes) } == 0 {
+        *MODES.lock().expect("Failed to lock the terminal modes!") = Some(modes);
+    }
+}
+
+/// Puts back the modes `save_modes` saw, once what had the terminal has
+/// stopped or been killed, since it can't undo what it did to them, like an
+/// editor's raw mode.
+pub fn restore_modes() {
+    if let Some(modes) = *MODES.lock().expect("Failed to lock the terminal modes!") {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &modes) };
+    }
+}
+
+/// Locks the saved terminal modes for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    MODES.lock().expect("Failed to lock the terminal modes!")
+}
@@ -0,0 +1,36 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// The conditions `trap` can hook: DEBUG runs before every simple command,
+/// ERR after a command fails where a failure isn't expected anyway.
+pub const CONDITIONS: [&str; 2] = ["DEBUG", "ERR"];
+
+static TRAPS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// The command trapped for a condition. An empty one runs nothing.
+pub fn get(condition: &str) -> Option<String> {
+    TRAPS
+        .lock()
+        .expect("Failed to lock traps!")
+        .get(condition)
+        .filter(|action| !action.is_empty())
+        .cloned()
+}
+
+/// Traps a condition, or with None, resets it.
+pub fn set(condition: &str, action: Option<String>) {
+    let mut traps = TRAPS.lock().expect("Failed to lock traps!");
+    match action {
+        Some(action) => traps.insert(condition.to_string(), action),
+        None => traps.remove(condition),
+    };
+}
+
+/// Every trap that is set, by condition, for saving and listing.
+pub fn snapshot() -> BTreeMap<String, String> {
+    TRAPS.lock().expect("Failed to lock traps!").clone()
+}
+
+/// Locks the traps for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    TRAPS.lock().expect("Failed to lock traps!")
+}
@@ -0,0 +1,53 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+
+/// Files `>` overwrote, most recent last, with where their old contents went.
+static CLOBBERED: Mutex<Vec<(PathBuf, PathBuf)>> = Mutex::new(Vec::new());
+
+fn dir() -> PathBuf {
+    env::temp_dir().join(format!("codecrafters-shell-trash-{}", process::id()))
+}
+
+/// Copies a file that is about to be truncated into this session's trash.
+/// Empty files have nothing worth keeping.
+pub fn save(path: &Path) -> io::Result<()> {
+    let Ok(metadata) = fs::metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(());
+    }
+
+    let mut clobbered = CLOBBERED.lock().expect("Failed to lock the trash!");
+    let dir = dir();
+    fs::create_dir_all(&dir)?;
+    let saved = dir.join(clobbered.len().to_string());
+    // Copied rather than moved, so the file keeps its inode, owner and mode.
+    fs::copy(path, &saved)?;
+    clobbered.push((path.canonicalize()?, saved));
+    Ok(())
+}
+
+/// Puts back the contents of the last overwritten file and says which it was.
+pub fn restore_last() -> io::Result<Option<PathBuf>> {
+    let mut clobbered = CLOBBERED.lock().expect("Failed to lock the trash!");
+    let Some((path, saved)) = clobbered.pop() else {
+        return Ok(None);
+    };
+
+    if let Err(err) = fs::copy(&saved, &path) {
+        clobbered.push((path, saved));
+        return Err(err);
+    }
+    _ = fs::remove_file(saved);
+    Ok(Some(path))
+}
+
+/// Locks the clobbered files for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    CLOBBERED.lock().expect("Failed to lock the trash!")
+}
@@ -0,0 +1,144 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    env,
+    sync::Mutex,
+    thread::{self, JoinHandle},
+};
+
+use crate::arith;
+
+// Variables set with `name=value`, which the shell sees but the programs it
+// starts don't.
+static SHELL: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+// Variables the shell changes from builtins, like $PWD, which may run on any
+// thread while others read the environment. They are handed to the programs
+// started after instead.
+static EXPORTED: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    // `NAME=value`s put in front of the builtin or function this thread runs
+    // for. The environment is every thread's at once, so they are kept here,
+    // where only this thread and the ones and programs it starts see them.
+    static PREFIXED: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A variable's value: a `NAME=value` in front of the command first, then the
+/// shell's own, then the exported ones', then the environment's.
+pub fn get(name: &str) -> Option<String> {
+    let prefixed = PREFIXED.with_borrow(|prefixed| {
+        prefixed
+            .iter()
+            .rev()
+            .find(|(prefixed, _)| prefixed == name)
+            .map(|(_, value)| value.clone())
+    });
+    if prefixed.is_some() {
+        return prefixed;
+    }
+    let shell = SHELL.lock().expect("Failed to lock shell variables!");
+    let exported = EXPORTED.lock().expect("Failed to lock exported variables!");
+    shell
+        .get(name)
+        .or_else(|| exported.get(name))
+        .cloned()
+        .or_else(|| env::var(name).ok())
+}
+
+/// `name=value`. A variable already in the environment stays there with its
+/// new value, so programs started later see it too; any other is the shell's.
+pub fn set(name: &str, value: String) {
+    // Inside `NAME=value command`, NAME keeps its new value only until the
+    // command is done.
+    let mut value = Some(value);
+    PREFIXED.with_borrow_mut(|prefixed| {
+        if let Some((_, old)) = prefixed
+            .iter_mut()
+            .rev()
+            .find(|(prefixed, _)| prefixed == name)
+        {
+            *old = value.take().expect("Only taken once!");
+        }
+    });
+    let Some(value) = value else {
+        return;
+    };
+    let mut exported = EXPORTED.lock().expect("Failed to lock exported variables!");
+    if let Some(old) = exported.get_mut(name) {
+        *old = value;
+        return;
+    }
+    drop(exported);
+    if env::var_os(name).is_some() {
+        // Assignments happen while a pipeline is being set up, like a
+        // builtin changing the environment.
+        unsafe { env::set_var(name, value) };
+        return;
+    }
+    let mut shell = SHELL.lock().expect("Failed to lock shell variables!");
+    shell.insert(name.to_string(), value);
+}
+
+/// Sets a variable that the programs started from now on see, without
+/// touching the shell's own environment.
+pub fn export(name: &str, value: String) {
+    let mut shell = SHELL.lock().expect("Failed to lock shell variables!");
+    let mut exported = EXPORTED.lock().expect("Failed to lock exported variables!");
+    shell.remove(name);
+    exported.insert(name.to_string(), value);
+}
+
+/// What `export` set, for the programs the shell starts.
+pub fn exported() -> Vec<(String, String)> {
+    let exported = EXPORTED.lock().expect("Failed to lock exported variables!");
+    exported
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+/// The `NAME=value`s this thread runs under, for the programs it starts.
+pub fn prefixed() -> Vec<(String, String)> {
+    PREFIXED.with_borrow(Clone::clone)
+}
+
+/// Starts a thread to run commands on, under the same `NAME=value`s as this
+/// one.
+pub fn spawn<T: Send + 'static>(run: impl FnOnce() -> T + Send + 'static) -> JoinHandle<T> {
+    spawn_prefixed(Vec::new(), run)
+}
+
+/// Like `spawn`, with `vars` on top, for `NAME=value command`.
+pub fn spawn_prefixed<T: Send + 'static>(
+    vars: Vec<(String, String)>,
+    run: impl FnOnce() -> T + Send + 'static,
+) -> JoinHandle<T> {
+    let prefixed = prefixed().into_iter().chain(vars).collect::<Vec<_>>();
+    thread::spawn(move || {
+        PREFIXED.set(prefixed);
+        run()
+    })
+}
+
+/// The variables as arithmetic sees them, so `(( n++ ))` and `let` change
+/// them like `n=...` would.
+pub struct Scope;
+
+impl arith::Scope for Scope {
+    fn get(&self, name: &str) -> Option<String> {
+        get(name)
+    }
+
+    fn set(&mut self, name: &str, value: arith::Value) {
+        set(name, value.to_string());
+    }
+}
+
+/// Locks the shell variables for `subshell::fork`.
+pub fn hold_for_fork() -> impl Sized {
+    (
+        SHELL.lock().expect("Failed to lock shell variables!"),
+        EXPORTED.lock().expect("Failed to lock exported variables!"),
+    )
+}
@@ -0,0 +1,127 @@
+use std::{env, path::Path};
+
+use crate::options;
+
+/// The Linux-on-Windows layer we are running under, which decides where the
+/// Windows drives are mounted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flavor {
+    /// Drives under `/mnt/c`.
+    Wsl,
+    /// Drives under `/c`.
+    Msys,
+}
+
+impl Flavor {
+    pub fn detect() -> Option<Flavor> {
+        if env::var_os("WSL_DISTRO_NAME").is_some()
+            || env::var_os("WSL_INTEROP").is_some()
+            || Path::new("/proc/sys/fs/binfmt_misc/WSLInterop").exists()
+        {
+            Some(Flavor::Wsl)
+        } else if env::var_os("MSYSTEM").is_some() {
+            Some(Flavor::Msys)
+        } else {
+            None
+        }
+    }
+
+    fn drive_prefix(self) -> &'static str {
+        match self {
+            Flavor::Wsl => "/mnt/",
+            Flavor::Msys => "/",
+        }
+    }
+}
+
+/// The drive letter of a `C:\...` or `C:/...` path.
+fn drive(path: &str) -> Option<char> {
+    let mut chars = path.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(letter), Some(':'), None | Some('\\' | '/')) if letter.is_ascii_alphabetic() => {
+            Some(letter)
+        }
+        _ => None,
+    }
+}
+
+/// `C:\Users\me` becomes `/mnt/c/Users/me`. Paths without a drive only get
+/// their separators flipped.
+pub fn to_unix(path: &str, flavor: Flavor) -> String {
+    let unix = path.replace('\\', "/");
+    match drive(path) {
+        Some(letter) => format!(
+            "{}{}{}",
+            flavor.drive_prefix(),
+            letter.to_ascii_lowercase(),
+            &unix[2..]
+        ),
+        None => unix,
+    }
+}
+
+/// `/mnt/c/Users/me` becomes `C:\Users\me`, or `C:/Users/me` when `mixed`.
+/// Other absolute paths only exist inside the distro, so WSL reaches them
+/// through its `\\wsl.localhost` share. Gives None when there is no such way.
+pub fn to_windows(path: &str, flavor: Flavor, mixed: bool) -> Option<String> {
+    let separator = if mixed { "/" } else { "\\" };
+
+    if let Some(rest) = path.strip_prefix(flavor.drive_prefix()) {
+        let mut chars = rest.chars();
+        if let Some(letter) = chars.next().filter(char::is_ascii_alphabetic)
+            && matches!(chars.next(), None | Some('/'))
+        {
+            let rest = &rest[1..];
+            let rest = if rest.is_empty() { "/" } else { rest };
+            return Some(format!(
+                "{}:{}",
+                letter.to_ascii_uppercase(),
+                rest.replace('/', separator)
+            ));
+        }
+    }
+
+    if !path.starts_with('/') {
+        return Some(path.replace('/', separator));
+    }
+    match flavor {
+        Flavor::Wsl => {
+            let distro = env::var("WSL_DISTRO_NAME").ok()?;
+            Some(format!(
+                "{0}{0}wsl.localhost{0}{1}{2}",
+                separator,
+                distro,
+                path.replace('/', separator)
+            ))
+        }
+        Flavor::Msys => None,
+    }
+}
+
+/// With the `winpaths` option on, rewrites arguments for the other side: real
+/// Linux paths become Windows paths for `.exe` programs, and `C:\...` becomes
+/// a mount path for everything else.
+pub fn translate_args(program: &Path, args: Vec<String>) -> Vec<String> {
+    let Some(flavor) = Flavor::detect().filter(|_| options::is_set("winpaths")) else {
+        return args;
+    };
+
+    let windows_program = program
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exe"));
+
+    args.into_iter()
+        .map(|arg| {
+            if windows_program {
+                match arg.starts_with('/') && Path::new(&arg).exists() {
+                    true => to_windows(&arg, flavor, false).unwrap_or(arg),
+                    false => arg,
+                }
+            } else if drive(&arg).is_some() {
+                to_unix(&arg, flavor)
+            } else {
+                arg
+            }
+        })
+        .collect()
+}